@@ -0,0 +1,261 @@
+//! The game as the player sees it: the world, the views onto it and the controls, independent of
+//! how the screen is drawn or where the keys come from. Each backend turns its own input into
+//! `Key`s, hands them to `Game::handle_key`, and draws the screen `Game::capture` returns.
+
+use crate::gfx::prelude::*;
+use crate::gfx::glyph_gfx::GfxScreen;
+use crate::state::action::Action;
+use crate::state::message::{Message, MessageLog, Severity};
+use crate::state::world::{Direction, Vertical, World};
+
+const WELCOME: &str = "Welcome! Press i for your inventory and m for the message history.";
+const DIG_PROMPT: &str = "Dig in which direction?";
+
+/// A key press, as far as the game is concerned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Key {
+    /// A typed character, which depends on the keyboard layout.
+    Char(char),
+    Up,
+    Down,
+    Left,
+    Right,
+    PageUp,
+    PageDown,
+    /// Closes whatever is open over the map, or quits from the map itself.
+    Escape,
+    /// Quits whatever is on screen, such as an interrupt in a terminal.
+    Quit,
+    /// A digit on the numeric keypad, which some backends cannot tell apart from typed digits.
+    Numpad(u8),
+}
+
+/// Whether the game should keep going after a key press.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Flow {
+    Continue,
+    Quit,
+}
+
+pub struct Game {
+    pub world: World,
+    world_renderer: WorldRenderer,
+    inventory_screen: InventoryScreen,
+    message_panel: MessagePanel,
+    message_log: MessageLog,
+    screen: GfxScreen,
+    /// Whether the next direction key digs rather than moves.
+    digging: bool,
+}
+
+impl Game {
+    /// Starts playing `world` on a screen `columns` by `rows` glyphs in size.
+    pub fn new(world: World, columns: u32, rows: u32) -> Self {
+        let mut game = Self {
+            world_renderer: WorldRenderer::new(world.id),
+            inventory_screen: InventoryScreen::new(),
+            message_panel: MessagePanel::new(),
+            message_log: MessageLog::default(),
+            screen: GfxScreen::new(columns, rows),
+            digging: false,
+            world,
+        };
+
+        game.resize(columns, rows);
+        game.message_log.push(Message::plain(game.world.current_tick, Severity::Info, WELCOME));
+        game.after_turn();
+        game
+    }
+
+    /// Fits the game to a screen `columns` by `rows` glyphs in size.
+    pub fn resize(&mut self, columns: u32, rows: u32) {
+        self.screen = GfxScreen::new(columns, rows);
+
+        // Leave the bottom of the screen to the message log.
+        self.world_renderer.camera_mut().tiles_dims =
+            (columns, rows.saturating_sub(MESSAGE_PANEL_ROWS));
+        self.follow_player();
+    }
+
+    /// Draws everything that is on show into the screen.
+    pub fn capture(&mut self) -> &GfxScreen {
+        // The history and the inventory cover the whole screen, so there is no point drawing
+        // anything under them.
+        if self.message_panel.is_history_open() {
+            self.message_panel.capture_history(&self.message_log, &mut self.screen);
+        } else if self.inventory_screen.is_open() {
+            self.inventory_screen.capture(&self.world, &mut self.screen);
+        } else {
+            self.screen.clear();
+            self.world_renderer.capture(&mut self.world, &mut self.screen);
+            self.message_panel.capture_panel(&self.message_log, &mut self.screen);
+        }
+
+        &self.screen
+    }
+
+    pub fn handle_key(&mut self, key: Key) -> Flow {
+        if key == Key::Quit {
+            return Flow::Quit;
+        }
+
+        // After `D`, a direction key digs that way and anything else calls it off.
+        if self.digging {
+            self.digging = false;
+            if let Some(Action::Move(direction)) = action_for_key(key) {
+                self.act(Action::Dig(direction));
+            }
+            return Flow::Continue;
+        }
+
+        // While the history is open, the keyboard scrolls it.
+        if self.message_panel.is_history_open() {
+            let rows = self.message_panel.history_messages(self.screen.size().1);
+            match key {
+                Key::Char('m') | Key::Escape => self.message_panel.toggle_history(),
+                Key::Up => self.message_log.scroll_up(1, rows),
+                Key::Down => self.message_log.scroll_down(1),
+                Key::PageUp => self.message_log.scroll_up(rows, rows),
+                Key::PageDown => self.message_log.scroll_down(rows),
+                _ => {},
+            }
+            return Flow::Continue;
+        }
+
+        // While the inventory is open, typed letters pick items out of it.
+        if self.inventory_screen.is_open() {
+            match key {
+                Key::Char('i') | Key::Escape => self.inventory_screen.toggle(),
+                Key::Char(c) => {
+                    if let Some(action) = self.inventory_screen.action_for_char(&self.world, c) {
+                        self.act(action);
+                    }
+                },
+                _ => {},
+            }
+            return Flow::Continue;
+        }
+
+        match key {
+            Key::Escape => return Flow::Quit,
+
+            Key::Char('m') => {
+                self.message_panel.toggle_history();
+                self.message_log.scroll_to_newest();
+            },
+            Key::Char('i') => self.inventory_screen.toggle(),
+
+            // Scroll the message panel under the map.
+            Key::Char('[') => self.message_log.scroll_up(1, self.message_panel.panel_messages()),
+            Key::Char(']') => self.message_log.scroll_down(1),
+
+            // Preview levels upside down.
+            Key::Char('g') | Key::Char('G') => {
+                self.world_renderer.toggle_render_modifier(RenderModifier::GravityInverse)
+            },
+
+            // Look at the level above or below.
+            Key::PageUp => self.world_renderer.camera_mut().world_offset.2 += 1,
+            Key::PageDown => self.world_renderer.camera_mut().world_offset.2 -= 1,
+
+            // Dig next to the player, in the direction pressed next.
+            Key::Char('D') => {
+                self.digging = true;
+                let prompt = Message::plain(self.world.current_tick, Severity::Info, DIG_PROMPT);
+                self.message_log.push(prompt);
+            },
+
+            // Shoot at the closest monster in sight.
+            Key::Char('f') => {
+                if let Some(target) = self.world.nearest_visible_hostile() {
+                    self.act(Action::Fire(target));
+                }
+            },
+
+            key => {
+                if let Some(action) = action_for_key(key) {
+                    self.act(action);
+                }
+            },
+        }
+
+        Flow::Continue
+    }
+
+    /// Has the player take `action`. The rest of the world only moves once the player has acted.
+    fn act(&mut self, action: Action) {
+        if self.world.act_player(action) {
+            self.after_turn();
+        }
+    }
+
+    /// Keeps the camera on the player after they have acted, and logs what happened meanwhile.
+    fn after_turn(&mut self) {
+        self.follow_player();
+
+        let events = self.world.take_events();
+        self.message_log.record(self.world.current_tick, &events);
+    }
+
+    fn follow_player(&mut self) {
+        if let Some(position) = self.world.player_position() {
+            self.world_renderer.camera_mut().center_on(position);
+        }
+    }
+}
+
+/// Maps arrow keys, vi-keys and the numpad to the direction they move the player in; numpad 5
+/// waits a moment. The stairs, waiting and picking things up are matched on the typed character,
+/// so they follow the keyboard layout.
+fn action_for_key(key: Key) -> Option<Action> {
+    let direction = match key {
+        Key::Up | Key::Char('k') | Key::Numpad(8) => Direction::North,
+        Key::Char('u') | Key::Numpad(9) => Direction::NorthEast,
+        Key::Right | Key::Char('l') | Key::Numpad(6) => Direction::East,
+        Key::Char('n') | Key::Numpad(3) => Direction::SouthEast,
+        Key::Down | Key::Char('j') | Key::Numpad(2) => Direction::South,
+        Key::Char('b') | Key::Numpad(1) => Direction::SouthWest,
+        Key::Left | Key::Char('h') | Key::Numpad(4) => Direction::West,
+        Key::Char('y') | Key::Numpad(7) => Direction::NorthWest,
+        Key::Numpad(5) | Key::Char('.') => return Some(Action::Wait),
+        Key::Char('<') => return Some(Action::Climb(Vertical::Up)),
+        Key::Char('>') => return Some(Action::Climb(Vertical::Down)),
+        Key::Char(',') => return Some(Action::PickUp),
+        _ => return None,
+    };
+
+    Some(Action::Move(direction))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::world::testing;
+
+    fn game() -> Game {
+        Game::new(testing::world_with_player().0, 80, 24)
+    }
+
+    #[test]
+    fn escape_closes_overlays_before_quitting() {
+        let mut game = game();
+
+        game.handle_key(Key::Char('i'));
+        assert_eq!(game.handle_key(Key::Escape), Flow::Continue);
+        assert!(!game.inventory_screen.is_open());
+
+        game.handle_key(Key::Char('m'));
+        assert_eq!(game.handle_key(Key::Escape), Flow::Continue);
+        assert!(!game.message_panel.is_history_open());
+
+        assert_eq!(game.handle_key(Key::Escape), Flow::Quit);
+    }
+
+    #[test]
+    fn quit_works_from_overlays() {
+        let mut game = game();
+
+        game.handle_key(Key::Char('i'));
+        assert_eq!(game.handle_key(Key::Quit), Flow::Quit);
+    }
+}
@@ -14,4 +14,22 @@ impl Camera {
 
         (x_offset, y_offset)
     }
+
+    /// Scrolls the camera so that the tile at `pos`, in global tile coordinates, sits in the middle
+    /// of the screen, and moves it to that tile's level.
+    pub fn center_on(&mut self, (x, y, z): (i32, i32, i32)) {
+        let dim = REGION_DIM as i32;
+        let (center_x, center_y) = (self.tiles_dims.0 as i32 / 2, self.tiles_dims.1 as i32 / 2);
+
+        // The screen column of a tile is `(region - world_offset) * dim + region_offset + local`.
+        let shift_x = center_x - x.rem_euclid(dim);
+        let shift_y = center_y - y.rem_euclid(dim);
+
+        self.world_offset = (
+            x.div_euclid(dim) - shift_x.div_euclid(dim),
+            y.div_euclid(dim) - shift_y.div_euclid(dim),
+            z,
+        );
+        self.region_offset = (shift_x.rem_euclid(dim) as u8, shift_y.rem_euclid(dim) as u8);
+    }
 }
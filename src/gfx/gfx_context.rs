@@ -1,9 +1,14 @@
+use std::io;
+
 use crate::gfx::prelude::*;
+use crate::gfx::glyph_gfx::{GfxGlyph, GfxScreen, GfxTile};
 
 pub struct GfxContext {
     gpu_context: GpuContext,
     monospace_glyph_context: MonospaceGlyphContext,
     next_frame_encoder: wgpu::CommandEncoder,
+    /// The frame being put together, queued for the GPU all at once when it is presented.
+    frame: GfxScreen,
 }
 
 impl GfxContext {
@@ -12,7 +17,7 @@ impl GfxContext {
         let gpu_context = crate::gfx::gpu_context::GpuContext::create(window).await.unwrap();
 
         let monospace_glyph_context = crate::gfx::glyph_context::MonospaceGlyphContext::new(
-            include_bytes!("../../resources/fonts/FiraMono-Regular.ttf").iter().cloned().collect(),
+            crate::gfx::glyph_context::FONT_TTF.to_vec(),
             (20.0, 20.0),
             &gpu_context,
         )?;
@@ -29,6 +34,7 @@ impl GfxContext {
             gpu_context,
             monospace_glyph_context,
             next_frame_encoder,
+            frame: GfxScreen::new(0, 0),
         })
     }
 
@@ -37,14 +43,29 @@ impl GfxContext {
         self.gpu_context.resize(size);
     }
 
-    pub fn render(
-        &mut self,
-        world: &mut crate::state::world::World,
-        world_renderer: &mut WorldRenderer,
-    ) {
+    pub fn size(&self) -> (u32, u32) {
+        self.gpu_context.size()
+    }
+}
+
+impl GlyphSurface for GfxContext {
+    fn clear(&mut self, width: u32, height: u32) {
+        if self.frame.size() == (width, height) {
+            self.frame.clear();
+        } else {
+            self.frame = GfxScreen::new(width, height);
+        }
+    }
+
+    fn put(&mut self, x: u32, y: u32, glyph: &str, fg: [f32; 4], bg: [f32; 4]) {
+        let glyph = GfxGlyph::new(glyph.to_string());
+        self.frame.put(x as i32, y as i32, GfxTile { glyph, fg, bg });
+    }
+
+    fn present(&mut self) -> io::Result<()> {
         let frame = self.gpu_context.get_next_frame().unwrap();
 
-        world_renderer.render(world, &mut self.monospace_glyph_context);
+        queue_screen(&self.frame, &mut self.monospace_glyph_context);
 
         let (width, height) = self.gpu_context.size();
 
@@ -64,9 +85,24 @@ impl GfxContext {
         );
 
         self.gpu_context.submit_command_encoder(final_encoder);
+        Ok(())
     }
+}
 
-    pub fn size(&self) -> (u32, u32) {
-        self.gpu_context.size()
+/// Queues every tile of `screen` as a glyph in its foreground colour. Backgrounds are not drawn.
+fn queue_screen(screen: &GfxScreen, glyph_context: &mut MonospaceGlyphContext) {
+    let scale = glyph_context.get_px_scale();
+
+    let mut section = wgpu_glyph::Section::default();
+    for row in screen.rows() {
+        for tile in row {
+            section = section.add_text(
+                wgpu_glyph::Text::new(&tile.glyph.glyph).with_color(tile.fg).with_scale(scale)
+            );
+        }
+        // TODO: Is this really the best way to do this?
+        section = section.add_text(wgpu_glyph::Text::new("\n").with_scale(scale));
     }
+
+    glyph_context.glyph_context.glyph_brush.queue(section);
 }
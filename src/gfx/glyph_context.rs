@@ -2,6 +2,9 @@ use wgpu_glyph::{ab_glyph, GlyphBrushBuilder, GlyphCruncher, Section, Text};
 
 use crate::gfx::gpu_context::GpuContext;
 
+/// The font everything is drawn in, Fira Mono, built into the executable.
+pub const FONT_TTF: &[u8] = include_bytes!("../../resources/fonts/FiraMono-Regular.ttf");
+
 pub struct GlyphContext {
     // TODO: Load fonts as part of a resource manager. Arcs can be handed out from there.
     pub font: wgpu_glyph::ab_glyph::FontArc,
@@ -3,22 +3,27 @@
 //! does not need to be recaptured. All non-stateful graphical elements (animations, etc.) are
 //! captured by the types in this module.
 
+use std::borrow::Cow;
+
 use crate::gfx::prelude::*;
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct GfxGlyph {
-    pub glyph: &'static str,
+    /// Usually a static string, but glyphs loaded from data files (e.g. materials) are owned.
+    pub glyph: Cow<'static, str>,
     pub render_offset: [f32; 2],
 }
 
 impl GfxGlyph {
-    pub fn new(glyph: &'static str) -> GfxGlyph {
+    pub fn new(glyph: impl Into<Cow<'static, str>>) -> GfxGlyph {
         GfxGlyph {
-            glyph,
+            glyph: glyph.into(),
             render_offset: [0.0, 0.0],
         }
     }
 }
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct GfxTile {
     pub glyph: GfxGlyph,
     pub fg: [f32; 4],
@@ -37,3 +42,85 @@ impl GfxRegion {
         }
     }
 }
+
+impl GfxTile {
+    /// A tile with nothing drawn on it.
+    pub fn blank() -> Self {
+        Self {
+            glyph: GfxGlyph::new(" "),
+            fg: [1.0, 1.0, 1.0, 1.0],
+            bg: [0.0, 0.0, 0.0, 1.0],
+        }
+    }
+}
+
+/// A whole screen of tiles, laid out in rows. Everything on screen, world and interface alike, is
+/// captured into one of these, and each backend draws it however it can.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GfxScreen {
+    width: u32,
+    height: u32,
+    /// This vector is guaranteed to be `width * height` in size.
+    tiles: Vec<GfxTile>,
+}
+
+impl GfxScreen {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            tiles: vec![GfxTile::blank(); width as usize * height as usize],
+        }
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// Blanks every tile.
+    pub fn clear(&mut self) {
+        for tile in &mut self.tiles {
+            *tile = GfxTile::blank();
+        }
+    }
+
+    pub fn get(&self, x: i32, y: i32) -> Option<&GfxTile> {
+        self.index(x, y).map(|idx| &self.tiles[idx])
+    }
+
+    /// Sets the tile at `(x, y)`. Tiles off the screen are ignored.
+    pub fn put(&mut self, x: i32, y: i32, tile: GfxTile) {
+        if let Some(idx) = self.index(x, y) {
+            self.tiles[idx] = tile;
+        }
+    }
+
+    /// Writes `text` one character per tile from `(x, y)` to the right, keeping the background of
+    /// the tiles it covers. Returns the column after the text.
+    pub fn print(&mut self, x: i32, y: i32, text: &str, fg: [f32; 4]) -> i32 {
+        let mut column = x;
+        for c in text.chars() {
+            if let Some(idx) = self.index(column, y) {
+                let tile = &mut self.tiles[idx];
+                tile.glyph = GfxGlyph::new(c.to_string());
+                tile.fg = fg;
+            }
+            column += 1;
+        }
+
+        column
+    }
+
+    /// The tiles of each row, top to bottom.
+    pub fn rows(&self) -> impl Iterator<Item = &[GfxTile]> {
+        self.tiles.chunks(self.width.max(1) as usize)
+    }
+
+    fn index(&self, x: i32, y: i32) -> Option<usize> {
+        if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 {
+            return None;
+        }
+
+        Some(y as usize * self.width as usize + x as usize)
+    }
+}
@@ -0,0 +1,109 @@
+//! A full-screen list of what the player is carrying, laid out on the same glyph grid as the world.
+//! Each item is given a letter, which is what the player types to use or drop it.
+
+use crate::state::prelude::*;
+use crate::gfx::glyph_gfx::GfxScreen;
+
+const TITLE_FG: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+const TEXT_FG: [f32; 4] = [0.8, 0.8, 0.8, 1.0];
+const HINT_FG: [f32; 4] = [0.5, 0.5, 0.5, 1.0];
+
+/// The letters items are listed under, in order. Items past the last letter are not listed. `i`
+/// closes the screen, so no item is listed under it.
+const LETTERS: &str = "abcdefghjklmnopqrstuvwxyz";
+
+/// The column item weights are lined up on.
+const WEIGHT_COLUMN: usize = 32;
+
+#[derive(Default)]
+pub struct InventoryScreen {
+    open: bool,
+}
+
+impl InventoryScreen {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    pub fn toggle(&mut self) {
+        self.open = !self.open;
+    }
+
+    /// The carried item listed under `letter`, ignoring case.
+    pub fn item_for_letter(&self, world: &World, letter: char) -> Option<EntityId> {
+        let index = LETTERS.find(letter.to_ascii_lowercase())?;
+        let inventory = world.entities.get::<Inventory>(world.player?)?;
+        inventory.items.get(index).copied()
+    }
+
+    /// The action typing `c` takes on the screen: a lowercase letter wields, wears or takes off
+    /// the item under that letter, and an uppercase one drops it.
+    pub fn action_for_char(&self, world: &World, c: char) -> Option<Action> {
+        let item = self.item_for_letter(world, c)?;
+        if c.is_ascii_uppercase() {
+            return Some(Action::Drop(item));
+        }
+
+        let inventory = world.entities.get::<Inventory>(world.player?)?;
+        match inventory.slot_of(item) {
+            Some(slot) => Some(Action::Unequip(slot)),
+            None if world.entities.has::<Equippable>(item) => Some(Action::Equip(item)),
+            None => None,
+        }
+    }
+
+    /// Draws the list over the whole of `screen`.
+    pub fn capture(&self, world: &World, screen: &mut GfxScreen) {
+        let player = match world.player {
+            Some(player) => player,
+            None => return,
+        };
+        let inventory = world.entities.get::<Inventory>(player);
+
+        let mut lines = Vec::new();
+        let carried = world.carried_weight(player);
+        let max = inventory.map_or(0, |inventory| inventory.max_weight);
+        let title = format!("Inventory ({} / {})", kilograms(carried), kilograms(max));
+        lines.push((title, TITLE_FG));
+        lines.push((String::new(), TEXT_FG));
+
+        let items = inventory.map_or(&[][..], |inventory| &inventory.items[..]);
+        if items.is_empty() {
+            lines.push(("You are not carrying anything.".to_string(), TEXT_FG));
+        }
+        for (letter, &item) in LETTERS.chars().zip(items) {
+            let fg = world.entities.get::<Appearance>(item).map_or(TEXT_FG, |a| a.fg);
+            let weight = world.entities.get::<Item>(item).map_or(0, Item::total_weight);
+
+            let mut line = format!("{} - {}", letter, world.item_label(item));
+            let padding = WEIGHT_COLUMN.saturating_sub(line.chars().count()).max(1);
+            line.push_str(&" ".repeat(padding));
+            line.push_str(&format!("{:>8}", kilograms(weight)));
+            match inventory.and_then(|inventory| inventory.slot_of(item)) {
+                Some(slot) if slot.is_wielded() => line.push_str("  (wielded)"),
+                Some(_) => line.push_str("  (worn)"),
+                None => {},
+            }
+
+            lines.push((line, fg));
+        }
+
+        lines.push((String::new(), TEXT_FG));
+        let hint = "a-z: wield, wear or take off   A-Z: drop   i: close";
+        lines.push((hint.to_string(), HINT_FG));
+
+        screen.clear();
+        for (row, (line, fg)) in lines.iter().enumerate() {
+            screen.print(1, 1 + row as i32, line, *fg);
+        }
+    }
+}
+
+/// A weight in grams, written in kilograms.
+fn kilograms(grams: u32) -> String {
+    format!("{}.{} kg", grams / 1000, grams % 1000 / 100)
+}
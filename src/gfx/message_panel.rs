@@ -0,0 +1,93 @@
+//! Shows the message log: a few lines at the bottom of the screen under the map, or the whole
+//! history full screen, stamped with the turn each message was logged on.
+
+use crate::state::prelude::*;
+use crate::gfx::glyph_gfx::GfxScreen;
+
+/// The rows at the bottom of the screen given to the log, including the rule above it.
+pub const MESSAGE_PANEL_ROWS: u32 = 6;
+
+const RULE_GLYPH: char = '─';
+const RULE_FG: [f32; 4] = [0.35, 0.35, 0.4, 1.0];
+const TITLE_FG: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+const TURN_FG: [f32; 4] = [0.5, 0.5, 0.5, 1.0];
+const HINT_FG: [f32; 4] = [0.5, 0.5, 0.5, 1.0];
+
+#[derive(Default)]
+pub struct MessagePanel {
+    history_open: bool,
+}
+
+impl MessagePanel {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_history_open(&self) -> bool {
+        self.history_open
+    }
+
+    pub fn toggle_history(&mut self) {
+        self.history_open = !self.history_open;
+    }
+
+    /// The number of messages that fit in the panel under the map.
+    pub fn panel_messages(&self) -> usize {
+        MESSAGE_PANEL_ROWS as usize - 1
+    }
+
+    /// The number of messages that fit in the full-screen history on a screen `rows` tall.
+    pub fn history_messages(&self, rows: u32) -> usize {
+        // Leave room for the title, a blank line either side of the list, and the hint.
+        rows.saturating_sub(4) as usize
+    }
+
+    /// Draws the newest messages, or wherever the log is scrolled to, across the bottom
+    /// `MESSAGE_PANEL_ROWS` rows of `screen`.
+    pub fn capture_panel(&self, log: &MessageLog, screen: &mut GfxScreen) {
+        let (columns, rows) = screen.size();
+        let mut rule: String = std::iter::repeat(RULE_GLYPH).take(columns as usize).collect();
+        if log.scroll() > 0 {
+            let note = format!(" {} newer ", log.scroll());
+            let keep = rule.chars().count().saturating_sub(note.chars().count() + 2);
+            rule = rule.chars().take(keep).chain(note.chars()).chain("──".chars()).collect();
+        }
+
+        let mut lines = vec![vec![Span::new(rule, RULE_FG)]];
+        lines.extend(log.visible(self.panel_messages()).map(Message::display_spans));
+
+        let top_row = rows.saturating_sub(MESSAGE_PANEL_ROWS);
+        print_lines(screen, top_row, &lines);
+    }
+
+    /// Draws as much of the log as fits over the whole of `screen`, oldest at the top.
+    pub fn capture_history(&self, log: &MessageLog, screen: &mut GfxScreen) {
+        let rows = screen.size().1;
+        let mut lines = vec![
+            vec![Span::new(format!("Message history ({} messages)", log.len()), TITLE_FG)],
+            Vec::new(),
+        ];
+        for message in log.visible(self.history_messages(rows)) {
+            let turn = message.tick.ticks() / TICKS_PER_TURN;
+            let mut line = vec![Span::new(format!("{:>7}  ", turn), TURN_FG)];
+            line.extend(message.display_spans());
+            lines.push(line);
+        }
+        lines.push(Vec::new());
+        let hint = "up/down, page up/page down: scroll   m: close";
+        lines.push(vec![Span::new(hint, HINT_FG)]);
+
+        screen.clear();
+        print_lines(screen, 0, &lines);
+    }
+}
+
+/// Prints `lines` of coloured spans, one per row, starting at `top_row`.
+fn print_lines(screen: &mut GfxScreen, top_row: u32, lines: &[Vec<Span>]) {
+    for (row, line) in lines.iter().enumerate() {
+        let mut column = 0;
+        for span in line {
+            column = screen.print(column, (top_row as usize + row) as i32, &span.text, span.fg);
+        }
+    }
+}
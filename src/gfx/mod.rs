@@ -3,6 +3,12 @@ pub mod gfx_context;
 pub mod glyph_context;
 pub mod glyph_gfx;
 pub mod gpu_context;
+pub mod inventory_screen;
+pub mod message_panel;
+pub mod raster;
+pub mod surface;
+pub mod terminal;
+pub mod text_grid;
 pub mod world_renderer;
 
 pub mod prelude {
@@ -10,5 +16,11 @@ pub mod prelude {
     pub use super::gfx_context::GfxContext;
     pub use super::glyph_context::{GlyphContext, MonospaceGlyphContext};
     pub use super::gpu_context::GpuContext;
-    pub use super::world_renderer::WorldRenderer;
+    pub use super::inventory_screen::InventoryScreen;
+    pub use super::message_panel::{MessagePanel, MESSAGE_PANEL_ROWS};
+    pub use super::raster::Rasteriser;
+    pub use super::surface::GlyphSurface;
+    pub use super::terminal::{TerminalContext, TerminalInput};
+    pub use super::text_grid::{Cell, TextGrid};
+    pub use super::world_renderer::{RenderModifier, WorldRenderer};
 }
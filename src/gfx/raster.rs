@@ -0,0 +1,248 @@
+//! Draws frames into an RGBA image on the CPU, so that pictures of the game can be taken without a
+//! GPU or a window: for bug reports, the README, or tests. Every cell is filled with its background
+//! colour and its glyph is blended over that in the foreground colour, in the same font the window
+//! uses.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use wgpu_glyph::ab_glyph::{self, Font, FontArc, PxScale, ScaleFont};
+
+use crate::gfx::camera::Camera;
+use crate::gfx::glyph_context::FONT_TTF;
+use crate::gfx::glyph_gfx::GfxScreen;
+use crate::gfx::surface::GlyphSurface;
+use crate::gfx::world_renderer::{RenderModifier, WorldRenderer};
+use crate::state::world::World;
+
+/// The height of glyphs in pixels, matching the window.
+pub const DEFAULT_GLYPH_SCALE: f32 = 20.0;
+
+pub struct Rasteriser {
+    font: FontArc,
+    scale: PxScale,
+    /// The size of every cell in pixels.
+    cell_size: (u32, u32),
+    /// How far below the top of a cell the baseline of its glyph sits, in pixels.
+    baseline: f32,
+    /// The size of the image in pixels.
+    width: u32,
+    height: u32,
+    /// Four bytes per pixel, row by row from the top left. This vector is guaranteed to be
+    /// `width * height * 4` in size.
+    pixels: Vec<u8>,
+}
+
+impl Rasteriser {
+    /// A rasteriser drawing glyphs `glyph_scale` pixels high.
+    pub fn new(glyph_scale: f32) -> Self {
+        let font = FontArc::try_from_slice(FONT_TTF).expect("the built-in font is valid");
+        let scale = PxScale::from(glyph_scale);
+
+        // The font is monospaced, so any glyph gives the width of all of them.
+        let scaled = font.as_scaled(scale);
+        let cell_width = scaled.h_advance(font.glyph_id('M')).ceil() as u32;
+        let cell_height = (scaled.ascent() - scaled.descent()).ceil() as u32;
+        let baseline = scaled.ascent();
+
+        Self {
+            font,
+            scale,
+            cell_size: (cell_width.max(1), cell_height.max(1)),
+            baseline,
+            width: 0,
+            height: 0,
+            pixels: Vec::new(),
+        }
+    }
+
+    pub fn cell_size(&self) -> (u32, u32) {
+        self.cell_size
+    }
+
+    /// The size of the image in pixels.
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// The image as 8-bit RGBA, row by row from the top left.
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    /// The colour of the pixel at `(x, y)`.
+    pub fn pixel(&self, x: u32, y: u32) -> Option<[u8; 4]> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+
+        let idx = (y * self.width + x) as usize * 4;
+        let mut pixel = [0; 4];
+        pixel.copy_from_slice(&self.pixels[idx..idx + 4]);
+        Some(pixel)
+    }
+
+    /// Encodes the image as a PNG into `out`.
+    pub fn encode_png(&self, out: impl Write) -> io::Result<()> {
+        let mut encoder = png::Encoder::new(out, self.width, self.height);
+        encoder.set_color(png::ColorType::RGBA);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut writer = encoder.write_header().map_err(to_io_error)?;
+        writer.write_image_data(&self.pixels).map_err(to_io_error)
+    }
+
+    pub fn save_png(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let out = BufWriter::new(File::create(path)?);
+        self.encode_png(out)
+    }
+
+    fn fill_cell(&mut self, x: u32, y: u32, colour: [u8; 4]) {
+        let (cell_width, cell_height) = self.cell_size;
+        for py in y * cell_height..(y + 1) * cell_height {
+            for px in x * cell_width..(x + 1) * cell_width {
+                let idx = (py * self.width + px) as usize * 4;
+                self.pixels[idx..idx + 4].copy_from_slice(&colour);
+            }
+        }
+    }
+
+    /// Blends `glyph` over the cell at `(x, y)` in `fg`. Anything the glyph draws outside its cell
+    /// is cut off, so that neighbouring cells look the same whatever order they are drawn in.
+    fn draw_glyph(&mut self, x: u32, y: u32, glyph: char, fg: [f32; 4]) {
+        let (cell_width, cell_height) = self.cell_size;
+        let (left, top) = (x * cell_width, y * cell_height);
+
+        let position = ab_glyph::point(left as f32, top as f32 + self.baseline);
+        let glyph = self.font.glyph_id(glyph).with_scale_and_position(self.scale, position);
+        let outline = match self.font.outline_glyph(glyph) {
+            Some(outline) => outline,
+            // Spaces and the like have nothing to draw.
+            None => return,
+        };
+
+        let bounds = outline.px_bounds();
+        let (width, pixels) = (self.width, &mut self.pixels);
+        outline.draw(|gx, gy, coverage| {
+            let px = bounds.min.x as i32 + gx as i32;
+            let py = bounds.min.y as i32 + gy as i32;
+            let in_cell = px >= left as i32 && px < (left + cell_width) as i32
+                && py >= top as i32 && py < (top + cell_height) as i32;
+            if !in_cell {
+                return;
+            }
+
+            let alpha = coverage.max(0.0).min(1.0) * fg[3];
+            let idx = (py as u32 * width + px as u32) as usize * 4;
+            for channel in 0..3 {
+                let under = pixels[idx + channel] as f32 / 255.0;
+                pixels[idx + channel] = to_u8(under + (fg[channel] - under) * alpha);
+            }
+        });
+    }
+}
+
+impl GlyphSurface for Rasteriser {
+    fn clear(&mut self, width: u32, height: u32) {
+        self.width = width * self.cell_size.0;
+        self.height = height * self.cell_size.1;
+        self.pixels.clear();
+        self.pixels.resize(self.width as usize * self.height as usize * 4, 0);
+
+        // Start from opaque black, as the window does.
+        for pixel in self.pixels.chunks_mut(4) {
+            pixel[3] = 255;
+        }
+    }
+
+    fn put(&mut self, x: u32, y: u32, glyph: &str, fg: [f32; 4], bg: [f32; 4]) {
+        if (x + 1) * self.cell_size.0 > self.width || (y + 1) * self.cell_size.1 > self.height {
+            return;
+        }
+
+        // Backgrounds are drawn opaque; there is nothing behind them to show through.
+        self.fill_cell(x, y, [to_u8(bg[0]), to_u8(bg[1]), to_u8(bg[2]), 255]);
+        for c in glyph.chars().filter(|c| !c.is_whitespace()) {
+            self.draw_glyph(x, y, c, fg);
+        }
+    }
+
+    /// The image is ready as soon as it is drawn, so there is nothing to show.
+    fn present(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Draws what `camera` looks at in `world`, from the player's point of view unless `reveal_all` is
+/// set, and saves it as a PNG at `path`.
+pub fn save_world_png(
+    world: &mut World,
+    camera: Camera,
+    reveal_all: bool,
+    path: impl AsRef<Path>,
+) -> io::Result<()> {
+    let (columns, rows) = camera.tiles_dims;
+
+    let mut world_renderer = WorldRenderer::new(world.id);
+    *world_renderer.camera_mut() = camera;
+    if reveal_all {
+        world_renderer.add_render_modifier(RenderModifier::RevealAll);
+    }
+
+    let mut screen = GfxScreen::new(columns, rows);
+    world_renderer.capture(world, &mut screen);
+
+    let mut rasteriser = Rasteriser::new(DEFAULT_GLYPH_SCALE);
+    rasteriser.draw(&screen)?;
+    rasteriser.save_png(path)
+}
+
+/// A colour channel from 0 to 1 as an 8-bit channel.
+fn to_u8(channel: f32) -> u8 {
+    (channel.max(0.0).min(1.0) * 255.0).round() as u8
+}
+
+fn to_io_error(error: png::EncodingError) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gfx::glyph_gfx::{GfxGlyph, GfxTile};
+
+    const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+
+    #[test]
+    fn rasterises_cells_and_encodes_png() {
+        let bg = [0.0, 0.4, 0.8, 1.0];
+        let mut screen = GfxScreen::new(3, 2);
+        screen.put(1, 1, GfxTile { glyph: GfxGlyph::new("@"), fg: [1.0, 1.0, 0.0, 1.0], bg });
+
+        let mut rasteriser = Rasteriser::new(DEFAULT_GLYPH_SCALE);
+        rasteriser.draw(&screen).unwrap();
+
+        let (cell_width, cell_height) = rasteriser.cell_size();
+        assert!(cell_width > 0 && cell_height > cell_width);
+        assert_eq!(rasteriser.size(), (3 * cell_width, 2 * cell_height));
+
+        // Corners of the cell are clear of the glyph, so they show the background as it is.
+        assert_eq!(rasteriser.pixel(cell_width, cell_height), Some([0, 102, 204, 255]));
+        assert_eq!(rasteriser.pixel(0, 0), Some([0, 0, 0, 255]));
+        assert_eq!(rasteriser.pixel(3 * cell_width, 0), None);
+
+        // The glyph leaves its mark somewhere in the cell.
+        let glyph_drawn = (cell_width..2 * cell_width)
+            .flat_map(|x| (cell_height..2 * cell_height).map(move |y| (x, y)))
+            .any(|(x, y)| rasteriser.pixel(x, y).unwrap()[0] > 128);
+        assert!(glyph_drawn);
+
+        let mut png = Vec::new();
+        rasteriser.encode_png(&mut png).unwrap();
+        assert_eq!(png[..8], PNG_SIGNATURE);
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(png[16..20], (3 * cell_width).to_be_bytes());
+        assert_eq!(png[20..24], (2 * cell_height).to_be_bytes());
+    }
+}
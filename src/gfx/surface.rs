@@ -0,0 +1,31 @@
+//! Where finished frames go. The game captures everything on screen into a `GfxScreen`, and a
+//! `GlyphSurface` is anything that can show one: a window, a terminal, or a grid in memory that
+//! tests can read back.
+
+use std::io;
+
+use crate::gfx::glyph_gfx::GfxScreen;
+
+pub trait GlyphSurface {
+    /// Blanks the whole surface, sized `width` by `height` cells for the frame about to be drawn.
+    fn clear(&mut self, width: u32, height: u32);
+
+    /// Puts `glyph` in the cell at `(x, y)`, drawn in `fg` over `bg`. Cells outside the size given
+    /// to the last `clear` are ignored.
+    fn put(&mut self, x: u32, y: u32, glyph: &str, fg: [f32; 4], bg: [f32; 4]);
+
+    /// Shows everything put since the last `clear`.
+    fn present(&mut self) -> io::Result<()>;
+
+    /// Draws the whole of `screen` as one frame.
+    fn draw(&mut self, screen: &GfxScreen) -> io::Result<()> {
+        let (width, height) = screen.size();
+        self.clear(width, height);
+        for (y, row) in screen.rows().enumerate() {
+            for (x, tile) in row.iter().enumerate() {
+                self.put(x as u32, y as u32, &tile.glyph.glyph, tile.fg, tile.bg);
+            }
+        }
+        self.present()
+    }
+}
@@ -0,0 +1,235 @@
+//! Draws the screen to a terminal with ANSI escape codes, for machines without a GPU. Colours are
+//! sent as 24-bit truecolor, so the terminal needs to support it to look right. Only rows that
+//! changed since the last frame are redrawn.
+//!
+//! The terminal is switched to raw mode and the alternate screen while a `TerminalContext` is
+//! alive, and put back the way it was when it is dropped.
+
+use std::fmt::Write as _;
+use std::io::{self, Write};
+
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyModifiers};
+use crossterm::terminal;
+
+use crate::game::Key;
+use crate::gfx::glyph_gfx::{GfxGlyph, GfxScreen, GfxTile};
+use crate::gfx::surface::GlyphSurface;
+
+const ENTER_ALTERNATE_SCREEN: &str = "\x1b[?1049h";
+const LEAVE_ALTERNATE_SCREEN: &str = "\x1b[?1049l";
+const HIDE_CURSOR: &str = "\x1b[?25l";
+const SHOW_CURSOR: &str = "\x1b[?25h";
+const RESET_COLOURS: &str = "\x1b[0m";
+const CLEAR_SCREEN: &str = "\x1b[2J";
+
+/// Something that happened at the terminal.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TerminalInput {
+    Key(Key),
+    /// The terminal is now this many columns and rows in size.
+    Resized(u32, u32),
+}
+
+pub struct TerminalContext {
+    out: io::Stdout,
+    /// The frame being put together, written out when it is presented.
+    frame: GfxScreen,
+    /// What is on the terminal now, to redraw only what changed.
+    previous: Option<GfxScreen>,
+}
+
+impl TerminalContext {
+    pub fn create() -> io::Result<Self> {
+        terminal::enable_raw_mode().map_err(to_io_error)?;
+
+        let mut out = io::stdout();
+        write!(out, "{}{}{}", ENTER_ALTERNATE_SCREEN, HIDE_CURSOR, CLEAR_SCREEN)?;
+        out.flush()?;
+
+        Ok(Self {
+            out,
+            frame: GfxScreen::new(0, 0),
+            previous: None,
+        })
+    }
+
+    /// The size of the terminal in columns and rows.
+    pub fn size(&self) -> io::Result<(u32, u32)> {
+        let (columns, rows) = terminal::size().map_err(to_io_error)?;
+        Ok((columns as u32, rows as u32))
+    }
+
+    /// Waits for the next key press or resize. Anything else the terminal reports is skipped.
+    pub fn next_input(&mut self) -> io::Result<TerminalInput> {
+        loop {
+            match event::read().map_err(to_io_error)? {
+                Event::Key(key_event) => {
+                    if let Some(key) = key_for_event(key_event) {
+                        return Ok(TerminalInput::Key(key));
+                    }
+                },
+                Event::Resize(columns, rows) => {
+                    return Ok(TerminalInput::Resized(columns as u32, rows as u32));
+                },
+                _ => {},
+            }
+        }
+    }
+}
+
+impl GlyphSurface for TerminalContext {
+    fn clear(&mut self, width: u32, height: u32) {
+        if self.frame.size() == (width, height) {
+            self.frame.clear();
+        } else {
+            self.frame = GfxScreen::new(width, height);
+        }
+    }
+
+    fn put(&mut self, x: u32, y: u32, glyph: &str, fg: [f32; 4], bg: [f32; 4]) {
+        let glyph = GfxGlyph::new(glyph.to_string());
+        self.frame.put(x as i32, y as i32, GfxTile { glyph, fg, bg });
+    }
+
+    /// Draws the frame from the top left of the terminal.
+    fn present(&mut self) -> io::Result<()> {
+        let screen = &self.frame;
+        let previous = self.previous.as_ref().filter(|p| p.size() == screen.size());
+        let frame = encode_frame(screen, previous);
+
+        self.out.write_all(frame.as_bytes())?;
+        self.out.flush()?;
+        self.previous = Some(screen.clone());
+        Ok(())
+    }
+}
+
+impl Drop for TerminalContext {
+    fn drop(&mut self) {
+        // Nothing more can be done if the terminal cannot be restored.
+        let _ = write!(self.out, "{}{}{}", RESET_COLOURS, SHOW_CURSOR, LEAVE_ALTERNATE_SCREEN);
+        let _ = self.out.flush();
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// The escape codes and characters that draw `screen` over `previous`, the same size screen
+/// already on the terminal. Without one, the terminal is cleared and every row is drawn.
+fn encode_frame(screen: &GfxScreen, previous: Option<&GfxScreen>) -> String {
+    let mut frame = String::new();
+    if previous.is_none() {
+        frame.push_str(RESET_COLOURS);
+        frame.push_str(CLEAR_SCREEN);
+    }
+
+    let previous_rows: Vec<_> = previous.map_or(Vec::new(), |p| p.rows().collect());
+    for (y, row) in screen.rows().enumerate() {
+        if previous_rows.get(y) == Some(&row) {
+            continue;
+        }
+
+        // Rows are written from their first column, and colours only when they change.
+        write!(frame, "\x1b[{};1H", y + 1).unwrap();
+        let mut colours = None;
+        for tile in row {
+            let tile_colours = (to_rgb(tile.fg), to_rgb(tile.bg));
+            if colours != Some(tile_colours) {
+                let ((fr, fg, fb), (br, bg, bb)) = tile_colours;
+                write!(frame, "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m", fr, fg, fb, br, bg, bb)
+                    .unwrap();
+                colours = Some(tile_colours);
+            }
+            frame.push_str(printable(tile));
+        }
+    }
+    frame.push_str(RESET_COLOURS);
+
+    frame
+}
+
+fn key_for_event(KeyEvent { code, modifiers }: KeyEvent) -> Option<Key> {
+    match code {
+        // Raw mode swallows the interrupt, so treat it as a request to quit.
+        KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => Some(Key::Quit),
+        KeyCode::Char(c) => Some(Key::Char(c)),
+        KeyCode::Up => Some(Key::Up),
+        KeyCode::Down => Some(Key::Down),
+        KeyCode::Left => Some(Key::Left),
+        KeyCode::Right => Some(Key::Right),
+        KeyCode::PageUp => Some(Key::PageUp),
+        KeyCode::PageDown => Some(Key::PageDown),
+        KeyCode::Esc => Some(Key::Escape),
+        _ => None,
+    }
+}
+
+/// A colour with channels from 0 to 1 as 8-bit channels. Alpha is ignored.
+fn to_rgb([r, g, b, _]: [f32; 4]) -> (u8, u8, u8) {
+    let channel = |c: f32| (c.max(0.0).min(1.0) * 255.0).round() as u8;
+    (channel(r), channel(g), channel(b))
+}
+
+/// The glyph of `tile`, or a space if it would upset the terminal.
+fn printable(tile: &GfxTile) -> &str {
+    let glyph = &*tile.glyph.glyph;
+    if glyph.chars().count() != 1 || glyph.chars().any(char::is_control) {
+        " "
+    } else {
+        glyph
+    }
+}
+
+fn to_io_error(error: crossterm::ErrorKind) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, error.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+    const BLACK: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+    const RED: [f32; 4] = [1.0, 0.0, 0.0, 1.0];
+
+    fn tile(glyph: &'static str, fg: [f32; 4]) -> GfxTile {
+        GfxTile { glyph: GfxGlyph::new(glyph), fg, bg: BLACK }
+    }
+
+    /// A two by two screen with `bottom_right` in its bottom right corner.
+    fn screen(bottom_right: GfxTile) -> GfxScreen {
+        let mut screen = GfxScreen::new(2, 2);
+        screen.put(0, 0, tile("#", WHITE));
+        screen.put(1, 0, tile(".", WHITE));
+        screen.put(0, 1, tile("@", RED));
+        screen.put(1, 1, bottom_right);
+        screen
+    }
+
+    #[test]
+    fn first_frames_clear_and_draw_every_row() {
+        let frame = encode_frame(&screen(tile("\n", RED)), None);
+
+        // Colours are only sent when they change, and control characters become spaces.
+        let expected = concat!(
+            "\x1b[0m\x1b[2J",
+            "\x1b[1;1H\x1b[38;2;255;255;255m\x1b[48;2;0;0;0m#.",
+            "\x1b[2;1H\x1b[38;2;255;0;0m\x1b[48;2;0;0;0m@ ",
+            "\x1b[0m",
+        );
+        assert_eq!(frame, expected);
+    }
+
+    #[test]
+    fn later_frames_redraw_only_changed_rows() {
+        let previous = screen(tile("g", RED));
+        let frame = encode_frame(&screen(tile("g", WHITE)), Some(&previous));
+
+        let expected = concat!(
+            "\x1b[2;1H\x1b[38;2;255;0;0m\x1b[48;2;0;0;0m@",
+            "\x1b[38;2;255;255;255m\x1b[48;2;0;0;0mg",
+            "\x1b[0m",
+        );
+        assert_eq!(frame, expected);
+        assert_eq!(encode_frame(&previous, Some(&previous)), RESET_COLOURS);
+    }
+}
@@ -0,0 +1,175 @@
+//! A surface that only remembers what was put where, so that frames can be checked without a GPU
+//! or a terminal. A frame reads back as plain text, one line per row, which can be compared against
+//! a golden file kept next to the code that draws it.
+//!
+//! Golden files are rewritten instead of compared when `UPDATE_GOLDEN` is set in the environment,
+//! so an intended change to what is drawn is one run away from its new golden files.
+
+use std::io;
+use std::path::Path;
+
+use crate::gfx::surface::GlyphSurface;
+
+/// The variable that turns golden file checks into golden file updates.
+const UPDATE_GOLDEN_VAR: &str = "UPDATE_GOLDEN";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cell {
+    pub glyph: char,
+    pub fg: [f32; 4],
+    pub bg: [f32; 4],
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Self {
+            glyph: ' ',
+            fg: [1.0, 1.0, 1.0, 1.0],
+            bg: [0.0, 0.0, 0.0, 1.0],
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TextGrid {
+    width: u32,
+    height: u32,
+    /// This vector is guaranteed to be `width * height` in size.
+    cells: Vec<Cell>,
+    /// How many frames have been presented.
+    frames: u32,
+}
+
+impl TextGrid {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> Option<&Cell> {
+        if x < self.width && y < self.height {
+            Some(&self.cells[(y * self.width + x) as usize])
+        } else {
+            None
+        }
+    }
+
+    pub fn cells(&self) -> &[Cell] {
+        &self.cells
+    }
+
+    /// The glyphs of the grid, one line per row. Trailing spaces are trimmed from each line so that
+    /// golden files do not depend on them surviving editors.
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for row in self.cells.chunks(self.width.max(1) as usize) {
+            let line: String = row.iter().map(|cell| cell.glyph).collect();
+            text.push_str(line.trim_end());
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Compares the grid's text against the golden file at `path`, or writes it there instead if
+    /// `UPDATE_GOLDEN` is set.
+    pub fn check_golden(&self, path: impl AsRef<Path>) -> Result<(), GoldenError> {
+        let path = path.as_ref();
+        let actual = self.to_text();
+
+        if std::env::var_os(UPDATE_GOLDEN_VAR).is_some() {
+            return std::fs::write(path, actual).map_err(GoldenError::Io);
+        }
+
+        let expected = std::fs::read_to_string(path).map_err(GoldenError::Io)?;
+        match first_difference(&expected, &actual) {
+            None => Ok(()),
+            Some((line, expected, actual)) => Err(GoldenError::Mismatch { line, expected, actual }),
+        }
+    }
+}
+
+impl GlyphSurface for TextGrid {
+    fn clear(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+        self.cells.clear();
+        self.cells.resize(width as usize * height as usize, Cell::default());
+    }
+
+    fn put(&mut self, x: u32, y: u32, glyph: &str, fg: [f32; 4], bg: [f32; 4]) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+
+        // Every glyph drawn so far is a single character; anything else would not line up in text.
+        let glyph = glyph.chars().next().unwrap_or(' ');
+        self.cells[(y * self.width + x) as usize] = Cell { glyph, fg, bg };
+    }
+
+    fn present(&mut self) -> io::Result<()> {
+        self.frames += 1;
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum GoldenError {
+    Io(io::Error),
+    /// The grid does not match the golden file, first at this line (counting from 1).
+    Mismatch {
+        line: usize,
+        expected: String,
+        actual: String,
+    },
+}
+
+impl std::fmt::Display for GoldenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            GoldenError::Io(e) => write!(f, "i/o error: {}", e),
+            GoldenError::Mismatch { line, expected, actual } => write!(
+                f,
+                "frame differs from golden file at line {}:\n expected: {:?}\n   actual: {:?}\n\
+                 (set {} to update the golden file)",
+                line, expected, actual, UPDATE_GOLDEN_VAR,
+            ),
+        }
+    }
+}
+
+impl std::error::Error for GoldenError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GoldenError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// The first line that differs between `expected` and `actual`, counting from 1, with both versions
+/// of it. A missing line reads as empty. Line endings are ignored, so golden files survive being
+/// checked out on Windows.
+fn first_difference(expected: &str, actual: &str) -> Option<(usize, String, String)> {
+    let mut expected = expected.lines();
+    let mut actual = actual.lines();
+
+    for line in 1.. {
+        match (expected.next(), actual.next()) {
+            (None, None) => return None,
+            (e, a) if e == a => {},
+            (e, a) => {
+                let (e, a) = (e.unwrap_or_default(), a.unwrap_or_default());
+                return Some((line, e.to_string(), a.to_string()));
+            },
+        }
+    }
+
+    unreachable!()
+}
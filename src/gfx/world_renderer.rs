@@ -7,11 +7,18 @@ use std::collections::{HashMap, HashSet};
 
 #[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
 pub enum RenderModifier {
+    /// Views the world upside down: ceilings are drawn as the surfaces things stand on, floors as
+    /// the overhead, and holes look up into the level above instead of down into the one below.
     GravityInverse,
+    /// Draws everything as if it were in view and fully lit, ignoring what the player has seen.
+    /// For pictures of whole maps rather than for play.
+    RevealAll,
 }
 
 struct CachedRegion {
-    tick: Tick,
+    /// The versions of the world region, the region below it and the region's light levels that
+    /// this was rendered from.
+    source: ((Tick, u64, u64), Option<(Tick, u64)>, u64),
     region: GfxRegion
 }
 
@@ -20,10 +27,35 @@ pub struct WorldRenderer {
     render_modifiers: HashSet<RenderModifier>,
     render_cache: HashMap<(i32, i32, i32), CachedRegion>,
     camera: gfx::camera::Camera,
+    /// Materials we have already warned about, so that a missing material is logged once rather
+    /// than for every tile made of it.
+    unknown_materials: HashSet<MaterialId>,
 }
 
 const OFF_SCREEN_RENDER_HEURISTIC: i32 = 2;
 
+/// How much darker the level below looks when seen through a hole in the floor.
+const BELOW_DIM: f32 = 0.45;
+/// How much darker floors look when there is a ceiling overhead.
+const OVERHANG_DIM: f32 = 0.7;
+
+/// Drawn where there is nothing to stand on and nothing visible underneath either.
+const CHASM_GLYPH: &str = ":";
+const CHASM_FG: [f32; 4] = [0.25, 0.25, 0.35, 1.0];
+const CHASM_BG: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+
+const CONNECTOR_FG: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
+/// How much darker remembered tiles look than tiles in view.
+const REMEMBERED_DIM: f32 = 0.5;
+/// How much colour remembered tiles keep, from 0 (grey) to 1 (full colour).
+const REMEMBERED_SATURATION: f32 = 0.3;
+
+/// Drawn for tiles the player has never seen.
+const UNKNOWN_GLYPH: &str = " ";
+const UNKNOWN_COLOUR: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+
+
 impl WorldRenderer {
     pub fn new(world_id: WorldId) -> Self {
         let camera = gfx::camera::Camera {
@@ -38,14 +70,17 @@ impl WorldRenderer {
             render_modifiers: HashSet::new(),
             render_cache: HashMap::new(),
             camera,
+            unknown_materials: HashSet::new(),
         }
     }
 
-    pub fn render(
+    /// Captures the part of the world the camera looks at into the top left of `screen`, one tile
+    /// per glyph.
+    pub fn capture(
         &mut self,
         // TODO: Separate the world from the cache so that we don't need a mutable handle to the world.
         world: &mut World,
-        glyph_context: &mut gfx::glyph_context::MonospaceGlyphContext,
+        screen: &mut GfxScreen,
     ) {
         assert!(self.world_id == world.id,
             "World renderer called with a different world than the one with which is was initialized.");
@@ -65,87 +100,162 @@ impl WorldRenderer {
 
         for y in base_y..final_y {
             for x in base_x..final_x {
-                self.render_offset(world, glyph_context, (x, y, z));
+                self.capture_offset(world, screen, (x, y, z));
             }
         }
+
+        // Everything on screen has just been touched, so only regions that scrolled out of view
+        // are candidates for eviction.
+        if let Err(e) = world.evict_regions() {
+            log::error!("Failed to evict regions to the region store: {}", e);
+        }
     }
 
-    fn render_offset(
+    fn capture_offset(
         &mut self,
         world: &mut World,
-        glyph_context: &mut gfx::glyph_context::MonospaceGlyphContext,
+        screen: &mut GfxScreen,
         offset: (i32, i32, i32),
     ) {
         let (tile_x, tile_y) = self.camera.get_screen_coords(offset);
+        let (columns, rows) = self.camera.tiles_dims;
 
+        let reveal_all = self.render_modifiers.contains(&RenderModifier::RevealAll);
         let region = &self.get_cached_region(world, offset).region;
 
-        let mut section = wgpu_glyph::Section {
-            screen_position: (
-                tile_x as f32 * glyph_context.glyph_width,
-                tile_y as f32 * glyph_context.glyph_height,
-            ),
-            ..wgpu_glyph::Section::default()
-        };
+        // Entities are drawn over the cached terrain rather than baked into it, so moving them
+        // does not invalidate the cache. Where several share a tile, the player is drawn over
+        // monsters, monsters over items and items over anything else lying on the floor, such as
+        // corpses. Only entities in view are drawn; remembered tiles show the terrain alone.
+        let mut entity_tiles: HashMap<usize, (&Appearance, u8)> = HashMap::new();
+        for &id in world.entities.in_region(offset) {
+            let appearance = world.entities.get::<Appearance>(id);
+            let (appearance, pos) = match (appearance, world.entities.position(id)) {
+                (Some(appearance), Some(pos)) => (appearance, pos),
+                _ => continue,
+            };
+
+            if !reveal_all && world.visibility(pos) != Visibility::Visible {
+                continue;
+            }
+
+            let rank = match id {
+                _ if world.entities.has::<PlayerControlled>(id) => 3,
+                _ if world.entities.has::<Blocking>(id) => 2,
+                _ if world.entities.has::<Item>(id) => 1,
+                _ => 0,
+            };
+            let idx = split_position(pos).1;
+            if entity_tiles.get(&idx).map_or(true, |&(_, top)| rank > top) {
+                entity_tiles.insert(idx, (appearance, rank));
+            }
+        }
 
         // Do not attempt to render a region if its `Tiles` vector is malformed.
         if region.tiles.len() != REGION_DIM as usize * REGION_DIM as usize {
             return;
         }
 
-        for y in 0..REGION_DIM {
-            for x in 0..REGION_DIM {
+        for y in 0..REGION_DIM as i32 {
+            for x in 0..REGION_DIM as i32 {
+                // Keep to the camera's view; the rest of the screen belongs to other panels.
+                let (screen_x, screen_y) = (tile_x + x, tile_y + y);
+                if screen_x >= columns as i32 || screen_y >= rows as i32 {
+                    continue;
+                }
+
                 let idx = (y as usize * REGION_DIM as usize) + x as usize;
                 let tile = &region.tiles[idx];
-                section = section.add_text(
-                    wgpu_glyph::Text::new(tile.glyph.glyph)
-                        .with_color(tile.fg)
-                        .with_scale(glyph_context.get_px_scale())
-                );
+                let tile = match entity_tiles.get(&idx) {
+                    Some((appearance, _)) => GfxTile {
+                        glyph: GfxGlyph::new(appearance.glyph.clone()),
+                        fg: appearance.fg,
+                        bg: tile.bg,
+                    },
+                    None => tile.clone(),
+                };
+                screen.put(screen_x, screen_y, tile);
             }
-            // TODO: Is this really the best way to do this?
-            section = section.add_text(wgpu_glyph::Text::new("\n"));
         }
-
-        glyph_context.glyph_context.glyph_brush.queue(section);
     }
 
     fn get_cached_region(
         &mut self,
-        world: &World,
+        world: &mut World,
         offset: (i32, i32, i32),
     ) -> &CachedRegion {
-        // TODO: This logic is haphazard.
-        if let Some(world_region) = world.get_cached_region(offset) {
-            let render_modifiers = &self.render_modifiers;
-            self.render_cache.entry(offset)
-                .and_modify(|cached_region| {
-                    if world_region.last_update_tick > cached_region.tick {
-                        *cached_region = CachedRegion {
-                            region: gen_gfx_region(&world_region.region, render_modifiers),
-                            tick: world.current_tick,
-                        };
-                    }
-                })
-                .or_insert_with(|| CachedRegion {
-                    region: gen_gfx_region(&world_region.region, render_modifiers),
-                    tick: world.current_tick,
-                })
+        // With gravity inverted, "below" is the level above.
+        let below_offset = if self.render_modifiers.contains(&RenderModifier::GravityInverse) {
+            (offset.0, offset.1, offset.2 + 1)
         } else {
-            // The region wasn't loaded in memory, so there's no way we can render it. Just "render"
-            // the empty region instead.
-            self.render_cache.entry(offset)
-                .and_modify(|cr| *cr = CachedRegion {
-                    region: GfxRegion::empty(),
-                    // Aggressively try to rerender this region.
-                    tick: world.current_tick - 1,
-                })
-                .or_insert_with(|| CachedRegion {
-                    region: GfxRegion::empty(),
-                    // Aggressively try to rerender this region.
-                    tick: world.current_tick - 1,
+            (offset.0, offset.1, offset.2 - 1)
+        };
+
+        // Regions are streamed in or generated on demand, so every offset has a world region. Load
+        // it up front so that the rest of the world can be borrowed alongside it. Holes in the
+        // region show the level below, so that needs to be loaded too.
+        let render_modifiers = &self.render_modifiers;
+        let sees_below = world.load_region(offset).region.blocks.iter()
+            .map(|b| apply_render_modifiers(b.fill, render_modifiers))
+            .any(|fill| matches!(fill, BlockFill::Empty | BlockFill::Ceiling(_)));
+        if sees_below {
+            world.load_region(below_offset);
+        }
+        let light_revision = world.update_region_light(offset);
+
+        let world: &World = world;
+        let world_region = world.get_cached_region(offset).expect("region was just loaded");
+        let below_region = if sees_below { world.get_cached_region(below_offset) } else { None };
+
+        let light = world.lighting.region(offset).expect("light was just updated");
+
+        let source = (
+            world_region.view_version(),
+            below_region.map(|r| r.version()),
+            light_revision,
+        );
+        let stale = match self.render_cache.get(&offset) {
+            Some(cached_region) => cached_region.source != source,
+            None => true,
+        };
+
+        if stale {
+            let dim = REGION_DIM as i32;
+            let reveal_all = self.render_modifiers.contains(&RenderModifier::RevealAll);
+            let visibility: Vec<Visibility> = (0..REGION_LEN as i32)
+                .map(|idx| {
+                    if reveal_all {
+                        return Visibility::Visible;
+                    }
+
+                    let (x, y) = (offset.0 * dim + idx % dim, offset.1 * dim + idx / dim);
+                    world.visibility((x, y, offset.2))
                 })
+                .collect();
+
+            let region = gen_gfx_region(
+                &world_region.region,
+                below_region.map(|r| &r.region),
+                &visibility,
+                &light.levels,
+                &mut TileStyler {
+                    materials: &world.materials,
+                    unknown_materials: &mut self.unknown_materials,
+                },
+                &self.render_modifiers,
+            );
+            self.render_cache.insert(offset, CachedRegion { region, source });
         }
+
+        &self.render_cache[&offset]
+    }
+
+    pub fn camera(&self) -> &gfx::camera::Camera {
+        &self.camera
+    }
+
+    pub fn camera_mut(&mut self) -> &mut gfx::camera::Camera {
+        &mut self.camera
     }
 
     pub fn add_render_modifier(&mut self, render_modifier: RenderModifier) {
@@ -157,25 +267,312 @@ impl WorldRenderer {
         self.render_cache.clear();
         self.render_modifiers.remove(&render_modifier);
     }
+
+    pub fn toggle_render_modifier(&mut self, render_modifier: RenderModifier) {
+        if self.render_modifiers.contains(&render_modifier) {
+            self.remove_render_modifier(render_modifier);
+        } else {
+            self.add_render_modifier(render_modifier);
+        }
+    }
+}
+
+/// Turns block fills into tiles, looking materials up in the registry.
+struct TileStyler<'a> {
+    materials: &'a MaterialRegistry,
+    unknown_materials: &'a mut HashSet<MaterialId>,
+}
+
+impl<'a> TileStyler<'a> {
+    fn material(&mut self, m_id: MaterialId) -> &'a Material {
+        if !self.materials.contains(m_id) && self.unknown_materials.insert(m_id) {
+            log::warn!("Material {:?} is not in the material registry; drawing a placeholder", m_id);
+        }
+
+        self.materials.get(m_id)
+    }
+
+    fn tile(&mut self, fill: BlockFill, below: Option<BlockFill>) -> GfxTile {
+        match fill {
+            BlockFill::Solid(m_id) => {
+                let material = self.material(m_id);
+                GfxTile {
+                    glyph: GfxGlyph::new(material.glyph.clone()),
+                    fg: material.fg,
+                    bg: material.bg,
+                }
+            },
+            // Floors open to the sky.
+            BlockFill::Floor(m_id) => self.surface(m_id, 1.0),
+            // Floors under an overhang are drawn in the ceiling's shadow.
+            BlockFill::FloorCeiling(floor_id, ceiling_id) => GfxTile {
+                bg: dim(self.material(ceiling_id).bg, OVERHANG_DIM),
+                ..self.surface(floor_id, OVERHANG_DIM)
+            },
+            // A hole under an overhang.
+            BlockFill::Ceiling(ceiling_id) => GfxTile {
+                bg: dim(self.material(ceiling_id).bg, OVERHANG_DIM),
+                ..self.below(below)
+            },
+            // Open space; look straight down through it.
+            BlockFill::Empty => self.below(below),
+        }
+    }
+
+    /// The top of whatever is underneath a hole, seen from the level above.
+    fn below(&mut self, below: Option<BlockFill>) -> GfxTile {
+        match below {
+            Some(BlockFill::Solid(m_id)) | Some(BlockFill::Floor(m_id)) => {
+                self.surface(m_id, BELOW_DIM)
+            },
+            // From above, the roof of the block below is what can be seen.
+            Some(BlockFill::Ceiling(m_id)) | Some(BlockFill::FloorCeiling(_, m_id)) => {
+                self.surface(m_id, BELOW_DIM)
+            },
+            Some(BlockFill::Empty) | None => GfxTile {
+                glyph: GfxGlyph::new(CHASM_GLYPH),
+                fg: CHASM_FG,
+                bg: CHASM_BG,
+            },
+        }
+    }
+
+    /// A walkable surface of the given material, darkened by `brightness`.
+    fn surface(&mut self, m_id: MaterialId, brightness: f32) -> GfxTile {
+        let material = self.material(m_id);
+        GfxTile {
+            glyph: GfxGlyph::new(material.floor_glyph.clone()),
+            fg: dim(material.fg, brightness),
+            bg: dim(material.bg, brightness),
+        }
+    }
+
+    /// Drawn for blocks the renderer cannot make sense of at all.
+    fn placeholder(&mut self) -> GfxTile {
+        self.tile(BlockFill::Solid(MaterialId::UNKNOWN), None)
+    }
 }
 
-fn gen_gfx_region(region: &Region, _render_modifiers: &HashSet<RenderModifier>) -> GfxRegion {
-    let mut tiles = Vec::with_capacity(REGION_DIM as usize * REGION_DIM as usize);
-    for b in &region.blocks {
-        match b.fill {
-            BlockFill::Solid(_m_id) => tiles.push(GfxTile {
-                glyph: GfxGlyph::new("#"),
-                fg: [1.0, 0.0, 0.0, 1.0],
-                bg: [0.0, 1.0, 0.0, 1.0],
-            }),
-            BlockFill::Floor(_m_id) => tiles.push(GfxTile {
-                glyph: GfxGlyph::new("."),
-                fg: [0.0, 0.0, 1.0, 1.0],
-                bg: [0.0, 0.0, 0.0, 1.0],
-            }),
-            _ => todo!(),
+fn dim([r, g, b, a]: [f32; 4], brightness: f32) -> [f32; 4] {
+    [r * brightness, g * brightness, b * brightness, a]
+}
+
+/// Pulls a colour towards grey, keeping `saturation` of its colour.
+fn desaturate([r, g, b, a]: [f32; 4], saturation: f32) -> [f32; 4] {
+    let grey = 0.3 * r + 0.59 * g + 0.11 * b;
+    let mix = |c: f32| grey + (c - grey) * saturation;
+    [mix(r), mix(g), mix(b), a]
+}
+
+/// Tints a colour by a light level. Channels saturate rather than exceeding full brightness.
+fn tint([r, g, b, a]: [f32; 4], [lr, lg, lb]: [f32; 3]) -> [f32; 4] {
+    [(r * lr).min(1.0), (g * lg).min(1.0), (b * lb).min(1.0), a]
+}
+
+/// How a tile looks under the given light level.
+fn lit(tile: GfxTile, level: [f32; 3]) -> GfxTile {
+    GfxTile {
+        fg: tint(tile.fg, level),
+        bg: tint(tile.bg, level),
+        ..tile
+    }
+}
+
+/// How a tile looks when it is remembered rather than in view.
+fn remembered(tile: GfxTile) -> GfxTile {
+    GfxTile {
+        fg: dim(desaturate(tile.fg, REMEMBERED_SATURATION), REMEMBERED_DIM),
+        bg: dim(desaturate(tile.bg, REMEMBERED_SATURATION), REMEMBERED_DIM),
+        ..tile
+    }
+}
+
+fn unknown() -> GfxTile {
+    GfxTile {
+        glyph: GfxGlyph::new(UNKNOWN_GLYPH),
+        fg: UNKNOWN_COLOUR,
+        bg: UNKNOWN_COLOUR,
+    }
+}
+
+fn connector_glyph(connector: Connector) -> &'static str {
+    match connector {
+        Connector::StairsUp => "<",
+        Connector::StairsDown => ">",
+        Connector::Ladder => "H",
+    }
+}
+
+/// Rewrites a connector as it appears under the active render modifiers.
+fn apply_render_modifiers_to_connector(
+    connector: Connector,
+    render_modifiers: &HashSet<RenderModifier>,
+) -> Connector {
+    if !render_modifiers.contains(&RenderModifier::GravityInverse) {
+        return connector;
+    }
+
+    match connector {
+        Connector::StairsUp => Connector::StairsDown,
+        Connector::StairsDown => Connector::StairsUp,
+        Connector::Ladder => Connector::Ladder,
+    }
+}
+
+/// Rewrites a block as it appears under the active render modifiers.
+fn apply_render_modifiers(fill: BlockFill, render_modifiers: &HashSet<RenderModifier>) -> BlockFill {
+    if !render_modifiers.contains(&RenderModifier::GravityInverse) {
+        return fill;
+    }
+
+    match fill {
+        BlockFill::Floor(m_id) => BlockFill::Ceiling(m_id),
+        BlockFill::Ceiling(m_id) => BlockFill::Floor(m_id),
+        BlockFill::FloorCeiling(floor_id, ceiling_id) => BlockFill::FloorCeiling(ceiling_id, floor_id),
+        BlockFill::Solid(_) | BlockFill::Empty => fill,
+    }
+}
+
+fn gen_gfx_region(
+    region: &Region,
+    below: Option<&Region>,
+    visibility: &[Visibility],
+    light: &[[f32; 3]],
+    styler: &mut TileStyler,
+    render_modifiers: &HashSet<RenderModifier>,
+) -> GfxRegion {
+    if region.blocks.len() != REGION_LEN {
+        log::error!(
+            "Region has {} blocks instead of {}; drawing placeholders for the missing blocks",
+            region.blocks.len(), REGION_LEN,
+        );
+    }
+
+    let mut tiles = Vec::with_capacity(REGION_LEN);
+    for idx in 0..REGION_LEN {
+        let visibility = visibility.get(idx).copied().unwrap_or(Visibility::Visible);
+        if visibility == Visibility::Unknown {
+            tiles.push(unknown());
+            continue;
         }
+
+        let block = match region.blocks.get(idx) {
+            Some(block) => block,
+            None => {
+                tiles.push(styler.placeholder());
+                continue;
+            },
+        };
+
+        let fill = apply_render_modifiers(block.fill, render_modifiers);
+        let below = below.and_then(|below| below.blocks.get(idx))
+            .map(|b| apply_render_modifiers(b.fill, render_modifiers));
+        let mut tile = styler.tile(fill, below);
+
+        if let Some(connector) = block.connector {
+            let connector = apply_render_modifiers_to_connector(connector, render_modifiers);
+            tile.glyph = GfxGlyph::new(connector_glyph(connector));
+            tile.fg = CONNECTOR_FG;
+        }
+
+        // Memories are drawn as they were, so only what is in view is lit. Revealed maps are
+        // drawn unlit, or everything out of reach of a light would be black.
+        match visibility {
+            Visibility::Remembered => tile = remembered(tile),
+            _ if render_modifiers.contains(&RenderModifier::RevealAll) => {},
+            _ => if let Some(&level) = light.get(idx) {
+                tile = lit(tile, level);
+            },
+        }
+
+        tiles.push(tile);
     }
 
     GfxRegion { tiles }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gfx::surface::GlyphSurface;
+    use crate::gfx::text_grid::TextGrid;
+    use crate::state::world::testing;
+
+    /// Where the golden frames for this file live, next to it.
+    fn golden_path(name: &str) -> std::path::PathBuf {
+        std::path::Path::new(file!()).with_file_name("golden").join(name)
+    }
+
+    #[test]
+    fn player_view_matches_golden() {
+        let (mut world, position) = testing::world_with_player();
+
+        let mut world_renderer = WorldRenderer::new(world.id);
+        world_renderer.camera_mut().tiles_dims = (48, 20);
+        world_renderer.camera_mut().center_on(position);
+
+        let mut screen = GfxScreen::new(48, 20);
+        world_renderer.capture(&mut world, &mut screen);
+
+        let mut grid = TextGrid::new();
+        grid.draw(&screen).unwrap();
+        if let Err(e) = grid.check_golden(golden_path("player_view.txt")) {
+            panic!("{}", e);
+        }
+    }
+
+    /// Renders `region` over `below`, fully in view and lit, under `render_modifiers`.
+    fn render(
+        region: &Region,
+        below: &Region,
+        render_modifiers: &HashSet<RenderModifier>,
+    ) -> Vec<GfxTile> {
+        let materials = MaterialRegistry::default();
+        let mut unknown_materials = HashSet::new();
+        let mut styler = TileStyler {
+            materials: &materials,
+            unknown_materials: &mut unknown_materials,
+        };
+
+        let visibility = vec![Visibility::Visible; REGION_LEN];
+        let light = vec![[1.0; 3]; REGION_LEN];
+        let below = Some(below);
+        gen_gfx_region(region, below, &visibility, &light, &mut styler, render_modifiers).tiles
+    }
+
+    #[test]
+    fn gravity_inverse_swaps_floors_and_ceilings() {
+        let mut region = Region::filled(BlockFill::Floor(MaterialId::STONE));
+        region.blocks[0].connector = Some(Connector::StairsUp);
+        region.blocks[1].fill = BlockFill::Ceiling(MaterialId::WOOD);
+        let below = Region::filled(BlockFill::Floor(MaterialId::DIRT));
+
+        let gravity_inverse = [RenderModifier::GravityInverse].iter().copied().collect();
+        let normal = render(&region, &below, &HashSet::new());
+        let inverted = render(&region, &below, &gravity_inverse);
+
+        let materials = MaterialRegistry::default();
+        let mut unknown_materials = HashSet::new();
+        let mut styler = TileStyler {
+            materials: &materials,
+            unknown_materials: &mut unknown_materials,
+        };
+
+        // The way up is the way down once the world is upside down.
+        assert_eq!(normal[0].glyph.glyph, "<");
+        assert_eq!(inverted[0].glyph.glyph, ">");
+        assert_eq!(inverted[0].fg, CONNECTOR_FG);
+
+        // The floor becomes an overhang over the dirt, and the ceiling something to stand on.
+        assert_eq!(normal[2], styler.surface(MaterialId::STONE, 1.0));
+        assert_eq!(inverted[2], GfxTile {
+            bg: dim(materials.get(MaterialId::STONE).bg, OVERHANG_DIM),
+            ..styler.surface(MaterialId::DIRT, BELOW_DIM)
+        });
+        assert_eq!(normal[1], GfxTile {
+            bg: dim(materials.get(MaterialId::WOOD).bg, OVERHANG_DIM),
+            ..styler.surface(MaterialId::DIRT, BELOW_DIM)
+        });
+        assert_eq!(inverted[1], styler.surface(MaterialId::WOOD, 1.0));
+    }
+}
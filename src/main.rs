@@ -1,5 +1,7 @@
 #![allow(dead_code)]
 
+use std::path::Path;
+
 use winit::{
     event,
     event::{Event, WindowEvent},
@@ -7,20 +9,252 @@ use winit::{
     window::Window,
 };
 
+mod game;
 mod gfx;
 mod state;
 mod util;
 
+use game::{Flow, Game, Key};
 use gfx::prelude::*;
+use state::item::ItemTemplate;
+use state::material::MaterialRegistry;
+use state::monster::MonsterTemplate;
+use state::world::{RegionStore, World, DEFAULT_REGION_BUDGET};
 
-async fn run(event_loop: EventLoop<()>, window: Window) {
-    env_logger::init();
+/// Where the game is drawn and where its keys come from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Backend {
+    /// A window drawn with the GPU.
+    Window,
+    /// The terminal the game was started from, drawn with ANSI escape codes.
+    Terminal,
+}
+
+/// What was asked for on the command line.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Command {
+    /// Play the game.
+    Play(Backend),
+    /// Save a picture of the world around the player as a PNG, without opening a window.
+    Render(RenderOptions),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct RenderOptions {
+    path: std::path::PathBuf,
+    /// The size of the picture in tiles.
+    columns: u32,
+    rows: u32,
+    /// The tile to center on, instead of the player.
+    at: Option<(i32, i32)>,
+    /// The level to look at, instead of the player's.
+    level: Option<i32>,
+    /// Draws the whole map rather than only what the player can see.
+    reveal: bool,
+    /// The world seed, instead of `ROGUELIKE_SEED` or a random one.
+    seed: Option<u64>,
+}
+
+impl Command {
+    /// Reads the command from `args`, which should not include the program name.
+    fn parse(args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut args = args.peekable();
+        if args.peek().map(|arg| &**arg) == Some("render") {
+            args.next();
+            return Self::parse_render(args);
+        }
+
+        let mut backend = Backend::Window;
+        while let Some(arg) = args.next() {
+            match &*arg {
+                "--backend" => {
+                    backend = match args.next().as_deref() {
+                        Some("window") => Backend::Window,
+                        Some("terminal") => Backend::Terminal,
+                        Some(other) => return Err(format!("unknown backend {:?}", other)),
+                        None => return Err("--backend needs a value".to_string()),
+                    };
+                },
+                other => return Err(format!("unexpected argument {:?}", other)),
+            }
+        }
+
+        Ok(Command::Play(backend))
+    }
+
+    fn parse_render(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut path = None;
+        let mut options = RenderOptions {
+            path: Default::default(),
+            columns: SCREEN_COLUMNS,
+            rows: SCREEN_ROWS,
+            at: None,
+            level: None,
+            reveal: false,
+            seed: None,
+        };
+
+        while let Some(arg) = args.next() {
+            match &*arg {
+                "--columns" => options.columns = parse_value(&arg, args.next())?,
+                "--rows" => options.rows = parse_value(&arg, args.next())?,
+                "--at" => options.at = Some(parse_position(&arg, args.next())?),
+                "--level" => options.level = Some(parse_value(&arg, args.next())?),
+                "--reveal" => options.reveal = true,
+                "--seed" => options.seed = Some(parse_value(&arg, args.next())?),
+                other if other.starts_with("--") || path.is_some() =>
+                    return Err(format!("unexpected argument {:?}", other)),
+                other => path = Some(std::path::PathBuf::from(other)),
+            }
+        }
+
+        let path = path.ok_or_else(|| "render needs a path to save the picture to".to_string())?;
+        options.path = path;
+        Ok(Command::Render(options))
+    }
+}
+
+/// Parses the value given after `flag`.
+fn parse_value<T: std::str::FromStr>(flag: &str, value: Option<String>) -> Result<T, String> {
+    let value = value.ok_or_else(|| format!("{} needs a value", flag))?;
+    value.parse().map_err(|_| format!("invalid value {:?} for {}", value, flag))
+}
+
+/// Parses a tile position given after `flag` as `X,Y`.
+fn parse_position(flag: &str, value: Option<String>) -> Result<(i32, i32), String> {
+    let value = value.ok_or_else(|| format!("{} needs a value", flag))?;
+    let mut parts = value.split(',').map(|part| part.trim().parse::<i32>());
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(Ok(x)), Some(Ok(y)), None) => Ok((x, y)),
+        _ => Err(format!("invalid position {:?} for {}; expected X,Y", value, flag)),
+    }
+}
 
-    let mut world = state::world::World::new();
+const USAGE: &str = "\
+usage: roguelike_experiment [--backend <window|terminal>]
+       roguelike_experiment render <path.png> [--columns N] [--rows N] [--at X,Y] [--level Z]
+                                              [--reveal] [--seed N]
 
+The world is picked with ROGUELIKE_SEED and ROGUELIKE_GENERATOR, as when playing; --seed
+overrides ROGUELIKE_SEED. Pictures are centered on the player unless --at is given.";
+
+/// Where the game is saved on quit and resumed from on start.
+const SAVE_PATH: &str = "save/world.sav";
+/// Where regions are evicted to while playing. The save refers to it, so it belongs to the save.
+const REGION_STORE_PATH: &str = "save/regions";
+
+/// Resumes the saved game if there is one, or generates a new world.
+fn load_or_make_world() -> World {
+    let path = Path::new(SAVE_PATH);
+    if !path.exists() {
+        return new_game();
+    }
+
+    match World::load(path, make_generator(), load_materials()) {
+        Ok(world) => {
+            log::info!("Resumed the game saved in {}", path.display());
+            world
+        },
+        Err(e) => {
+            // Keep the save out of the way rather than overwriting it with the new game on quit.
+            let unreadable = path.with_extension("sav.unreadable");
+            log::error!(
+                "Failed to load {} ({}); moved it to {} and started a new world",
+                path.display(), e, unreadable.display(),
+            );
+            if let Err(e) = std::fs::rename(path, &unreadable) {
+                log::error!("Failed to move {}: {}", path.display(), e);
+            }
+            new_game()
+        },
+    }
+}
+
+/// Generates a new world for playing, evicting regions to an emptied region store.
+fn new_game() -> World {
+    let world = make_world(None);
+    let store = RegionStore::open(REGION_STORE_PATH).and_then(|store| {
+        store.clear()?;
+        Ok(store)
+    });
+
+    match store {
+        Ok(store) => world.with_region_store(store, DEFAULT_REGION_BUDGET),
+        Err(e) => {
+            log::error!("Failed to open the region store in {}: {}", REGION_STORE_PATH, e);
+            world
+        },
+    }
+}
+
+/// Saves the game so that it resumes on the next start. Once the player has died the game is
+/// over, so the save is removed instead.
+fn save_world(world: &World) {
+    let path = Path::new(SAVE_PATH);
+
+    if world.is_player_dead() {
+        match std::fs::remove_file(path) {
+            Ok(()) => log::info!("The player died; removed {}", path.display()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {},
+            Err(e) => log::error!("Failed to remove {}: {}", path.display(), e),
+        }
+        match std::fs::remove_dir_all(REGION_STORE_PATH) {
+            Ok(()) => {},
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {},
+            Err(e) => log::error!("Failed to remove {}: {}", REGION_STORE_PATH, e),
+        }
+        return;
+    }
+
+    if let Some(dir) = path.parent() {
+        if let Err(e) = std::fs::create_dir_all(dir) {
+            log::error!("Failed to create {}: {}", dir.display(), e);
+            return;
+        }
+    }
+    match world.save(path) {
+        Ok(()) => log::info!("Saved the game to {}", path.display()),
+        Err(e) => log::error!("Failed to save the game to {}: {}", path.display(), e),
+    }
+}
+
+/// Generates the world and fills it with the player, monsters and items. Without a `seed`, the
+/// seed comes from `ROGUELIKE_SEED`, or is picked at random.
+fn make_world(seed: Option<u64>) -> World {
+    // Log the seed so that any world can be reproduced by passing it back in.
+    let seed = seed.unwrap_or_else(|| match std::env::var("ROGUELIKE_SEED") {
+        Ok(seed) => seed.parse().expect("ROGUELIKE_SEED must be an unsigned 64-bit integer"),
+        Err(_) => rand::random(),
+    });
+    log::info!("World seed: {}", seed);
+
+    let mut world = World::new(make_generator(), seed).with_materials(load_materials());
+    match world.spawn_player((0, 0, 0)) {
+        Some(position) => log::info!("Player spawned at {:?}", position),
+        None => log::warn!("Found nowhere to spawn the player"),
+    }
+    if let Some(position) = world.player_position() {
+        let templates = [MonsterTemplate::rat(), MonsterTemplate::goblin(), MonsterTemplate::orc()];
+        let monsters = world.populate(&templates, MONSTER_COUNT, position, MONSTER_SPREAD);
+        log::info!("Spawned {} monsters", monsters.len());
+
+        let templates = [
+            ItemTemplate::dagger(), ItemTemplate::sword(), ItemTemplate::shield(),
+            ItemTemplate::helmet(), ItemTemplate::leather_armour(), ItemTemplate::boots(),
+            ItemTemplate::gold(),
+        ];
+        let items =
+            world.scatter_items(&templates, ITEM_COUNT, GOLD_PILE, position, MONSTER_SPREAD);
+        log::info!("Scattered {} items", items.len());
+    }
+
+    world
+}
+
+async fn run_window(event_loop: EventLoop<()>, window: Window, world: World) {
     // Initialize the gfx context.
     let mut gfx_context = GfxContext::create(&window).await.unwrap();
-    let mut world_renderer = WorldRenderer::new(world.id);
+    let mut game = Game::new(world, SCREEN_COLUMNS, SCREEN_ROWS);
 
     // Start focused by default, assuming the application was executed with the intention of using
     // it straight away.
@@ -28,19 +262,38 @@ async fn run(event_loop: EventLoop<()>, window: Window) {
 
     event_loop.run(move |event, _, control_flow| {
         *control_flow = ControlFlow::Poll;
+
+        // Keys that type characters arrive as `ReceivedCharacter`, so that they follow the keyboard
+        // layout; everything else is matched on the key itself.
+        let key = match event {
+            Event::WindowEvent { event: WindowEvent::ReceivedCharacter(c), .. }
+                if !c.is_control() => Some(Key::Char(c)),
+            Event::WindowEvent { event: WindowEvent::KeyboardInput { input: event::KeyboardInput {
+                virtual_keycode: Some(key),
+                state: event::ElementState::Pressed, ..
+            }, .. }, .. } => key_for_virtual_keycode(key),
+            _ => None,
+        };
+        if let Some(key) = key {
+            if game.handle_key(key) == Flow::Quit {
+                *control_flow = ControlFlow::Exit;
+                save_world(&game.world);
+
+                window.set_cursor_grab(false).unwrap();
+                window.set_cursor_visible(true);
+            }
+            return;
+        }
+
         match event {
             Event::MainEventsCleared => window.request_redraw(),
-            Event::RedrawRequested(_) =>
-                gfx_context.render(&mut world, &mut world_renderer),
+            Event::RedrawRequested(_) => gfx_context.draw(game.capture()).unwrap(),
             Event::WindowEvent { event: WindowEvent::Resized(size), .. } =>
                 gfx_context.resize(size),
             // Handle requests to close the window...
-            Event::WindowEvent { event: WindowEvent::CloseRequested, .. } |
-            Event::WindowEvent { event: WindowEvent::KeyboardInput { input: event::KeyboardInput {
-                virtual_keycode: Some(event::VirtualKeyCode::Escape),
-                state: event::ElementState::Pressed, ..
-            }, .. }, .. } => {
+            Event::WindowEvent { event: WindowEvent::CloseRequested, .. } => {
                 *control_flow = ControlFlow::Exit;
+                save_world(&game.world);
 
                 window.set_cursor_grab(false).unwrap();
                 window.set_cursor_visible(true);
@@ -66,9 +319,161 @@ async fn run(event_loop: EventLoop<()>, window: Window) {
     });
 }
 
+/// Maps the keys that do not type a character. The numpad is told apart from the digit row so
+/// that it can move the player.
+fn key_for_virtual_keycode(key: event::VirtualKeyCode) -> Option<Key> {
+    use event::VirtualKeyCode::*;
+
+    Some(match key {
+        Up => Key::Up,
+        Down => Key::Down,
+        Left => Key::Left,
+        Right => Key::Right,
+        PageUp => Key::PageUp,
+        PageDown => Key::PageDown,
+        Escape => Key::Escape,
+        Numpad1 => Key::Numpad(1),
+        Numpad2 => Key::Numpad(2),
+        Numpad3 => Key::Numpad(3),
+        Numpad4 => Key::Numpad(4),
+        Numpad5 => Key::Numpad(5),
+        Numpad6 => Key::Numpad(6),
+        Numpad7 => Key::Numpad(7),
+        Numpad8 => Key::Numpad(8),
+        Numpad9 => Key::Numpad(9),
+        _ => return None,
+    })
+}
+
+fn run_terminal(world: World) -> std::io::Result<()> {
+    let mut terminal = TerminalContext::create()?;
+    let (columns, rows) = terminal.size()?;
+    let mut game = Game::new(world, columns, rows);
+
+    // Save however the game ends, so that a broken terminal does not cost the player their game.
+    let result = play_in_terminal(&mut terminal, &mut game);
+    save_world(&game.world);
+    result
+}
+
+fn play_in_terminal(terminal: &mut TerminalContext, game: &mut Game) -> std::io::Result<()> {
+    loop {
+        terminal.draw(game.capture())?;
+
+        match terminal.next_input()? {
+            TerminalInput::Key(key) => {
+                if game.handle_key(key) == Flow::Quit {
+                    return Ok(());
+                }
+            },
+            TerminalInput::Resized(columns, rows) => game.resize(columns, rows),
+        }
+    }
+}
+
+/// Saves a picture of the world around the player, or around where the player would be if they
+/// could not be spawned.
+fn render_png(mut world: World, options: &RenderOptions) -> std::io::Result<()> {
+    let (player_x, player_y, z) = world.player_position().unwrap_or((0, 0, 0));
+    let (x, y) = options.at.unwrap_or((player_x, player_y));
+
+    let mut camera = Camera {
+        world_offset: (0, 0, 0),
+        region_offset: (0, 0),
+        tiles_dims: (options.columns, options.rows),
+    };
+    camera.center_on((x, y, options.level.unwrap_or(z)));
+
+    gfx::raster::save_world_png(&mut world, camera, options.reveal, &options.path)
+}
+
+/// The size of the window in glyphs.
+// TODO: Assuming glyph size of (10, 20) and window size of (1280, 720).
+const SCREEN_COLUMNS: u32 = 128;
+const SCREEN_ROWS: u32 = 36;
+
+/// How many monsters are scattered around the player at the start, and how far away they may be.
+const MONSTER_COUNT: u32 = 12;
+const MONSTER_SPREAD: i32 = 40;
+
+/// How many items are scattered around the player at the start, and the most gold in one pile.
+const ITEM_COUNT: u32 = 16;
+const GOLD_PILE: u32 = 25;
+
+/// The number of z-levels in generated dungeons.
+const DUNGEON_DEPTH: u32 = 8;
+
+/// Reads the material registry from `resources/materials.ron`, so that materials can be tweaked
+/// without a rebuild. Falls back to the copy built into the binary if the file is missing or bad.
+fn load_materials() -> MaterialRegistry {
+    MaterialRegistry::load("resources/materials.ron").unwrap_or_else(|e| {
+        log::warn!("Using the built-in materials: {}", e);
+        MaterialRegistry::default()
+    })
+}
+
+/// Picks the map generator named by `ROGUELIKE_GENERATOR` (`bsp`, `cave`, `overworld` or `rooms`),
+/// defaulting to `bsp`.
+fn make_generator() -> Box<dyn state::world::WorldGenerator> {
+    use state::world::gen::*;
+
+    // TODO: Assuming glyph size of (10, 20) and window size of (1280, 720).
+    let (width, height) = (128, 36);
+
+    match std::env::var("ROGUELIKE_GENERATOR").as_deref() {
+        Ok("cave") => {
+            let params = CaveParams::load("resources/caves.ron").unwrap_or_else(|e| {
+                log::warn!("Using default cave parameters: {}", e);
+                CaveParams::default()
+            });
+            let generator = CaveGenerator::new(width, height, params);
+            Box::new(BoundedGenerator::new(generator).with_depth(DUNGEON_DEPTH))
+        },
+        Ok("overworld") => Box::new(OverworldGenerator::default()),
+        Ok("rooms") => {
+            let generator = RoomsGenerator::new(width, height);
+            Box::new(BoundedGenerator::new(generator).with_depth(DUNGEON_DEPTH))
+        },
+        Ok("bsp") | Err(_) => {
+            let generator = BspGenerator::new(width, height);
+            Box::new(BoundedGenerator::new(generator).with_depth(DUNGEON_DEPTH))
+        },
+        Ok(other) => {
+            log::warn!("Unknown generator {:?}, falling back to bsp", other);
+            let generator = BspGenerator::new(width, height);
+            Box::new(BoundedGenerator::new(generator).with_depth(DUNGEON_DEPTH))
+        },
+    }
+}
+
 fn main() {
-    let event_loop = EventLoop::new();
-    let window = winit::window::Window::new(&event_loop).unwrap();
-    window.set_inner_size(winit::dpi::PhysicalSize::new(1280, 720));
-    futures::executor::block_on(run(event_loop, window));
+    env_logger::init();
+
+    let command = Command::parse(std::env::args().skip(1)).unwrap_or_else(|e| {
+        eprintln!("{}\n{}", e, USAGE);
+        std::process::exit(2);
+    });
+
+    match command {
+        Command::Play(Backend::Window) => {
+            let world = load_or_make_world();
+            let event_loop = EventLoop::new();
+            let window = winit::window::Window::new(&event_loop).unwrap();
+            window.set_inner_size(winit::dpi::PhysicalSize::new(1280, 720));
+            futures::executor::block_on(run_window(event_loop, window, world));
+        },
+        Command::Play(Backend::Terminal) => {
+            if let Err(e) = run_terminal(load_or_make_world()) {
+                eprintln!("Terminal error: {}", e);
+                std::process::exit(1);
+            }
+        },
+        Command::Render(options) => {
+            if let Err(e) = render_png(make_world(options.seed), &options) {
+                eprintln!("Failed to save {}: {}", options.path.display(), e);
+                std::process::exit(1);
+            }
+            log::info!("Saved a picture of the world to {}", options.path.display());
+        },
+    }
 }
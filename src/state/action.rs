@@ -0,0 +1,133 @@
+//! Everything an actor can do on its turn. The player and monsters both act through
+//! `World::perform`, so the rules are the same whoever is acting.
+
+use crate::state::ecs::EntityId;
+use crate::state::item::EquipSlot;
+use crate::state::scheduler::{Actor, TICKS_PER_TURN};
+use crate::state::world::{Direction, Vertical, World};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// Step to a neighbouring tile on the same level. Stepping into something hostile attacks it
+    /// instead.
+    Move(Direction),
+    /// Attack whatever is on a neighbouring tile, without moving if there is nothing there.
+    Attack(Direction),
+    /// Shoot at a tile. The shot hits the first thing in its way.
+    Fire((i32, i32, i32)),
+    /// Take the stairs or ladder underfoot.
+    Climb(Vertical),
+    /// Dig through the solid block on a neighbouring tile, leaving a floor of the same material.
+    Dig(Direction),
+    /// Pick up everything underfoot.
+    PickUp,
+    /// Put a carried item down underfoot.
+    Drop(EntityId),
+    /// Wield or put on a carried item.
+    Equip(EntityId),
+    /// Stop wielding or take off whatever is in a slot.
+    Unequip(EquipSlot),
+    /// Do nothing for a moment.
+    Wait,
+}
+
+/// How much longer a diagonal step takes than a straight one, in thousandths. Roughly the square
+/// root of two, so that walking diagonally is no faster than walking around a corner.
+const DIAGONAL_COST_PER_MILLE: u64 = 1414;
+
+impl Action {
+    /// The ticks a normal-speed actor spends on this action. A plain step or a swing takes a turn;
+    /// reaching for things is quicker, and shooting or climbing slower. Digging depends on what is
+    /// dug through, so `World::perform` charges it by the material instead.
+    pub fn base_cost(self) -> u64 {
+        match self {
+            Action::Move(direction) if direction.is_diagonal() => {
+                TICKS_PER_TURN * DIAGONAL_COST_PER_MILLE / 1000
+            },
+            Action::Move(_) => TICKS_PER_TURN,
+            Action::Attack(_) => TICKS_PER_TURN,
+            Action::Fire(_) => TICKS_PER_TURN * 3 / 2,
+            Action::Climb(_) => TICKS_PER_TURN * 2,
+            Action::Dig(_) => TICKS_PER_TURN,
+            Action::PickUp => TICKS_PER_TURN / 2,
+            Action::Drop(_) => TICKS_PER_TURN / 2,
+            Action::Equip(_) => TICKS_PER_TURN,
+            Action::Unequip(_) => TICKS_PER_TURN,
+            Action::Wait => TICKS_PER_TURN / 2,
+        }
+    }
+}
+
+impl World {
+    /// Carries out `action` for `entity`. Returns the number of ticks it took, or `None` if the
+    /// action could not be carried out, in which case no time passes.
+    pub fn perform(&mut self, entity: EntityId, action: Action) -> Option<u64> {
+        // The block is gone once it has been dug through, so find out how hard it was first.
+        let base_cost = match action {
+            Action::Dig(direction) => self.dig_cost(entity, direction),
+            _ => action.base_cost(),
+        };
+
+        let done = match action {
+            Action::Move(direction) => {
+                let target = self.entities.position(entity).map(|pos| direction.step(pos));
+                match target {
+                    Some(target) if self.hostile_at(entity, target).is_some() => {
+                        self.melee(entity, target)
+                    },
+                    _ => self.move_entity(entity, direction),
+                }
+            },
+            Action::Attack(direction) => match self.entities.position(entity) {
+                Some(position) => self.melee(entity, direction.step(position)),
+                None => false,
+            },
+            Action::Fire(target) => self.fire(entity, target),
+            Action::Climb(direction) => self.climb_entity(entity, direction),
+            Action::Dig(direction) => self.dig(entity, direction),
+            Action::PickUp => self.pick_up(entity),
+            Action::Drop(item) => self.drop_item(entity, item),
+            Action::Equip(item) => self.equip(entity, item),
+            Action::Unequip(slot) => self.unequip(entity, slot),
+            Action::Wait => true,
+        };
+
+        if !done {
+            return None;
+        }
+
+        let actor = self.entities.get::<Actor>(entity).copied().unwrap_or_default();
+        Some(actor.cost(base_cost))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::event::Event;
+    use crate::state::world::{testing, BlockFill};
+
+    #[test]
+    fn digging_takes_as_long_as_the_material_is_hard() {
+        let (mut world, position) = testing::world_with_player();
+        let player = world.player.unwrap();
+
+        let (direction, target, material) = Direction::ALL.iter()
+            .map(|&direction| (direction, direction.step(position)))
+            .find_map(|(direction, target)| match world.block_at(target).fill {
+                BlockFill::Solid(material) => Some((direction, target, material)),
+                _ => None,
+            })
+            .expect("rooms have walls");
+        let hardness = world.materials.get(material).hardness as u64;
+
+        let cost = world.perform(player, Action::Dig(direction));
+        assert_eq!(cost, Some(hardness * TICKS_PER_TURN / 10));
+        assert_eq!(world.block_at(target).fill, BlockFill::Floor(material));
+        assert!(matches!(world.take_events()[..], [Event::Dug { .. }]));
+
+        // There is nothing left to dig through.
+        assert_eq!(world.perform(player, Action::Dig(direction)), None);
+        assert!(world.can_enter(target));
+    }
+}
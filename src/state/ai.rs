@@ -0,0 +1,314 @@
+//! Monster behaviour. Every monster runs a small state machine: it idles or wanders around its home
+//! until it sees the player, hunts the player while it can see them (and for a while after, at the
+//! place they were last seen), runs when badly hurt, and otherwise makes its way back home.
+//!
+//! Monsters act through `World::perform` like the player does. Their random choices come from an
+//! rng derived from the world seed, the monster and the current tick, so a recorded game replays
+//! exactly. Saves keep every monster's state and the turn order, so this holds across a save and
+//! load too.
+
+use rand::Rng;
+
+use crate::state::action::Action;
+use crate::state::combat::{bresenham_line, RangedAttack};
+use crate::state::ecs::{EntityId, Health};
+use crate::state::fov::Vision;
+use crate::state::path::MoveCosts;
+use crate::state::world::{BlockFill, Direction, Vertical, World};
+
+/// Salt for the rng behind the choices monsters make.
+const AI_SALT: u64 = 0x4149_4149;
+
+/// How many tiles a path search may explore before a monster gives up on reaching somewhere.
+const PATH_LIMIT: usize = 2048;
+
+/// How far a fleeing monster looks for somewhere to run to, in move cost.
+const FLEE_RANGE: i64 = 2000;
+
+/// How strongly fleeing monsters prefer distance over open space. See `World::flee_map`.
+const FLEE_FACTOR: f32 = 1.2;
+
+/// How many turns a hunting monster keeps looking for a target it has lost sight of, in case it
+/// cannot get to where the target was last seen.
+const HUNT_PATIENCE: u32 = 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum AiState {
+    /// Standing around at home.
+    Idle,
+    /// Ambling about near home.
+    Wander,
+    /// Chasing `target`, who was last seen at `last_seen`. The monster gives up once it gets
+    /// there, or after `patience` more turns without seeing the target.
+    Hunt {
+        target: EntityId,
+        last_seen: (i32, i32, i32),
+        #[serde(default)]
+        patience: u32,
+    },
+    /// Running away from `from`.
+    Flee {
+        from: EntityId,
+    },
+    /// Heading back home.
+    ReturnHome,
+}
+
+/// Makes an entity act on its own.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Ai {
+    pub state: AiState,
+    /// Where the monster idles and wanders around, and returns to once it loses interest.
+    pub home: (i32, i32, i32),
+    /// How far from home the monster wanders before heading back.
+    pub wander_radius: i32,
+    /// The monster flees once its health drops to this percentage of its maximum.
+    pub flee_below: i32,
+}
+
+impl Ai {
+    pub fn new(home: (i32, i32, i32)) -> Self {
+        Self {
+            state: AiState::Idle,
+            home,
+            wander_radius: 6,
+            flee_below: 25,
+        }
+    }
+}
+
+/// The action that takes something at `from` one step along a path to `next`.
+fn step_towards(from: (i32, i32, i32), next: (i32, i32, i32)) -> Action {
+    let delta = ((next.0 - from.0).signum(), (next.1 - from.1).signum());
+    if delta == (0, 0) {
+        let direction = if next.2 > from.2 { Vertical::Up } else { Vertical::Down };
+        return Action::Climb(direction);
+    }
+
+    Direction::ALL.iter()
+        .find(|direction| direction.delta() == delta)
+        .map_or(Action::Wait, |&direction| Action::Move(direction))
+}
+
+impl World {
+    /// Picks the action an entity with an `Ai` takes on its turn, updating its state on the way.
+    /// Entities without one wait.
+    pub fn decide_ai(&mut self, entity: EntityId) -> Action {
+        let ai = self.entities.get::<Ai>(entity).copied();
+        let (mut ai, position) = match (ai, self.entities.position(entity)) {
+            (Some(ai), Some(position)) => (ai, position),
+            _ => return Action::Wait,
+        };
+        let mut rng = self.entity_rng(AI_SALT, entity);
+
+        // Perception. Monsters are only interested in the player.
+        let radius = self.entities.get::<Vision>(entity).map_or(0, |vision| vision.radius);
+        let seen_player = match self.player.and_then(|p| Some((p, self.entities.position(p)?))) {
+            Some((player, player_position)) if player_position.2 == position.2
+                && self.field_of_view(position, radius).contains(&player_position) =>
+            {
+                Some((player, player_position))
+            },
+            _ => None,
+        };
+
+        let hurt = self.entities.get::<Health>(entity)
+            .map_or(false, |health| health.is_below(ai.flee_below));
+
+        ai.state = match (ai.state, seen_player) {
+            (_, Some((player, _))) if hurt => AiState::Flee { from: player },
+            (_, Some((player, last_seen))) => {
+                AiState::Hunt { target: player, last_seen, patience: HUNT_PATIENCE }
+            },
+            (AiState::Hunt { last_seen, .. }, None) if last_seen == position => AiState::ReturnHome,
+            (AiState::Hunt { patience: 0, .. }, None) => AiState::ReturnHome,
+            (AiState::Hunt { target, last_seen, patience }, None) => {
+                AiState::Hunt { target, last_seen, patience: patience - 1 }
+            },
+            (AiState::Flee { .. }, None) => AiState::ReturnHome,
+            (AiState::ReturnHome, None) if position == ai.home => AiState::Idle,
+            (AiState::Idle, None) if rng.gen_bool(0.25) => AiState::Wander,
+            (AiState::Wander, None) if rng.gen_bool(0.125) => AiState::Idle,
+            (AiState::Wander, None) if distance(position, ai.home) > ai.wander_radius => {
+                AiState::ReturnHome
+            },
+            (state, None) => state,
+        };
+
+        if let Some(stored) = self.entities.get_mut::<Ai>(entity) {
+            *stored = ai;
+        }
+
+        let costs = MoveCosts::default();
+        match ai.state {
+            AiState::Idle => Action::Wait,
+            AiState::Wander => {
+                let direction = Direction::ALL[rng.gen_range(0, Direction::ALL.len())];
+                Action::Move(direction)
+            },
+            AiState::Hunt { target, last_seen, .. } => {
+                let target_here = self.entities.position(target) == Some(last_seen);
+                if target_here && distance(position, last_seen) <= 1 {
+                    // Moving into the target attacks it.
+                    return step_towards(position, last_seen);
+                }
+                if target_here && self.has_line_of_fire(entity, position, last_seen) {
+                    return Action::Fire(last_seen);
+                }
+
+                self.action_towards(position, last_seen, &costs)
+            },
+            AiState::Flee { from } => {
+                let threat = match self.entities.position(from) {
+                    Some(threat) => threat,
+                    None => return Action::Wait,
+                };
+
+                let approach = self.dijkstra_map(&[threat], &costs, FLEE_RANGE);
+                let flee = self.flee_map(&approach, &costs, FLEE_FACTOR);
+                match self.downhill(&flee, position) {
+                    Some(next) => step_towards(position, next),
+                    None => Action::Wait,
+                }
+            },
+            AiState::ReturnHome => self.action_towards(position, ai.home, &costs),
+        }
+    }
+
+    /// Whether `entity` at `from` can shoot `to` without a wall or anyone else getting in the way.
+    fn has_line_of_fire(
+        &mut self,
+        entity: EntityId,
+        from: (i32, i32, i32),
+        to: (i32, i32, i32),
+    ) -> bool {
+        let range = match self.entities.get::<RangedAttack>(entity) {
+            Some(ranged) => ranged.range as i32,
+            None => return false,
+        };
+        if from.2 != to.2 || distance(from, to) > range {
+            return false;
+        }
+
+        let mut line = bresenham_line(from, to);
+        line.pop();
+        line.into_iter().all(|pos| {
+            let entities = &self.entities;
+            !entities.at(pos).any(|id| entities.has::<Health>(id))
+                && !matches!(self.block_at(pos).fill, BlockFill::Solid(_))
+        })
+    }
+
+    /// The first step along the cheapest path from `from` to `to`, or a wait if there is no way
+    /// there.
+    fn action_towards(
+        &mut self,
+        from: (i32, i32, i32),
+        to: (i32, i32, i32),
+        costs: &MoveCosts,
+    ) -> Action {
+        match self.find_path(from, to, costs, PATH_LIMIT) {
+            Some(path) if !path.is_empty() => step_towards(from, path[0]),
+            _ => Action::Wait,
+        }
+    }
+}
+
+/// The number of king's moves between two tiles on the same level.
+fn distance(a: (i32, i32, i32), b: (i32, i32, i32)) -> i32 {
+    (a.0 - b.0).abs().max((a.1 - b.1).abs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::monster::MonsterTemplate;
+    use crate::state::world::testing;
+
+    /// A world with the player at the origin of open floor and a rat at home nearby.
+    fn world() -> (World, EntityId, EntityId) {
+        let mut world = testing::flat_world();
+        assert_eq!(world.spawn_player((0, 0, 0)), Some((0, 0, 0)));
+        let player = world.player.unwrap();
+        let rat = world.spawn_monster(&MonsterTemplate::rat(), (4, 0, 0));
+        (world, player, rat)
+    }
+
+    fn state(world: &World, entity: EntityId) -> AiState {
+        world.entities.get::<Ai>(entity).unwrap().state
+    }
+
+    #[test]
+    fn monsters_hunt_what_they_see_then_go_home() {
+        let (mut world, player, rat) = world();
+
+        world.decide_ai(rat);
+        let hunt = AiState::Hunt { target: player, last_seen: (0, 0, 0), patience: HUNT_PATIENCE };
+        assert_eq!(state(&world, rat), hunt);
+
+        // The player slips away, and the rat makes for where it last saw them.
+        world.entities.set_position(player, (40, 0, 0));
+        world.entities.set_position(rat, (1, 0, 0));
+        assert_eq!(world.decide_ai(rat), Action::Move(Direction::West));
+
+        world.entities.set_position(rat, (0, 0, 0));
+        world.decide_ai(rat);
+        assert_eq!(state(&world, rat), AiState::ReturnHome);
+        assert_eq!(world.decide_ai(rat), Action::Move(Direction::East));
+
+        world.entities.set_position(rat, (4, 0, 0));
+        world.decide_ai(rat);
+        assert_eq!(state(&world, rat), AiState::Idle);
+    }
+
+    #[test]
+    fn hunters_give_up_on_places_they_cannot_reach() {
+        let (mut world, player, rat) = world();
+        world.decide_ai(rat);
+        world.entities.set_position(player, (40, 0, 0));
+
+        // However long the rat is kept from moving, it only keeps looking for so long.
+        for _ in 0..HUNT_PATIENCE {
+            world.decide_ai(rat);
+            assert!(matches!(state(&world, rat), AiState::Hunt { .. }));
+        }
+        world.decide_ai(rat);
+        assert_eq!(state(&world, rat), AiState::ReturnHome);
+    }
+
+    #[test]
+    fn badly_hurt_monsters_flee() {
+        let (mut world, player, rat) = world();
+        world.entities.get_mut::<Health>(rat).unwrap().current = 1;
+
+        let action = world.decide_ai(rat);
+        assert_eq!(state(&world, rat), AiState::Flee { from: player });
+        assert!(world.perform(rat, action).is_some());
+        let (x, _, _) = world.entities.position(rat).unwrap();
+        assert!(x > 4);
+
+        world.entities.set_position(player, (-40, 0, 0));
+        world.decide_ai(rat);
+        assert_eq!(state(&world, rat), AiState::ReturnHome);
+    }
+
+    #[test]
+    fn the_same_seed_makes_the_same_decisions() {
+        let play = || {
+            let (mut world, position) = testing::world_with_player();
+            let monsters = [MonsterTemplate::rat(), MonsterTemplate::goblin()];
+            world.populate(&monsters, 8, position, 12);
+            for _ in 0..30 {
+                world.act_player(Action::Wait);
+            }
+
+            world.entities.query::<Ai>()
+                .map(|(id, &ai)| (id, ai, world.entities.position(id)))
+                .collect::<Vec<_>>()
+        };
+
+        let decisions = play();
+        assert!(!decisions.is_empty());
+        assert_eq!(play(), decisions);
+    }
+}
@@ -0,0 +1,450 @@
+//! Fighting. An attack rolls a d20 plus the attacker's bonus against the defender's evasion: a
+//! natural 20 is a critical hit that rolls its damage dice twice, a natural 1 always misses. Damage
+//! is reduced by armour and halved by resistance to its type. Whatever drops to zero health dies,
+//! leaving a corpse behind.
+//!
+//! Ranged attacks fly along a Bresenham line and hit the first thing in the way, which is not
+//! necessarily what was aimed at. Solid blocks stop them.
+//!
+//! Dice come from an rng derived from the world seed, the attacker and the current tick, so fights
+//! replay exactly. Everything that happens is reported through `World::events`.
+
+use std::borrow::Cow;
+
+use rand::Rng;
+
+use crate::state::ecs::{Appearance, EntityId, Health, Name, PlayerControlled};
+use crate::state::event::{AttackOutcome, Event};
+use crate::state::world::{BlockFill, Visibility, World};
+
+/// Salt for the dice rolled in combat.
+const COMBAT_SALT: u64 = 0x4649_4748;
+
+const CORPSE_GLYPH: &str = "%";
+/// How much darker a corpse is than what it was.
+const CORPSE_DIM: f32 = 0.6;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum DamageType {
+    Blunt,
+    Piercing,
+    Slashing,
+    Fire,
+    Cold,
+    Poison,
+}
+
+/// `count`d`sides`+`bonus`, as in 2d6+1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Dice {
+    pub count: u32,
+    pub sides: u32,
+    pub bonus: i32,
+}
+
+impl Dice {
+    pub const fn new(count: u32, sides: u32, bonus: i32) -> Self {
+        Self { count, sides, bonus }
+    }
+
+    pub fn roll(&self, rng: &mut impl Rng) -> i32 {
+        let rolled: u32 = (0..self.count).map(|_| rng.gen_range(1, self.sides.max(1) + 1)).sum();
+        rolled as i32 + self.bonus
+    }
+}
+
+impl std::fmt::Display for Dice {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.bonus {
+            0 => write!(f, "{}d{}", self.count, self.sides),
+            b if b > 0 => write!(f, "{}d{}+{}", self.count, self.sides, b),
+            b => write!(f, "{}d{}{}", self.count, self.sides, b),
+        }
+    }
+}
+
+/// How an entity fights in melee, bare-handed. Wielded weapons change this; see
+/// `World::attack_of`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Attack {
+    pub to_hit: i32,
+    pub damage: Dice,
+    pub damage_type: DamageType,
+}
+
+/// How an entity fights at range.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct RangedAttack {
+    pub to_hit: i32,
+    pub damage: Dice,
+    pub damage_type: DamageType,
+    /// The furthest a shot flies, in tiles.
+    pub range: u32,
+}
+
+/// How hard an entity is to hurt, before armour is worn. See `World::defence_of`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Defence {
+    /// Added to the roll an attacker has to beat.
+    pub evasion: i32,
+    /// Taken off the damage of every hit.
+    pub armour: i32,
+    /// Damage of these types is halved.
+    pub resistances: Vec<DamageType>,
+}
+
+/// Marks the remains of something that died.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Corpse;
+
+/// Returns the tiles on the line from `from` to `to`, excluding `from` and including `to`. The line
+/// stays on the level of `from`.
+pub fn bresenham_line(from: (i32, i32, i32), to: (i32, i32, i32)) -> Vec<(i32, i32, i32)> {
+    let (mut x, mut y, z) = from;
+    let (dx, dy) = ((to.0 - x).abs(), -(to.1 - y).abs());
+    let (sx, sy) = ((to.0 - x).signum(), (to.1 - y).signum());
+    let mut error = dx + dy;
+
+    let mut line = Vec::with_capacity(dx.max(-dy) as usize);
+    while (x, y) != (to.0, to.1) {
+        let doubled = 2 * error;
+        if doubled >= dy {
+            error += dy;
+            x += sx;
+        }
+        if doubled <= dx {
+            error += dx;
+            y += sy;
+        }
+        line.push((x, y, z));
+    }
+
+    line
+}
+
+impl World {
+    /// What is called `entity` in messages.
+    pub fn name_of(&self, entity: EntityId) -> Cow<'static, str> {
+        if self.entities.has::<PlayerControlled>(entity) {
+            return "you".into();
+        }
+
+        self.entities.get::<Name>(entity).map_or("something".into(), |name| name.0.clone())
+    }
+
+    /// Whether `attacker` is willing to attack `target`. The player fights everything; everything
+    /// else only fights the player.
+    pub fn is_hostile(&self, attacker: EntityId, target: EntityId) -> bool {
+        attacker != target
+            && self.entities.has::<Health>(target)
+            && (self.player == Some(attacker) || self.player == Some(target))
+    }
+
+    /// The first thing on the tile at `pos` that `attacker` would attack.
+    pub fn hostile_at(&self, attacker: EntityId, pos: (i32, i32, i32)) -> Option<EntityId> {
+        self.entities.at(pos).find(|&id| self.is_hostile(attacker, id))
+    }
+
+    /// The closest thing the player can see and would attack, for aiming at.
+    pub fn nearest_visible_hostile(&self) -> Option<(i32, i32, i32)> {
+        let player = self.player?;
+        let (x, y, z) = self.entities.position(player)?;
+
+        self.entities.query::<Health>()
+            .filter(|&(id, _)| self.is_hostile(player, id))
+            .filter_map(|(id, _)| self.entities.position(id))
+            .filter(|&pos| pos.2 == z && self.visibility(pos) == Visibility::Visible)
+            .min_by_key(|&(tx, ty, _)| (tx - x).abs().max((ty - y).abs()))
+    }
+
+    /// `attacker` hits out at whatever is on the tile at `pos`. Returns `false` if there is
+    /// nothing there to attack or `attacker` cannot fight.
+    pub fn melee(&mut self, attacker: EntityId, pos: (i32, i32, i32)) -> bool {
+        let attack = match self.attack_of(attacker) {
+            Some(attack) => attack,
+            None => return false,
+        };
+        let target = match self.hostile_at(attacker, pos) {
+            Some(target) => target,
+            None => return false,
+        };
+
+        let Attack { to_hit, damage, damage_type } = attack;
+        self.resolve_attack(attacker, target, to_hit, damage, damage_type, false);
+        true
+    }
+
+    /// `attacker` shoots at the tile at `target`. The shot hits the first creature along the way,
+    /// stops at the first solid block, and otherwise flies out to the attacker's range. Returns
+    /// `false` if `attacker` has no ranged attack, is aiming at itself or is aiming further than
+    /// it can shoot.
+    pub fn fire(&mut self, attacker: EntityId, target: (i32, i32, i32)) -> bool {
+        let ranged = self.entities.get::<RangedAttack>(attacker).copied();
+        let (ranged, origin) = match (ranged, self.entities.position(attacker)) {
+            (Some(ranged), Some(origin)) => (ranged, origin),
+            _ => return false,
+        };
+        let (dx, dy) = (target.0 - origin.0, target.1 - origin.1);
+        let reach = dx.abs().max(dy.abs());
+        if reach == 0 || reach > ranged.range as i32 {
+            return false;
+        }
+
+        // Extend the line past the target so that a shot that misses its mark keeps flying.
+        let scale = (ranged.range as i32 + reach - 1) / reach;
+        let far = (origin.0 + dx * scale, origin.1 + dy * scale, origin.2);
+
+        for pos in bresenham_line(origin, far).into_iter().take(ranged.range as usize) {
+            if let BlockFill::Solid(_) = self.block_at(pos).fill {
+                let attacker_name = self.name_of(attacker);
+                self.events.push(Event::ShotBlocked { attacker: attacker_name, at: pos });
+                return true;
+            }
+
+            let entities = &self.entities;
+            let victim = entities.at(pos).find(|&id| id != attacker && entities.has::<Health>(id));
+            if let Some(victim) = victim {
+                let RangedAttack { to_hit, damage, damage_type, .. } = ranged;
+                self.resolve_attack(attacker, victim, to_hit, damage, damage_type, true);
+                return true;
+            }
+        }
+
+        true
+    }
+
+    fn resolve_attack(
+        &mut self,
+        attacker: EntityId,
+        target: EntityId,
+        to_hit: i32,
+        damage: Dice,
+        damage_type: DamageType,
+        ranged: bool,
+    ) {
+        let mut rng = self.entity_rng(COMBAT_SALT, attacker);
+        let defence = self.defence_of(target);
+
+        let roll = rng.gen_range(1, 21);
+        let outcome = if roll == 1 || (roll != 20 && roll + to_hit < 10 + defence.evasion) {
+            AttackOutcome::Miss
+        } else {
+            let critical = roll == 20;
+            let mut dealt = damage.roll(&mut rng);
+            if critical {
+                dealt += damage.roll(&mut rng);
+            }
+            if defence.resistances.contains(&damage_type) {
+                dealt /= 2;
+            }
+            let dealt = (dealt - defence.armour).max(0);
+
+            AttackOutcome::Hit { damage: dealt, damage_type, critical }
+        };
+
+        self.events.push(Event::Attacked {
+            attacker: self.name_of(attacker),
+            target: self.name_of(target),
+            ranged,
+            outcome,
+        });
+
+        if let AttackOutcome::Hit { damage, .. } = outcome {
+            let dead = match self.entities.get_mut::<Health>(target) {
+                Some(health) => {
+                    health.current -= damage;
+                    health.current <= 0
+                },
+                None => false,
+            };
+
+            if dead {
+                self.kill(target);
+            }
+        }
+    }
+
+    /// Removes `entity` from play, leaving its corpse and everything it carried on its tile. The
+    /// player's entity is kept, so that the game can show where they fell, but loses its health
+    /// and its turns.
+    pub fn kill(&mut self, entity: EntityId) {
+        self.events.push(Event::Died { name: self.name_of(entity) });
+        self.scheduler.unschedule(entity);
+        self.drop_everything(entity);
+
+        if self.player == Some(entity) {
+            self.entities.remove::<Health>(entity);
+            return;
+        }
+
+        if let Some(position) = self.entities.position(entity) {
+            let name = self.entities.get::<Name>(entity).map_or("something", |name| &name.0);
+            let name = Name(format!("{} corpse", name).into());
+            let [r, g, b, a] = self.entities.get::<Appearance>(entity).map_or([1.0; 4], |a| a.fg);
+
+            let corpse = self.entities.spawn();
+            self.entities.insert(corpse, Corpse);
+            self.entities.insert(corpse, name);
+            self.entities.insert(corpse, Appearance {
+                glyph: CORPSE_GLYPH.into(),
+                fg: [r * CORPSE_DIM, g * CORPSE_DIM, b * CORPSE_DIM, a],
+            });
+            self.entities.set_position(corpse, position);
+        }
+
+        self.entities.despawn(entity);
+    }
+
+    /// Whether the player has been killed.
+    pub fn is_player_dead(&self) -> bool {
+        match self.player {
+            Some(player) => !self.entities.has::<Health>(player),
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::item::{Inventory, ItemTemplate};
+    use crate::state::world::testing;
+    use crate::state::world::{Block, MaterialId};
+
+    /// Always does exactly `damage`, twice that on a critical hit.
+    fn fixed(damage: i32) -> Dice {
+        Dice::new(0, 1, damage)
+    }
+
+    /// A world with the player at the origin of open floor.
+    fn world() -> (World, EntityId) {
+        let mut world = testing::flat_world();
+        assert_eq!(world.spawn_player((0, 0, 0)), Some((0, 0, 0)));
+        let player = world.player.unwrap();
+        (world, player)
+    }
+
+    /// A punching bag called `name` at `pos` that is not going to die any time soon.
+    fn dummy(
+        world: &mut World,
+        name: &'static str,
+        pos: (i32, i32, i32),
+        defence: Defence,
+    ) -> EntityId {
+        let dummy = world.entities.spawn();
+        world.entities.insert(dummy, Name(name.into()));
+        world.entities.insert(dummy, Health::new(1_000_000));
+        world.entities.insert(dummy, defence);
+        world.entities.set_position(dummy, pos);
+        dummy
+    }
+
+    /// The outcomes of attacking `target` once per tick for `rounds` ticks.
+    fn attack_rounds(
+        world: &mut World,
+        attacker: EntityId,
+        target: EntityId,
+        to_hit: i32,
+        damage_type: DamageType,
+        rounds: usize,
+    ) -> Vec<AttackOutcome> {
+        (0..rounds)
+            .map(|_| {
+                world.current_tick = world.current_tick + 1;
+                world.resolve_attack(attacker, target, to_hit, fixed(10), damage_type, false);
+                match world.take_events()[..] {
+                    [Event::Attacked { outcome, .. }] => outcome,
+                    ref events => panic!("expected a single attack, got {:?}", events),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn natural_ones_miss_and_natural_twenties_crit() {
+        let (mut world, player) = world();
+        let target = dummy(&mut world, "dummy", (1, 0, 0), Defence::default());
+
+        // Nobody misses a sure thing except on a natural 1.
+        let outcomes = attack_rounds(&mut world, player, target, 100, DamageType::Blunt, 200);
+        let misses = outcomes.iter().filter(|&&outcome| outcome == AttackOutcome::Miss).count();
+        assert!(misses > 0 && misses < 40);
+
+        // Nobody hits a lost cause except on a natural 20, which is always critical.
+        let outcomes = attack_rounds(&mut world, player, target, -100, DamageType::Blunt, 200);
+        let hits: Vec<_> = outcomes.into_iter()
+            .filter(|&outcome| outcome != AttackOutcome::Miss)
+            .collect();
+        assert!(!hits.is_empty() && hits.len() < 40);
+        assert!(hits.iter().all(|&hit| {
+            hit == AttackOutcome::Hit { damage: 20, damage_type: DamageType::Blunt, critical: true }
+        }));
+    }
+
+    #[test]
+    fn resistance_halves_and_armour_reduces_damage() {
+        let (mut world, player) = world();
+        let defence = Defence { evasion: 0, armour: 3, resistances: vec![DamageType::Fire] };
+        let target = dummy(&mut world, "dummy", (1, 0, 0), defence);
+
+        let expected = [(DamageType::Fire, 2, 7), (DamageType::Cold, 7, 17)];
+        for &(damage_type, normal, doubled) in &expected {
+            for outcome in attack_rounds(&mut world, player, target, 100, damage_type, 100) {
+                if let AttackOutcome::Hit { damage, critical, .. } = outcome {
+                    assert_eq!(damage, if critical { doubled } else { normal });
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn shots_stop_at_the_first_thing_in_the_way() {
+        let (mut world, player) = world();
+        let range = world.entities.get::<RangedAttack>(player).unwrap().range as i32;
+        let near = dummy(&mut world, "near", (2, 0, 0), Defence::default());
+        dummy(&mut world, "far", (4, 0, 0), Defence::default());
+
+        assert!(world.fire(player, (4, 0, 0)));
+        match &world.take_events()[..] {
+            [Event::Attacked { target, ranged: true, .. }] => assert_eq!(target, "near"),
+            events => panic!("expected the near dummy to be shot at, got {:?}", events),
+        }
+
+        // A wall in the way stops the shot before it reaches anyone.
+        world.entities.despawn(near);
+        world.set_block((1, 1, 0), Block::new(BlockFill::Solid(MaterialId::STONE)));
+        assert!(world.fire(player, (4, 2, 0)));
+        let blocked = Event::ShotBlocked { attacker: "you".into(), at: (1, 1, 0) };
+        assert_eq!(world.take_events(), [blocked]);
+
+        // Aiming out of range, or at nothing at all, wastes no time.
+        assert!(!world.fire(player, (range + 1, 0, 0)));
+        assert!(!world.fire(player, (0, 0, 0)));
+        world.entities.get_mut::<RangedAttack>(player).unwrap().range = 0;
+        assert!(!world.fire(player, (1, 0, 0)));
+        assert!(world.take_events().is_empty());
+    }
+
+    #[test]
+    fn the_dead_leave_a_corpse_and_their_things() {
+        let (mut world, player) = world();
+        let rat = dummy(&mut world, "rat", (1, 0, 0), Defence::default());
+        world.entities.get_mut::<Health>(rat).unwrap().current = 1;
+        world.entities.insert(rat, Inventory::new(1000));
+        let dagger = world.spawn_item(&ItemTemplate::dagger(), 1, (1, 0, 0));
+        world.give_item(rat, dagger);
+
+        while world.entities.is_alive(rat) {
+            world.current_tick = world.current_tick + 1;
+            assert!(world.melee(player, (1, 0, 0)));
+        }
+
+        assert!(world.take_events().contains(&Event::Died { name: "rat".into() }));
+        let left: Vec<_> = world.entities.at((1, 0, 0)).collect();
+        let corpse = left.iter().copied().find(|&id| world.entities.has::<Corpse>(id));
+        let corpse = corpse.expect("the rat left its corpse behind");
+        assert_eq!(world.entities.get::<Name>(corpse).unwrap().0, "rat corpse");
+        assert!(left.contains(&dagger));
+        assert_eq!(world.hostile_at(player, (1, 0, 0)), None);
+    }
+}
@@ -0,0 +1,400 @@
+//! Storage for everything in the world that is not terrain: the player, monsters, items and
+//! features such as doors. An entity is only an id; what it is depends on the components attached
+//! to it. Each component type lives in its own typed storage, indexed by the entity's slot.
+//!
+//! Positions are not an ordinary component. They are kept alongside an index from region keys to
+//! the entities inside each region, so that anything working a region at a time (streaming,
+//! rendering) can find the entities in it without scanning every entity in the world.
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+use crate::state::ai::Ai;
+use crate::state::combat::{Attack, Corpse, Defence, RangedAttack};
+use crate::state::fov::Vision;
+use crate::state::item::{Equippable, Inventory, Item};
+use crate::state::light::Light;
+use crate::state::scheduler::Actor;
+use crate::state::world::split_position;
+
+/// Identifies an entity. Slots are reused once an entity is despawned, but the generation is
+/// bumped each time, so an id held past its entity's despawn never refers to the new occupant.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct EntityId {
+    index: u32,
+    generation: u32,
+}
+
+impl EntityId {
+    pub fn index(self) -> usize {
+        self.index as usize
+    }
+
+    pub fn generation(self) -> u32 {
+        self.generation
+    }
+}
+
+/// Values of one component type, at most one per entity.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Storage<T> {
+    slots: Vec<Option<(u32, T)>>,
+}
+
+impl<T> Default for Storage<T> {
+    fn default() -> Self {
+        Self {
+            slots: Vec::new(),
+        }
+    }
+}
+
+impl<T> Storage<T> {
+    pub fn get(&self, id: EntityId) -> Option<&T> {
+        match self.slots.get(id.index())? {
+            Some((generation, value)) if *generation == id.generation => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn get_mut(&mut self, id: EntityId) -> Option<&mut T> {
+        match self.slots.get_mut(id.index())? {
+            Some((generation, value)) if *generation == id.generation => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn contains(&self, id: EntityId) -> bool {
+        self.get(id).is_some()
+    }
+
+    /// Attaches `value` to `id`, returning the value it replaces.
+    pub fn insert(&mut self, id: EntityId, value: T) -> Option<T> {
+        if self.slots.len() <= id.index() {
+            self.slots.resize_with(id.index() + 1, || None);
+        }
+
+        let previous = self.remove(id);
+        self.slots[id.index()] = Some((id.generation, value));
+        previous
+    }
+
+    pub fn remove(&mut self, id: EntityId) -> Option<T> {
+        let slot = self.slots.get_mut(id.index())?;
+        match slot {
+            Some((generation, _)) if *generation == id.generation => slot.take().map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (EntityId, &T)> {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| {
+            slot.as_ref().map(|(generation, value)| {
+                (EntityId { index: index as u32, generation: *generation }, value)
+            })
+        })
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (EntityId, &mut T)> {
+        self.slots.iter_mut().enumerate().filter_map(|(index, slot)| {
+            slot.as_mut().map(|(generation, value)| {
+                (EntityId { index: index as u32, generation: *generation }, value)
+            })
+        })
+    }
+}
+
+/// A type that can be attached to entities. Every component has a storage in `Ecs`; use the
+/// `components!` list at the bottom of this file to add new ones.
+pub trait Component: Sized + 'static {
+    fn storage(ecs: &Ecs) -> &Storage<Self>;
+    fn storage_mut(ecs: &mut Ecs) -> &mut Storage<Self>;
+}
+
+/// Marks the entity the player controls.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct PlayerControlled;
+
+/// How an entity is drawn.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Appearance {
+    pub glyph: Cow<'static, str>,
+    pub fg: [f32; 4],
+}
+
+/// Marks entities that nothing else can move into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Blocking;
+
+/// What an entity is called in messages.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Name(pub Cow<'static, str>);
+
+/// How much damage an entity can take before it dies.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Health {
+    pub current: i32,
+    pub max: i32,
+}
+
+impl Health {
+    pub fn new(max: i32) -> Self {
+        Self {
+            current: max,
+            max,
+        }
+    }
+
+    /// Whether health has dropped to `percent` percent of the maximum or below.
+    pub fn is_below(&self, percent: i32) -> bool {
+        self.current * 100 <= self.max * percent
+    }
+}
+
+macro_rules! components {
+    ($($field:ident: $component:ty,)*) => {
+        /// Every entity and its components.
+        #[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+        pub struct Ecs {
+            /// The current generation of every slot, and whether an entity occupies it.
+            generations: Vec<(u32, bool)>,
+            free: Vec<u32>,
+            positions: Storage<(i32, i32, i32)>,
+            /// The entities with a position, keyed by the region they are in. Ordered so that saving
+            /// the same entities twice produces the same bytes.
+            by_region: BTreeMap<(i32, i32, i32), Vec<EntityId>>,
+            $($field: Storage<$component>,)*
+        }
+
+        $(
+            impl Component for $component {
+                fn storage(ecs: &Ecs) -> &Storage<Self> {
+                    &ecs.$field
+                }
+
+                fn storage_mut(ecs: &mut Ecs) -> &mut Storage<Self> {
+                    &mut ecs.$field
+                }
+            }
+        )*
+
+        impl Ecs {
+            fn remove_components(&mut self, id: EntityId) {
+                $(self.$field.remove(id);)*
+            }
+        }
+    };
+}
+
+components! {
+    player_controlled: PlayerControlled,
+    appearances: Appearance,
+    blocking: Blocking,
+    actors: Actor,
+    visions: Vision,
+    lights: Light,
+    names: Name,
+    healths: Health,
+    ais: Ai,
+    attacks: Attack,
+    ranged_attacks: RangedAttack,
+    defences: Defence,
+    corpses: Corpse,
+    items: Item,
+    equippables: Equippable,
+    inventories: Inventory,
+}
+
+impl Ecs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an entity without any components.
+    pub fn spawn(&mut self) -> EntityId {
+        match self.free.pop() {
+            Some(index) => {
+                let slot = &mut self.generations[index as usize];
+                slot.1 = true;
+                EntityId { index, generation: slot.0 }
+            },
+            None => {
+                self.generations.push((0, true));
+                EntityId { index: self.generations.len() as u32 - 1, generation: 0 }
+            },
+        }
+    }
+
+    /// Removes an entity and all of its components. Returns `false` if it was already gone.
+    pub fn despawn(&mut self, id: EntityId) -> bool {
+        if !self.is_alive(id) {
+            return false;
+        }
+
+        self.remove_position(id);
+        self.remove_components(id);
+
+        let slot = &mut self.generations[id.index()];
+        slot.0 = slot.0.wrapping_add(1);
+        slot.1 = false;
+        self.free.push(id.index);
+
+        true
+    }
+
+    pub fn is_alive(&self, id: EntityId) -> bool {
+        self.generations.get(id.index()) == Some(&(id.generation, true))
+    }
+
+    pub fn entities(&self) -> impl Iterator<Item = EntityId> + '_ {
+        self.generations.iter().enumerate()
+            .filter(|(_, &(_, alive))| alive)
+            .map(|(index, &(generation, _))| EntityId { index: index as u32, generation })
+    }
+
+    /// Attaches `component` to `id`, returning the component it replaces. Components attached to
+    /// ids that are no longer alive are dropped.
+    pub fn insert<T: Component>(&mut self, id: EntityId, component: T) -> Option<T> {
+        if !self.is_alive(id) {
+            return None;
+        }
+
+        T::storage_mut(self).insert(id, component)
+    }
+
+    pub fn remove<T: Component>(&mut self, id: EntityId) -> Option<T> {
+        T::storage_mut(self).remove(id)
+    }
+
+    pub fn get<T: Component>(&self, id: EntityId) -> Option<&T> {
+        T::storage(self).get(id)
+    }
+
+    pub fn get_mut<T: Component>(&mut self, id: EntityId) -> Option<&mut T> {
+        T::storage_mut(self).get_mut(id)
+    }
+
+    pub fn has<T: Component>(&self, id: EntityId) -> bool {
+        T::storage(self).contains(id)
+    }
+
+    /// Every entity with a `T`.
+    pub fn query<T: Component>(&self) -> impl Iterator<Item = (EntityId, &T)> {
+        T::storage(self).iter()
+    }
+
+    pub fn query_mut<T: Component>(&mut self) -> impl Iterator<Item = (EntityId, &mut T)> {
+        T::storage_mut(self).iter_mut()
+    }
+
+    /// Every entity with both an `A` and a `B`.
+    pub fn query2<A: Component, B: Component>(&self) -> impl Iterator<Item = (EntityId, &A, &B)> {
+        let b = B::storage(self);
+        A::storage(self).iter().filter_map(move |(id, a)| Some((id, a, b.get(id)?)))
+    }
+
+    /// Where `id` is, in global tile coordinates.
+    pub fn position(&self, id: EntityId) -> Option<(i32, i32, i32)> {
+        self.positions.get(id).copied()
+    }
+
+    /// Places `id` at `pos`, keeping the region index up to date.
+    pub fn set_position(&mut self, id: EntityId, pos: (i32, i32, i32)) {
+        if !self.is_alive(id) {
+            return;
+        }
+
+        self.remove_position(id);
+        self.positions.insert(id, pos);
+        self.by_region.entry(split_position(pos).0).or_default().push(id);
+    }
+
+    /// Takes `id` out of the world without despawning it, for instance when it is picked up.
+    pub fn remove_position(&mut self, id: EntityId) -> Option<(i32, i32, i32)> {
+        let pos = self.positions.remove(id)?;
+
+        let offset = split_position(pos).0;
+        if let Some(entities) = self.by_region.get_mut(&offset) {
+            entities.retain(|&e| e != id);
+            if entities.is_empty() {
+                self.by_region.remove(&offset);
+            }
+        }
+
+        Some(pos)
+    }
+
+    /// Every entity with a position inside the region at `offset`.
+    pub fn in_region(&self, offset: (i32, i32, i32)) -> &[EntityId] {
+        self.by_region.get(&offset).map_or(&[], |entities| entities.as_slice())
+    }
+
+    /// Every entity standing on the tile at `pos`.
+    pub fn at(&self, pos: (i32, i32, i32)) -> impl Iterator<Item = EntityId> + '_ {
+        self.in_region(split_position(pos).0).iter()
+            .copied()
+            .filter(move |&id| self.position(id) == Some(pos))
+    }
+
+    /// Every entity with a position, along with that position.
+    pub fn positions(&self) -> impl Iterator<Item = (EntityId, (i32, i32, i32))> + '_ {
+        self.positions.iter().map(|(id, &pos)| (id, pos))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::prelude::*;
+
+    const DIM: i32 = REGION_DIM as i32;
+
+    #[test]
+    fn stale_ids_miss_the_reused_slot() {
+        let mut ecs = Ecs::new();
+        let old = ecs.spawn();
+        ecs.insert(old, Name("old".into()));
+        assert!(ecs.despawn(old));
+        assert!(!ecs.despawn(old));
+
+        let new = ecs.spawn();
+        assert_eq!(new.index(), old.index());
+        assert_ne!(new, old);
+        assert!(ecs.is_alive(new) && !ecs.is_alive(old));
+        assert!(!ecs.has::<Name>(new));
+
+        // Nothing done through the stale id reaches the new occupant.
+        assert_eq!(ecs.insert(old, Name("stale".into())), None);
+        ecs.set_position(old, (1, 2, 0));
+        assert_eq!(ecs.get::<Name>(old), None);
+        assert_eq!(ecs.get::<Name>(new), None);
+        assert_eq!(ecs.position(new), None);
+        assert_eq!(ecs.entities().collect::<Vec<_>>(), [new]);
+    }
+
+    #[test]
+    fn region_index_follows_moves_and_despawns() {
+        let mut ecs = Ecs::new();
+        let (a, b) = (ecs.spawn(), ecs.spawn());
+        ecs.set_position(a, (DIM - 1, 0, 0));
+        ecs.set_position(b, (1, 1, 0));
+        assert_eq!(ecs.in_region((0, 0, 0)), [a, b]);
+
+        // Crossing the border moves the entity to the neighbouring region's list.
+        ecs.set_position(a, (DIM, 0, 0));
+        assert_eq!(ecs.in_region((0, 0, 0)), [b]);
+        assert_eq!(ecs.in_region((1, 0, 0)), [a]);
+        assert_eq!(ecs.at((DIM, 0, 0)).collect::<Vec<_>>(), [a]);
+
+        // So does going below zero, into negative regions.
+        ecs.set_position(b, (-1, -1, 0));
+        assert!(ecs.in_region((0, 0, 0)).is_empty());
+        assert_eq!(ecs.in_region((-1, -1, 0)), [b]);
+
+        ecs.despawn(a);
+        assert!(ecs.in_region((1, 0, 0)).is_empty());
+        assert_eq!(ecs.at((DIM, 0, 0)).count(), 0);
+        assert_eq!(ecs.positions().collect::<Vec<_>>(), [(b, (-1, -1, 0))]);
+    }
+}
@@ -0,0 +1,163 @@
+//! Things that happen in the world that the player should hear about. The world queues them up as
+//! they happen; the UI takes them with `World::take_events` and turns them into messages.
+//!
+//! Events carry names rather than entity ids, since whatever they are about may well be gone by
+//! the time they are shown.
+
+use std::borrow::Cow;
+use std::fmt;
+
+use crate::state::combat::DamageType;
+use crate::state::world::World;
+
+/// How an attack went.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AttackOutcome {
+    Miss,
+    Hit {
+        damage: i32,
+        damage_type: DamageType,
+        /// The attack rolled a natural 20 and did double damage.
+        critical: bool,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// `attacker` attacked `target`, in melee or at range.
+    Attacked {
+        attacker: Cow<'static, str>,
+        target: Cow<'static, str>,
+        ranged: bool,
+        outcome: AttackOutcome,
+    },
+    /// A shot from `attacker` hit a wall at `at`.
+    ShotBlocked {
+        attacker: Cow<'static, str>,
+        at: (i32, i32, i32),
+    },
+    /// `name` dug through a block of `material`.
+    Dug {
+        name: Cow<'static, str>,
+        material: String,
+    },
+    /// `name` died.
+    Died {
+        name: Cow<'static, str>,
+    },
+    /// `name` picked `item` up off the floor.
+    PickedUp {
+        name: Cow<'static, str>,
+        item: String,
+    },
+    /// `name` could not pick `item` up without carrying too much.
+    TooHeavy {
+        name: Cow<'static, str>,
+        item: String,
+    },
+    /// `name` dropped `item` on the floor.
+    Dropped {
+        name: Cow<'static, str>,
+        item: String,
+    },
+    /// `name` wielded or put on `item`.
+    Equipped {
+        name: Cow<'static, str>,
+        item: String,
+        wielded: bool,
+    },
+    /// `name` stopped wielding or took off `item`.
+    Unequipped {
+        name: Cow<'static, str>,
+        item: String,
+        wielded: bool,
+    },
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::Attacked { attacker, target, ranged, outcome } => {
+                let (subject, object) = (subject(attacker), object(target));
+                match (outcome, ranged) {
+                    (AttackOutcome::Miss, false) => {
+                        write!(f, "{} {} {}.", subject, verb(attacker, "miss"), object)
+                    },
+                    (AttackOutcome::Miss, true) => {
+                        write!(f, "{} {} at {} and {}.", subject, verb(attacker, "shoot"), object,
+                            verb(attacker, "miss"))
+                    },
+                    (AttackOutcome::Hit { damage, critical, .. }, _) => {
+                        let how = if *critical { "critically " } else { "" };
+                        let hit = verb(attacker, if *ranged { "shoot" } else { "hit" });
+                        write!(f, "{} {}{} {} for {}.", subject, how, hit, object, damage)
+                    },
+                }
+            },
+            Event::ShotBlocked { attacker, .. } => {
+                write!(f, "{} {} the wall.", subject(attacker), verb(attacker, "shoot"))
+            },
+            Event::Dug { name, material } => {
+                write!(f, "{} {} through the {}.", subject(name), verb(name, "dig"), material)
+            },
+            Event::Died { name } => write!(f, "{} {}.", subject(name), verb(name, "die")),
+            Event::PickedUp { name, item } => {
+                write!(f, "{} {} up the {}.", subject(name), verb(name, "pick"), item)
+            },
+            Event::TooHeavy { name, item } if is_player(name) => {
+                write!(f, "You can't carry the {} as well.", item)
+            },
+            Event::TooHeavy { name, item } => {
+                write!(f, "{} can't carry the {} as well.", subject(name), item)
+            },
+            Event::Dropped { name, item } => {
+                write!(f, "{} {} the {}.", subject(name), verb(name, "drop"), item)
+            },
+            Event::Equipped { name, item, wielded: true } => {
+                write!(f, "{} {} the {}.", subject(name), verb(name, "wield"), item)
+            },
+            Event::Equipped { name, item, wielded: false } => {
+                write!(f, "{} {} on the {}.", subject(name), verb(name, "put"), item)
+            },
+            Event::Unequipped { name, item, wielded: true } => {
+                write!(f, "{} {} away the {}.", subject(name), verb(name, "put"), item)
+            },
+            Event::Unequipped { name, item, wielded: false } => {
+                write!(f, "{} {} off the {}.", subject(name), verb(name, "take"), item)
+            },
+        }
+    }
+}
+
+/// Whether `name` is how the player is referred to. See `World::name_of`.
+fn is_player(name: &str) -> bool {
+    name == "you"
+}
+
+/// `name` at the start of a sentence.
+fn subject(name: &str) -> String {
+    if is_player(name) { "You".to_string() } else { format!("The {}", name) }
+}
+
+/// `name` anywhere else in a sentence.
+fn object(name: &str) -> String {
+    if is_player(name) { "you".to_string() } else { format!("the {}", name) }
+}
+
+/// `base` agreeing with `name` as its subject.
+fn verb(name: &str, base: &str) -> String {
+    if is_player(name) {
+        base.to_string()
+    } else if base.ends_with('s') || base.ends_with("sh") {
+        format!("{}es", base)
+    } else {
+        format!("{}s", base)
+    }
+}
+
+impl World {
+    /// Takes everything that has happened since the last call, oldest first.
+    pub fn take_events(&mut self) -> Vec<Event> {
+        std::mem::take(&mut self.events)
+    }
+}
@@ -0,0 +1,253 @@
+//! Field of view by symmetric shadowcasting. The area around the viewer is split into four
+//! quadrants, each scanned row by row outwards from the viewer. Opaque tiles cast shadows that
+//! narrow the range of columns visible in the rows behind them. A floor tile is only visible when
+//! its centre lies inside the lit range, which makes sight symmetric: if A can see B, B can see A.
+//!
+//! Slopes are kept as exact fractions, so the result does not depend on floating point rounding.
+//!
+//! See https://www.albertford.com/shadowcasting/ for a walkthrough of the algorithm.
+
+use std::collections::HashSet;
+
+use crate::state::world::World;
+
+/// How far an entity can see.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Vision {
+    pub radius: u32,
+}
+
+/// Returns every tile visible from `origin` within `radius` tiles, on the same level. Opaque tiles
+/// that bound the view are visible themselves. `blocks_sight` is asked about tiles in any order,
+/// and may be asked about the same tile more than once.
+pub fn field_of_view(
+    origin: (i32, i32, i32),
+    radius: u32,
+    mut blocks_sight: impl FnMut((i32, i32, i32)) -> bool,
+) -> HashSet<(i32, i32, i32)> {
+    let mut visible = HashSet::new();
+    visible.insert(origin);
+
+    let radius = radius as i32;
+    for &quadrant in &[Quadrant::North, Quadrant::East, Quadrant::South, Quadrant::West] {
+        let mut rows = vec![Row {
+            depth: 1,
+            start_slope: Slope::new(-1, 1),
+            end_slope: Slope::new(1, 1),
+        }];
+
+        while let Some(mut row) = rows.pop() {
+            if row.depth > radius {
+                continue;
+            }
+
+            let mut previous_opaque = None;
+            for col in row.min_col()..=row.max_col() {
+                let pos = quadrant.transform(origin, row.depth, col);
+                let opaque = blocks_sight(pos);
+
+                let in_radius = row.depth * row.depth + col * col <= radius * radius + radius;
+                if in_radius && (opaque || row.is_symmetric(col)) {
+                    visible.insert(pos);
+                }
+
+                match previous_opaque {
+                    Some(true) if !opaque => row.start_slope = Slope::tile_edge(row.depth, col),
+                    Some(false) if opaque => rows.push(Row {
+                        end_slope: Slope::tile_edge(row.depth, col),
+                        ..row.next()
+                    }),
+                    _ => {},
+                }
+
+                previous_opaque = Some(opaque);
+            }
+
+            if previous_opaque == Some(false) {
+                rows.push(row.next());
+            }
+        }
+    }
+
+    visible
+}
+
+impl World {
+    /// Returns every tile visible from `origin` within `radius` tiles. Whether a block can be seen
+    /// through is decided by its material.
+    pub fn field_of_view(
+        &mut self,
+        origin: (i32, i32, i32),
+        radius: u32,
+    ) -> HashSet<(i32, i32, i32)> {
+        field_of_view(origin, radius, |pos| {
+            let fill = self.block_at(pos).fill;
+            self.materials.blocks_sight(fill)
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Quadrant {
+    North,
+    East,
+    South,
+    West,
+}
+
+impl Quadrant {
+    /// Maps a row depth and column within this quadrant to a tile.
+    fn transform(self, (x, y, z): (i32, i32, i32), depth: i32, col: i32) -> (i32, i32, i32) {
+        match self {
+            Quadrant::North => (x + col, y - depth, z),
+            Quadrant::South => (x + col, y + depth, z),
+            Quadrant::East => (x + depth, y + col, z),
+            Quadrant::West => (x - depth, y + col, z),
+        }
+    }
+}
+
+/// A slope as an exact fraction. The denominator is always positive.
+#[derive(Clone, Copy, Debug)]
+struct Slope {
+    num: i64,
+    den: i64,
+}
+
+impl Slope {
+    fn new(num: i64, den: i64) -> Self {
+        Self { num, den }
+    }
+
+    /// The slope through the left edge of the tile at `col` in the row at `depth`.
+    fn tile_edge(depth: i32, col: i32) -> Self {
+        Self::new(2 * col as i64 - 1, 2 * depth as i64)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Row {
+    depth: i32,
+    start_slope: Slope,
+    end_slope: Slope,
+}
+
+impl Row {
+    /// The first column in this row touched by the lit range, rounding half-columns outwards.
+    fn min_col(&self) -> i32 {
+        let Slope { num, den } = self.start_slope;
+        (2 * self.depth as i64 * num + den).div_euclid(2 * den) as i32
+    }
+
+    /// The last column in this row touched by the lit range, rounding half-columns outwards.
+    fn max_col(&self) -> i32 {
+        let Slope { num, den } = self.end_slope;
+        -(-(2 * self.depth as i64 * num - den)).div_euclid(2 * den) as i32
+    }
+
+    /// Whether the centre of the tile at `col` lies inside the lit range.
+    fn is_symmetric(&self, col: i32) -> bool {
+        let (depth, col) = (self.depth as i64, col as i64);
+        col * self.start_slope.den >= depth * self.start_slope.num
+            && col * self.end_slope.den <= depth * self.end_slope.num
+    }
+
+    fn next(&self) -> Self {
+        Self {
+            depth: self.depth + 1,
+            ..*self
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Walls are `#`, everything else can be seen through. Rows run down the page and everything
+    /// outside the map is a wall.
+    fn walls<'a>(map: &'a [&'a str]) -> impl Fn((i32, i32, i32)) -> bool + 'a {
+        move |(x, y, _)| {
+            if x < 0 || y < 0 {
+                return true;
+            }
+            map.get(y as usize)
+                .and_then(|row| row.as_bytes().get(x as usize))
+                .map_or(true, |&tile| tile == b'#')
+        }
+    }
+
+    #[test]
+    fn straight_corridor() {
+        let map = [
+            "##########",
+            "#........#",
+            "##########",
+        ];
+        let visible = field_of_view((1, 1, 0), 20, walls(&map));
+
+        for x in 0..10 {
+            assert!(visible.contains(&(x, 1, 0)), "({}, 1) should be visible", x);
+        }
+        // The walls either side bound the view, and nothing past them shows.
+        assert!(visible.contains(&(5, 0, 0)));
+        assert!(visible.contains(&(5, 2, 0)));
+        assert!(visible.iter().all(|&(_, y, _)| (0..=2).contains(&y)));
+    }
+
+    #[test]
+    fn pillar_casts_shadow() {
+        let map = [
+            ".........",
+            ".........",
+            "....#....",
+            ".........",
+            ".........",
+        ];
+        let visible = field_of_view((4, 0, 0), 10, walls(&map));
+
+        assert!(visible.contains(&(4, 2, 0)), "the pillar itself is visible");
+        assert!(!visible.contains(&(4, 3, 0)), "the tile behind the pillar is hidden");
+        assert!(!visible.contains(&(4, 4, 0)));
+        assert!(visible.contains(&(0, 4, 0)), "tiles off to the side are not shadowed");
+        assert!(visible.contains(&(8, 4, 0)));
+    }
+
+    #[test]
+    fn sight_is_symmetric() {
+        let map = [
+            "############",
+            "#....#.....#",
+            "#.##...#.#.#",
+            "#....#...#.#",
+            "#.#.....##.#",
+            "#...#......#",
+            "############",
+        ];
+        let blocks_sight = walls(&map);
+
+        let floor: Vec<_> = (0..12)
+            .flat_map(|x| (0..7).map(move |y| (x, y, 0)))
+            .filter(|&pos| !blocks_sight(pos))
+            .collect();
+        for &a in &floor {
+            let from_a = field_of_view(a, 20, &blocks_sight);
+            for &b in &floor {
+                let from_b_sees_a = field_of_view(b, 20, &blocks_sight).contains(&a);
+                assert_eq!(from_a.contains(&b), from_b_sees_a, "{:?} and {:?} disagree", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn radius_cuts_off_sight() {
+        let map = [".".repeat(21)];
+        let map: Vec<&str> = map.iter().map(|row| row.as_str()).collect();
+        let visible = field_of_view((10, 0, 0), 4, walls(&map));
+
+        assert!(visible.contains(&(14, 0, 0)));
+        assert!(visible.contains(&(6, 0, 0)));
+        assert!(!visible.contains(&(15, 0, 0)));
+        assert!(!visible.contains(&(5, 0, 0)));
+    }
+}
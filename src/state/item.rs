@@ -0,0 +1,613 @@
+//! Things that can be picked up. An item is an ordinary entity with an `Item` component: on the
+//! floor it has a position like anything else, and once picked up it loses its position and is
+//! listed in its holder's `Inventory` instead.
+//!
+//! Items that are `Equippable` can be wielded or worn in a slot, which changes the combat stats of
+//! whoever has them on. Stats are never written back into the `Attack` and `Defence` components;
+//! they are worked out on demand by `World::attack_of` and `World::defence_of`.
+
+use std::borrow::Cow;
+
+use rand::Rng;
+
+use crate::state::combat::{Attack, DamageType, Defence, Dice};
+use crate::state::ecs::{Appearance, EntityId, Name};
+use crate::state::event::Event;
+use crate::state::world::gen::seeded_rng;
+use crate::state::world::World;
+
+/// Salt for the rng that scatters items over the floor.
+const SCATTER_SALT: u64 = 0x4954_454D;
+
+/// Makes an entity something that can be picked up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Item {
+    /// The weight of a single item, in grams.
+    pub weight: u32,
+    /// How many items this entity stands for.
+    pub count: u32,
+    /// Whether items with the same name merge into one stack when carried together.
+    pub stackable: bool,
+}
+
+impl Item {
+    /// The weight of the whole stack, in grams.
+    pub fn total_weight(&self) -> u32 {
+        self.weight * self.count
+    }
+}
+
+/// Where on the body something is wielded or worn. Each slot holds at most one item.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum EquipSlot {
+    MainHand,
+    OffHand,
+    Head,
+    Body,
+    Feet,
+}
+
+impl EquipSlot {
+    /// Whether items in this slot are held rather than worn.
+    pub fn is_wielded(self) -> bool {
+        matches!(self, EquipSlot::MainHand | EquipSlot::OffHand)
+    }
+}
+
+/// Makes an item something that can be wielded or worn, and says what it does for its wearer.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Equippable {
+    pub slot: EquipSlot,
+    /// Added to the wearer's chance to hit in melee.
+    pub to_hit: i32,
+    /// Replaces the wearer's own melee damage, for weapons.
+    pub damage: Option<(Dice, DamageType)>,
+    pub evasion: i32,
+    pub armour: i32,
+    pub resistances: Vec<DamageType>,
+}
+
+impl Equippable {
+    fn new(slot: EquipSlot) -> Self {
+        Self {
+            slot,
+            to_hit: 0,
+            damage: None,
+            evasion: 0,
+            armour: 0,
+            resistances: Vec::new(),
+        }
+    }
+}
+
+/// What an entity is carrying.
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Inventory {
+    /// Every carried item, equipped or not, in the order they were picked up.
+    pub items: Vec<EntityId>,
+    /// The carried items that are wielded or worn, and where.
+    pub equipped: Vec<(EquipSlot, EntityId)>,
+    /// The most the entity can carry, in grams.
+    pub max_weight: u32,
+}
+
+impl Inventory {
+    pub fn new(max_weight: u32) -> Self {
+        Self {
+            max_weight,
+            ..Self::default()
+        }
+    }
+
+    /// The item in `slot`, if there is one.
+    pub fn equipped_in(&self, slot: EquipSlot) -> Option<EntityId> {
+        self.equipped.iter().find(|&&(s, _)| s == slot).map(|&(_, item)| item)
+    }
+
+    /// The slot `item` is equipped in, if it is equipped at all.
+    pub fn slot_of(&self, item: EntityId) -> Option<EquipSlot> {
+        self.equipped.iter().find(|&&(_, i)| i == item).map(|&(slot, _)| slot)
+    }
+}
+
+/// Everything needed to spawn a kind of item.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ItemTemplate {
+    pub name: Cow<'static, str>,
+    pub glyph: Cow<'static, str>,
+    pub fg: [f32; 4],
+    pub weight: u32,
+    pub stackable: bool,
+    pub equippable: Option<Equippable>,
+}
+
+impl ItemTemplate {
+    pub fn dagger() -> Self {
+        Self {
+            name: "dagger".into(),
+            glyph: "|".into(),
+            fg: [0.75, 0.75, 0.8, 1.0],
+            weight: 500,
+            stackable: false,
+            equippable: Some(Equippable {
+                to_hit: 2,
+                damage: Some((Dice::new(1, 4, 1), DamageType::Piercing)),
+                ..Equippable::new(EquipSlot::MainHand)
+            }),
+        }
+    }
+
+    pub fn sword() -> Self {
+        Self {
+            name: "sword".into(),
+            glyph: "|".into(),
+            fg: [0.85, 0.85, 0.95, 1.0],
+            weight: 1500,
+            stackable: false,
+            equippable: Some(Equippable {
+                damage: Some((Dice::new(1, 8, 1), DamageType::Slashing)),
+                ..Equippable::new(EquipSlot::MainHand)
+            }),
+        }
+    }
+
+    pub fn shield() -> Self {
+        Self {
+            name: "shield".into(),
+            glyph: "[".into(),
+            fg: [0.6, 0.45, 0.3, 1.0],
+            weight: 3000,
+            stackable: false,
+            equippable: Some(Equippable {
+                evasion: 2,
+                ..Equippable::new(EquipSlot::OffHand)
+            }),
+        }
+    }
+
+    pub fn helmet() -> Self {
+        Self {
+            name: "helmet".into(),
+            glyph: "[".into(),
+            fg: [0.7, 0.7, 0.7, 1.0],
+            weight: 1000,
+            stackable: false,
+            equippable: Some(Equippable {
+                armour: 1,
+                ..Equippable::new(EquipSlot::Head)
+            }),
+        }
+    }
+
+    pub fn leather_armour() -> Self {
+        Self {
+            name: "leather armour".into(),
+            glyph: "[".into(),
+            fg: [0.55, 0.35, 0.2, 1.0],
+            weight: 5000,
+            stackable: false,
+            equippable: Some(Equippable {
+                armour: 1,
+                resistances: vec![DamageType::Cold],
+                ..Equippable::new(EquipSlot::Body)
+            }),
+        }
+    }
+
+    pub fn boots() -> Self {
+        Self {
+            name: "boots".into(),
+            glyph: "[".into(),
+            fg: [0.45, 0.3, 0.2, 1.0],
+            weight: 800,
+            stackable: false,
+            equippable: Some(Equippable {
+                evasion: 1,
+                ..Equippable::new(EquipSlot::Feet)
+            }),
+        }
+    }
+
+    pub fn gold() -> Self {
+        Self {
+            name: "gold coin".into(),
+            glyph: "$".into(),
+            fg: [1.0, 0.85, 0.2, 1.0],
+            weight: 10,
+            stackable: true,
+            equippable: None,
+        }
+    }
+}
+
+impl World {
+    /// Creates `count` items from `template` as a single entity, on the floor at `pos`.
+    pub fn spawn_item(
+        &mut self,
+        template: &ItemTemplate,
+        count: u32,
+        pos: (i32, i32, i32),
+    ) -> EntityId {
+        let count = if template.stackable { count.max(1) } else { 1 };
+
+        let item = self.entities.spawn();
+        self.entities.insert(item, Name(template.name.clone()));
+        let appearance = Appearance { glyph: template.glyph.clone(), fg: template.fg };
+        self.entities.insert(item, appearance);
+        let stackable = template.stackable;
+        self.entities.insert(item, Item { weight: template.weight, count, stackable });
+        if let Some(equippable) = &template.equippable {
+            self.entities.insert(item, equippable.clone());
+        }
+        self.entities.set_position(item, pos);
+
+        item
+    }
+
+    /// Scatters `count` items picked from `templates` over free floor within `radius` tiles of
+    /// `center`, the same way `World::populate` scatters monsters. Stackable items come in piles
+    /// of up to `max_pile`. Returns the items placed.
+    pub fn scatter_items(
+        &mut self,
+        templates: &[ItemTemplate],
+        count: u32,
+        max_pile: u32,
+        center: (i32, i32, i32),
+        radius: i32,
+    ) -> Vec<EntityId> {
+        let mut rng = seeded_rng(self.seed ^ SCATTER_SALT, center.2 as i64 as u64);
+        let mut items = Vec::new();
+
+        if templates.is_empty() {
+            return items;
+        }
+
+        for _ in 0..count {
+            let template = &templates[rng.gen_range(0, templates.len())];
+            let pile = rng.gen_range(1, max_pile.max(1) + 1);
+            if let Some(pos) = self.random_free_tile(&mut rng, center, radius) {
+                items.push(self.spawn_item(template, pile, pos));
+            }
+        }
+
+        items
+    }
+
+    /// What `item` is called in lists, with the size of the stack if there is more than one.
+    pub fn item_label(&self, item: EntityId) -> String {
+        let name = self.entities.get::<Name>(item).map_or("something", |name| &name.0);
+        match self.entities.get::<Item>(item) {
+            Some(&Item { count, .. }) if count > 1 => format!("{} x{}", name, count),
+            _ => name.to_string(),
+        }
+    }
+
+    /// The total weight of everything `entity` is carrying, in grams.
+    pub fn carried_weight(&self, entity: EntityId) -> u32 {
+        self.entities.get::<Inventory>(entity).map_or(0, |inventory| {
+            inventory.items.iter()
+                .filter_map(|&item| self.entities.get::<Item>(item))
+                .map(Item::total_weight)
+                .sum()
+        })
+    }
+
+    /// Picks up everything on the floor under `entity` that it can carry. Stackable items merge
+    /// into any stack of the same name already carried. Returns `false` if nothing was picked up.
+    pub fn pick_up(&mut self, entity: EntityId) -> bool {
+        let position = match self.entities.position(entity) {
+            Some(position) if self.entities.has::<Inventory>(entity) => position,
+            _ => return false,
+        };
+
+        let entities = &self.entities;
+        let floor: Vec<_> = entities.at(position).filter(|&id| entities.has::<Item>(id)).collect();
+
+        let mut picked_up = false;
+        for item in floor {
+            let weight = self.entities.get::<Item>(item).map_or(0, Item::total_weight);
+            let max_weight = self.entities.get::<Inventory>(entity).map_or(0, |i| i.max_weight);
+            let (name, label) = (self.name_of(entity), self.item_label(item));
+            if self.carried_weight(entity) + weight > max_weight {
+                self.events.push(Event::TooHeavy { name, item: label });
+                continue;
+            }
+
+            // The label is taken first, since a stack that merges into another is despawned.
+            self.give_item(entity, item);
+            self.events.push(Event::PickedUp { name, item: label });
+            picked_up = true;
+        }
+
+        picked_up
+    }
+
+    /// Puts `item` into `entity`'s inventory, taking it off the floor if it is there, and merges it
+    /// into a matching stack. The weight limit is not checked.
+    pub fn give_item(&mut self, entity: EntityId, item: EntityId) {
+        self.entities.remove_position(item);
+        let stack = self.entities.get::<Inventory>(entity)
+            .and_then(|inventory| {
+                inventory.items.iter().copied().find(|&carried| self.stacks_with(carried, item))
+            });
+
+        match stack {
+            Some(stack) => {
+                let count = self.entities.get::<Item>(item).map_or(0, |item| item.count);
+                if let Some(stack) = self.entities.get_mut::<Item>(stack) {
+                    stack.count += count;
+                }
+                self.entities.despawn(item);
+            },
+            None => {
+                if let Some(inventory) = self.entities.get_mut::<Inventory>(entity) {
+                    inventory.items.push(item);
+                }
+            },
+        }
+    }
+
+    /// Whether two items are the same kind of stackable item.
+    fn stacks_with(&self, a: EntityId, b: EntityId) -> bool {
+        let stackable = |id| self.entities.get::<Item>(id).map_or(false, |item| item.stackable);
+        let name = |id| self.entities.get::<Name>(id).map(|name| &name.0);
+
+        a != b && stackable(a) && stackable(b) && name(a) == name(b)
+    }
+
+    /// Drops a carried `item` on the floor under `entity`, taking it off first if it is equipped.
+    /// Returns `false` if `entity` is not carrying it.
+    pub fn drop_item(&mut self, entity: EntityId, item: EntityId) -> bool {
+        let position = match self.entities.position(entity) {
+            Some(position) => position,
+            None => return false,
+        };
+        let carried = self.entities.get_mut::<Inventory>(entity).map_or(false, |inventory| {
+            let carried = inventory.items.contains(&item);
+            inventory.items.retain(|&i| i != item);
+            inventory.equipped.retain(|&(_, i)| i != item);
+            carried
+        });
+        if !carried {
+            return false;
+        }
+
+        self.entities.set_position(item, position);
+        let (name, label) = (self.name_of(entity), self.item_label(item));
+        self.events.push(Event::Dropped { name, item: label });
+        true
+    }
+
+    /// Drops everything `entity` is carrying, for instance when it dies.
+    pub fn drop_everything(&mut self, entity: EntityId) {
+        let items = self.entities.get::<Inventory>(entity)
+            .map_or(Vec::new(), |inventory| inventory.items.clone());
+
+        if let Some(position) = self.entities.position(entity) {
+            for &item in &items {
+                self.entities.set_position(item, position);
+            }
+        }
+
+        if let Some(inventory) = self.entities.get_mut::<Inventory>(entity) {
+            inventory.items.clear();
+            inventory.equipped.clear();
+        }
+    }
+
+    /// Wields or puts on a carried `item`, taking off whatever was in its slot. Returns `false`
+    /// if `entity` is not carrying it or it cannot be equipped.
+    pub fn equip(&mut self, entity: EntityId, item: EntityId) -> bool {
+        let slot = match self.entities.get::<Equippable>(item) {
+            Some(equippable) => equippable.slot,
+            None => return false,
+        };
+        match self.entities.get::<Inventory>(entity) {
+            Some(inventory) if inventory.items.contains(&item) => {
+                if inventory.slot_of(item).is_some() {
+                    return false;
+                }
+            },
+            _ => return false,
+        }
+
+        self.unequip(entity, slot);
+
+        if let Some(inventory) = self.entities.get_mut::<Inventory>(entity) {
+            inventory.equipped.push((slot, item));
+        }
+        self.events.push(Event::Equipped {
+            name: self.name_of(entity),
+            item: self.item_label(item),
+            wielded: slot.is_wielded(),
+        });
+        true
+    }
+
+    /// Takes off whatever `entity` has in `slot`, keeping it in the inventory. Returns `false` if
+    /// the slot is empty.
+    pub fn unequip(&mut self, entity: EntityId, slot: EquipSlot) -> bool {
+        let item = match self.entities.get_mut::<Inventory>(entity) {
+            Some(inventory) => match inventory.equipped_in(slot) {
+                Some(item) => {
+                    inventory.equipped.retain(|&(s, _)| s != slot);
+                    item
+                },
+                None => return false,
+            },
+            None => return false,
+        };
+
+        self.events.push(Event::Unequipped {
+            name: self.name_of(entity),
+            item: self.item_label(item),
+            wielded: slot.is_wielded(),
+        });
+        true
+    }
+
+    /// The bonuses of everything `entity` has equipped.
+    fn equipment_of(&self, entity: EntityId) -> impl Iterator<Item = &Equippable> + '_ {
+        self.entities.get::<Inventory>(entity)
+            .into_iter()
+            .flat_map(|inventory| inventory.equipped.iter())
+            .filter_map(move |&(_, item)| self.entities.get::<Equippable>(item))
+    }
+
+    /// How `entity` fights in melee with its equipment: its own `Attack`, with the damage of its
+    /// weapon if it wields one. Entities without an `Attack` cannot fight at all.
+    pub fn attack_of(&self, entity: EntityId) -> Option<Attack> {
+        let mut attack = *self.entities.get::<Attack>(entity)?;
+        for equippable in self.equipment_of(entity) {
+            attack.to_hit += equippable.to_hit;
+            if let Some((damage, damage_type)) = equippable.damage {
+                attack.damage = damage;
+                attack.damage_type = damage_type;
+            }
+        }
+
+        Some(attack)
+    }
+
+    /// How hard `entity` is to hurt with its equipment on.
+    pub fn defence_of(&self, entity: EntityId) -> Defence {
+        let mut defence = self.entities.get::<Defence>(entity).cloned().unwrap_or_default();
+        for equippable in self.equipment_of(entity) {
+            defence.evasion += equippable.evasion;
+            defence.armour += equippable.armour;
+            for &resistance in &equippable.resistances {
+                if !defence.resistances.contains(&resistance) {
+                    defence.resistances.push(resistance);
+                }
+            }
+        }
+
+        defence
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::world::testing;
+
+    /// A world with the player standing on open floor, along with the player and where they are.
+    fn world() -> (World, EntityId, (i32, i32, i32)) {
+        let mut world = testing::flat_world();
+        let position = world.spawn_player((0, 0, 0)).expect("the floor is open");
+        let player = world.player.unwrap();
+        (world, player, position)
+    }
+
+    fn inventory(world: &World, entity: EntityId) -> &Inventory {
+        world.entities.get::<Inventory>(entity).expect("the player carries things")
+    }
+
+    /// Puts a new item from `template` straight into `entity`'s inventory.
+    fn give(world: &mut World, entity: EntityId, template: &ItemTemplate) -> EntityId {
+        let position = world.entities.position(entity).unwrap();
+        let item = world.spawn_item(template, 1, position);
+        world.give_item(entity, item);
+        item
+    }
+
+    #[test]
+    fn stackable_items_merge() {
+        let (mut world, player, position) = world();
+
+        let coins = world.spawn_item(&ItemTemplate::gold(), 5, position);
+        assert!(world.pick_up(player));
+        let more_coins = world.spawn_item(&ItemTemplate::gold(), 3, position);
+        let dagger = world.spawn_item(&ItemTemplate::dagger(), 1, position);
+        assert!(world.pick_up(player));
+
+        assert_eq!(inventory(&world, player).items, [coins, dagger]);
+        assert!(!world.entities.is_alive(more_coins));
+        assert_eq!(world.item_label(coins), "gold coin x8");
+        assert_eq!(world.carried_weight(player), 8 * 10 + 500);
+
+        let picked_up = |item: &str| Event::PickedUp { name: "you".into(), item: item.into() };
+        let expected = [picked_up("gold coin x5"), picked_up("gold coin x3"), picked_up("dagger")];
+        assert_eq!(world.take_events(), expected);
+    }
+
+    #[test]
+    fn items_over_the_weight_limit_stay_on_the_floor() {
+        let (mut world, player, position) = world();
+        world.entities.get_mut::<Inventory>(player).unwrap().max_weight = 1000;
+
+        let sword = world.spawn_item(&ItemTemplate::sword(), 1, position);
+        assert!(!world.pick_up(player));
+
+        assert!(inventory(&world, player).items.is_empty());
+        assert_eq!(world.entities.position(sword), Some(position));
+        let too_heavy = Event::TooHeavy { name: "you".into(), item: "sword".into() };
+        assert_eq!(world.take_events(), [too_heavy]);
+    }
+
+    #[test]
+    fn dropped_items_land_underfoot() {
+        let (mut world, player, position) = world();
+        let dagger = give(&mut world, player, &ItemTemplate::dagger());
+        assert!(world.equip(player, dagger));
+        world.take_events();
+
+        assert!(world.drop_item(player, dagger));
+        assert!(inventory(&world, player).items.is_empty());
+        assert_eq!(inventory(&world, player).equipped_in(EquipSlot::MainHand), None);
+        assert_eq!(world.entities.position(dagger), Some(position));
+        let dropped = Event::Dropped { name: "you".into(), item: "dagger".into() };
+        assert_eq!(world.take_events(), [dropped]);
+
+        assert!(!world.drop_item(player, dagger));
+    }
+
+    #[test]
+    fn equipping_replaces_what_is_in_the_slot() {
+        let (mut world, player, _) = world();
+        let dagger = give(&mut world, player, &ItemTemplate::dagger());
+        let sword = give(&mut world, player, &ItemTemplate::sword());
+
+        assert!(world.equip(player, dagger));
+        assert!(world.equip(player, sword));
+        assert_eq!(inventory(&world, player).equipped_in(EquipSlot::MainHand), Some(sword));
+        assert_eq!(inventory(&world, player).items, [dagger, sword]);
+
+        let names: Vec<_> = world.take_events().into_iter()
+            .map(|event| match event {
+                Event::Equipped { item, .. } => format!("on {}", item),
+                Event::Unequipped { item, .. } => format!("off {}", item),
+                other => panic!("unexpected event {:?}", other),
+            })
+            .collect();
+        assert_eq!(names, ["on dagger", "off dagger", "on sword"]);
+    }
+
+    #[test]
+    fn equipment_changes_combat_stats() {
+        let (mut world, player, _) = world();
+        let bare_attack = world.attack_of(player).unwrap();
+        let bare_defence = world.defence_of(player);
+
+        for template in &[ItemTemplate::dagger(), ItemTemplate::shield(), ItemTemplate::helmet()] {
+            let item = give(&mut world, player, template);
+            assert!(world.equip(player, item));
+        }
+        let armour = give(&mut world, player, &ItemTemplate::leather_armour());
+        assert!(world.equip(player, armour));
+
+        let attack = world.attack_of(player).unwrap();
+        assert_eq!(attack.to_hit, bare_attack.to_hit + 2);
+        assert_eq!(attack.damage, Dice::new(1, 4, 1));
+        assert_eq!(attack.damage_type, DamageType::Piercing);
+
+        let defence = world.defence_of(player);
+        assert_eq!(defence.evasion, bare_defence.evasion + 2);
+        assert_eq!(defence.armour, bare_defence.armour + 2);
+        assert!(defence.resistances.contains(&DamageType::Cold));
+
+        // Carried but not worn does nothing.
+        assert!(world.unequip(player, EquipSlot::Body));
+        assert_eq!(world.defence_of(player).armour, bare_defence.armour + 1);
+    }
+}
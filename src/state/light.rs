@@ -0,0 +1,264 @@
+//! Coloured lighting. Light comes from entities carrying a `Light` and spreads as far as the light's
+//! radius, fading with distance and stopping at anything opaque. On top of that every level has an
+//! ambient light set by the world generator, so the surface can be lit by day while dungeons are
+//! dark.
+//!
+//! Lighting is worked out lazily and cached at two levels. Each light remembers the tiles it lit,
+//! which only change when the light itself changes, moves, or the blocks around it are written to.
+//! Each region remembers its light levels, which only change when the lights reaching it do.
+
+use std::collections::HashMap;
+
+use crate::util::prelude::*;
+use crate::state::ecs::EntityId;
+use crate::state::world::{split_position, Tick, World};
+
+/// How quickly light fades towards the edge of its radius.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum Falloff {
+    /// Fades evenly with distance.
+    Linear,
+    /// Stays bright near the source and drops off sharply towards the edge.
+    Quadratic,
+}
+
+/// A point light carried by an entity.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Light {
+    /// The colour of the light at its source, with channels from 0 to 1.
+    pub colour: [f32; 3],
+    pub radius: u32,
+    pub falloff: Falloff,
+}
+
+impl Light {
+    /// How strong the light is `distance` tiles from its source, from 1 at the source to 0 just
+    /// beyond the radius.
+    pub fn intensity(&self, distance: f32) -> f32 {
+        let t = (distance / (self.radius as f32 + 1.0)).min(1.0);
+        match self.falloff {
+            Falloff::Linear => 1.0 - t,
+            Falloff::Quadratic => 1.0 - t * t,
+        }
+    }
+}
+
+/// The light levels of every tile in a region.
+#[derive(Clone, Debug)]
+pub struct RegionLight {
+    /// This vector is guaranteed to be REGION_DIM^2 in size.
+    pub levels: Vec<[f32; 3]>,
+    /// Changes whenever the levels do.
+    pub revision: u64,
+    /// The ambient light and the footprints of the lights reaching this region that the levels
+    /// were summed from.
+    sources: ([f32; 3], Vec<(EntityId, u64)>),
+}
+
+/// The versions of the regions a light's footprint covers.
+type RegionVersions = Vec<((i32, i32, i32), (Tick, u64))>;
+
+/// Everything a light's footprint depends on.
+#[derive(Clone, Debug, PartialEq)]
+struct FootprintKey {
+    position: (i32, i32, i32),
+    light: Light,
+    regions: RegionVersions,
+}
+
+/// The tiles lit by a single light, with how much light each receives.
+#[derive(Clone, Debug)]
+struct Footprint {
+    key: FootprintKey,
+    revision: u64,
+    lit: HashMap<(i32, i32, i32), [f32; 3]>,
+}
+
+/// The lighting caches of a world. These are rebuilt on demand and never saved.
+#[derive(Clone, Debug, Default)]
+pub struct Lighting {
+    footprints: HashMap<EntityId, Footprint>,
+    regions: HashMap<(i32, i32, i32), RegionLight>,
+    next_revision: u64,
+}
+
+impl Lighting {
+    /// The light levels of a region, if they have been worked out. Use
+    /// `World::update_region_light` to bring them up to date.
+    pub fn region(&self, offset: (i32, i32, i32)) -> Option<&RegionLight> {
+        self.regions.get(&offset)
+    }
+
+    /// Drops the cached light levels of a region that is no longer resident.
+    pub fn forget_region(&mut self, offset: (i32, i32, i32)) {
+        self.regions.remove(&offset);
+    }
+
+    fn next_revision(&mut self) -> u64 {
+        self.next_revision += 1;
+        self.next_revision
+    }
+}
+
+/// The region offsets covered by a square of the given radius around `pos`.
+fn regions_around((x, y, z): (i32, i32, i32), radius: i32) -> Vec<(i32, i32, i32)> {
+    let ((x1, y1, _), _) = split_position((x - radius, y - radius, z));
+    let ((x2, y2, _), _) = split_position((x + radius, y + radius, z));
+
+    let mut offsets = Vec::new();
+    for ry in y1..=y2 {
+        for rx in x1..=x2 {
+            offsets.push((rx, ry, z));
+        }
+    }
+
+    offsets
+}
+
+impl World {
+    /// Brings the light levels of the region at `offset` up to date and returns their revision.
+    /// Only lights that moved or changed, or whose surroundings were written to, are recomputed.
+    pub fn update_region_light(&mut self, offset: (i32, i32, i32)) -> u64 {
+        let mut lighting = std::mem::take(&mut self.lighting);
+
+        let entities = &self.entities;
+        lighting.footprints.retain(|&id, _| entities.is_alive(id));
+
+        let lights: Vec<_> = self.entities.query::<Light>()
+            .filter_map(|(id, &light)| Some((id, light, self.entities.position(id)?)))
+            .filter(|&(_, light, position)| {
+                position.2 == offset.2
+                    && regions_around(position, light.radius as i32).contains(&offset)
+            })
+            .collect();
+
+        let mut sources = Vec::with_capacity(lights.len());
+        for (id, light, position) in lights {
+            let regions = regions_around(position, light.radius as i32).into_iter()
+                .map(|region| (region, self.load_region(region).version()))
+                .collect();
+            let key = FootprintKey { position, light, regions };
+
+            let up_to_date = lighting.footprints.get(&id).map_or(false, |f| f.key == key);
+            if !up_to_date {
+                let footprint = Footprint {
+                    lit: self.light_footprint(position, light),
+                    revision: lighting.next_revision(),
+                    key,
+                };
+                lighting.footprints.insert(id, footprint);
+            }
+
+            sources.push((id, lighting.footprints[&id].revision));
+        }
+        sources.sort();
+
+        let sources = (self.ambient_light(offset.2), sources);
+        let up_to_date = lighting.regions.get(&offset).map_or(false, |r| r.sources == sources);
+        if !up_to_date {
+            let mut levels = vec![sources.0; REGION_LEN];
+            for &(id, _) in &sources.1 {
+                for (&pos, &[r, g, b]) in &lighting.footprints[&id].lit {
+                    let (region, idx) = split_position(pos);
+                    if region == offset {
+                        let level = &mut levels[idx];
+                        *level = [level[0] + r, level[1] + g, level[2] + b];
+                    }
+                }
+            }
+
+            let revision = lighting.next_revision();
+            lighting.regions.insert(offset, RegionLight { levels, revision, sources });
+        }
+
+        let revision = lighting.regions[&offset].revision;
+        self.lighting = lighting;
+        revision
+    }
+
+    /// The tiles `light` at `position` reaches, and how much of its colour each receives.
+    fn light_footprint(
+        &mut self,
+        position: (i32, i32, i32),
+        light: Light,
+    ) -> HashMap<(i32, i32, i32), [f32; 3]> {
+        let (x, y, _) = position;
+        let [r, g, b] = light.colour;
+
+        self.field_of_view(position, light.radius).into_iter()
+            .map(|pos| {
+                let (dx, dy) = ((pos.0 - x) as f32, (pos.1 - y) as f32);
+                let intensity = light.intensity((dx * dx + dy * dy).sqrt());
+                (pos, [r * intensity, g * intensity, b * intensity])
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::world::testing::flat_world;
+    use crate::state::world::{Block, BlockFill, MaterialId};
+
+    const TORCH: Light = Light {
+        colour: [1.0, 1.0, 1.0],
+        radius: 4,
+        falloff: Falloff::Linear,
+    };
+
+    /// A world with a torch in the middle of region (0, 0, 0), and the torch.
+    fn lit_world() -> (World, EntityId) {
+        let mut world = flat_world();
+        let torch = world.entities.spawn();
+        world.entities.insert(torch, TORCH);
+        world.entities.set_position(torch, (8, 8, 0));
+        (world, torch)
+    }
+
+    fn footprint_revision(world: &World, id: EntityId) -> u64 {
+        world.lighting.footprints[&id].revision
+    }
+
+    #[test]
+    fn unchanged_lights_are_reused() {
+        let (mut world, torch) = lit_world();
+        let revision = world.update_region_light((0, 0, 0));
+        let footprint = footprint_revision(&world, torch);
+
+        assert_eq!(world.update_region_light((0, 0, 0)), revision);
+        assert_eq!(footprint_revision(&world, torch), footprint);
+
+        // Writing to a region the light does not reach leaves it alone.
+        world.set_block((40, 40, 0), Block::new(BlockFill::Solid(MaterialId::STONE)));
+        assert_eq!(world.update_region_light((0, 0, 0)), revision);
+        assert_eq!(footprint_revision(&world, torch), footprint);
+    }
+
+    #[test]
+    fn writes_within_the_footprint_relight() {
+        let (mut world, torch) = lit_world();
+        let revision = world.update_region_light((0, 0, 0));
+        let footprint = footprint_revision(&world, torch);
+        let (_, behind) = split_position((11, 8, 0));
+        let before = world.lighting.region((0, 0, 0)).unwrap().levels[behind];
+
+        world.set_block((10, 8, 0), Block::new(BlockFill::Solid(MaterialId::STONE)));
+        assert_ne!(world.update_region_light((0, 0, 0)), revision);
+        assert_ne!(footprint_revision(&world, torch), footprint);
+
+        let after = world.lighting.region((0, 0, 0)).unwrap().levels[behind];
+        assert!(after[0] < before[0], "{:?} should be darker than {:?}", after, before);
+    }
+
+    #[test]
+    fn moving_a_light_relights() {
+        let (mut world, torch) = lit_world();
+        let revision = world.update_region_light((0, 0, 0));
+        let footprint = footprint_revision(&world, torch);
+
+        world.entities.set_position(torch, (9, 8, 0));
+        assert_ne!(world.update_region_light((0, 0, 0)), revision);
+        assert_ne!(footprint_revision(&world, torch), footprint);
+    }
+}
@@ -0,0 +1,182 @@
+//! The material registry gives each `MaterialId` its meaning: how blocks made of the material are
+//! drawn and how they behave. The registry is loaded from a RON file; see
+//! `resources/materials.ron` for the format.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::state::world::{BlockFill, MaterialId};
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct Material {
+    pub id: MaterialId,
+    pub name: String,
+    /// Drawn for solid blocks of this material.
+    pub glyph: String,
+    /// Drawn for floors of this material.
+    #[serde(default = "default_floor_glyph")]
+    pub floor_glyph: String,
+    pub fg: [f32; 4],
+    pub bg: [f32; 4],
+    /// Whether a solid block of this material blocks sight.
+    pub opaque: bool,
+    /// Whether a floor of this material can be stood on.
+    pub walkable: bool,
+    /// How long the material takes to dig through, in tenths of a turn. Zero is instant.
+    pub hardness: u32,
+}
+
+fn default_floor_glyph() -> String {
+    ".".to_string()
+}
+
+#[derive(Clone, Debug)]
+pub struct MaterialRegistry {
+    materials: HashMap<MaterialId, Material>,
+    /// Handed out for ids missing from the registry, so that a bad data file shows up on screen
+    /// instead of crashing.
+    unknown: Material,
+}
+
+impl MaterialRegistry {
+    pub fn from_ron(text: &str) -> Result<Self, String> {
+        let list: Vec<Material> = ron::de::from_str(text).map_err(|e| e.to_string())?;
+
+        let mut materials = HashMap::new();
+        for material in list {
+            if let Some(previous) = materials.insert(material.id, material) {
+                return Err(format!("material id {:?} is defined more than once", previous.id));
+            }
+        }
+
+        Ok(Self {
+            materials,
+            unknown: Material {
+                id: MaterialId::UNKNOWN,
+                name: "unknown".to_string(),
+                glyph: "?".to_string(),
+                floor_glyph: "?".to_string(),
+                fg: [1.0, 0.0, 1.0, 1.0],
+                bg: [0.0, 0.0, 0.0, 1.0],
+                opaque: true,
+                walkable: true,
+                hardness: 0,
+            },
+        })
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+
+        Self::from_ron(&text).map_err(|e| format!("failed to parse {}: {}", path.display(), e))
+    }
+
+    /// Whether `id` has an entry in the registry.
+    pub fn contains(&self, id: MaterialId) -> bool {
+        self.materials.contains_key(&id)
+    }
+
+    /// Looks up a material. Ids missing from the registry get a loud placeholder material.
+    pub fn get(&self, id: MaterialId) -> &Material {
+        self.materials.get(&id).unwrap_or(&self.unknown)
+    }
+
+    /// Whether something standing in a block with this fill can see through it.
+    pub fn blocks_sight(&self, fill: BlockFill) -> bool {
+        match fill {
+            BlockFill::Solid(m) => self.get(m).opaque,
+            _ => false,
+        }
+    }
+
+    /// Whether a walker can enter a block with this fill.
+    pub fn blocks_movement(&self, fill: BlockFill) -> bool {
+        match fill {
+            BlockFill::Solid(_) => true,
+            BlockFill::Floor(m) | BlockFill::FloorCeiling(m, _) => !self.get(m).walkable,
+            BlockFill::Ceiling(_) | BlockFill::Empty => false,
+        }
+    }
+}
+
+/// The registry shipped in `resources/materials.ron`, built into the binary.
+impl Default for MaterialRegistry {
+    fn default() -> Self {
+        Self::from_ron(include_str!("../../resources/materials.ron"))
+            .expect("the built-in material registry is malformed")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A minimal material entry for `id`.
+    fn entry(id: u32, name: &str) -> String {
+        format!(
+            "(id: {}, name: \"{}\", glyph: \"#\", fg: (1.0, 1.0, 1.0, 1.0), \
+             bg: (0.0, 0.0, 0.0, 1.0), opaque: true, walkable: true, hardness: 10)",
+            id, name,
+        )
+    }
+
+    #[test]
+    fn shipped_materials_load() {
+        // Relative to the crate root, where tests run.
+        let path = Path::new(file!()).ancestors().nth(3).unwrap().join("resources/materials.ron");
+        let materials = MaterialRegistry::load(path).unwrap();
+
+        let placed = [
+            MaterialId::STONE,
+            MaterialId::GRANITE,
+            MaterialId::DIRT,
+            MaterialId::GRASS,
+            MaterialId::SAND,
+            MaterialId::WATER,
+            MaterialId::WOOD,
+            MaterialId::SNOW,
+            MaterialId::TREE,
+        ];
+        for &id in &placed {
+            assert!(materials.contains(id), "{:?} is missing", id);
+            assert_eq!(materials.get(id).id, id);
+        }
+        assert_eq!(materials.get(MaterialId::STONE).name, "stone");
+        assert_eq!(materials.get(MaterialId::STONE).floor_glyph, ".");
+
+        assert!(materials.blocks_sight(BlockFill::Solid(MaterialId::STONE)));
+        assert!(!materials.blocks_sight(BlockFill::Floor(MaterialId::STONE)));
+        assert!(materials.blocks_movement(BlockFill::Solid(MaterialId::STONE)));
+        assert!(!materials.blocks_movement(BlockFill::Floor(MaterialId::STONE)));
+        assert!(materials.blocks_movement(BlockFill::Floor(MaterialId::WATER)));
+        assert!(!materials.blocks_movement(BlockFill::Empty));
+    }
+
+    #[test]
+    fn duplicate_ids_are_rejected() {
+        let text = format!("[{}, {}]", entry(0, "stone"), entry(1, "granite"));
+        assert!(MaterialRegistry::from_ron(&text).is_ok());
+
+        let text = format!("[{}, {}]", entry(0, "stone"), entry(0, "also stone"));
+        let error = MaterialRegistry::from_ron(&text).unwrap_err();
+        assert!(error.contains("more than once"), "{}", error);
+    }
+
+    #[test]
+    fn unknown_ids_get_a_placeholder() {
+        let materials = MaterialRegistry::from_ron(&format!("[{}]", entry(0, "stone"))).unwrap();
+
+        assert!(!materials.contains(MaterialId::GRANITE));
+        let unknown = materials.get(MaterialId::GRANITE);
+        assert_eq!(unknown.id, MaterialId::UNKNOWN);
+        assert_eq!(unknown.glyph, "?");
+
+        // Unknown floors can be walked on and unknown walls cannot be seen through.
+        assert!(!materials.blocks_movement(BlockFill::Floor(MaterialId::GRANITE)));
+        assert!(materials.blocks_sight(BlockFill::Solid(MaterialId::GRANITE)));
+    }
+}
@@ -0,0 +1,264 @@
+//! The message log: what the player has been told, oldest first. Messages are made of coloured
+//! spans and stamped with the tick they were logged on. A message that repeats the one before it
+//! is not logged again; the earlier one counts the repeat instead, and reads "... x3". Messages
+//! about the same thing that differ only in details, such as hits doing different damage, repeat
+//! each other too, and the merged message leaves the details out.
+//!
+//! The log is part of the interface rather than the world, so it is not saved.
+
+use std::collections::VecDeque;
+
+use crate::state::event::{AttackOutcome, Event};
+use crate::state::world::Tick;
+
+/// How many messages are kept before the oldest are forgotten.
+pub const MESSAGE_LOG_CAPACITY: usize = 500;
+
+/// The colour damage numbers are picked out in.
+const DAMAGE_FG: [f32; 4] = [1.0, 0.35, 0.25, 1.0];
+/// The colour repeat counts are drawn in.
+const REPEAT_FG: [f32; 4] = [0.6, 0.6, 0.6, 1.0];
+
+/// How much a message matters to the player, which decides its colour.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Severity {
+    /// Routine goings-on.
+    Info,
+    /// Something went the player's way.
+    Good,
+    /// Something the player should pay attention to.
+    Warning,
+    /// Something that could get the player killed, or did.
+    Danger,
+}
+
+impl Severity {
+    pub fn colour(self) -> [f32; 4] {
+        match self {
+            Severity::Info => [0.8, 0.8, 0.8, 1.0],
+            Severity::Good => [0.45, 0.9, 0.45, 1.0],
+            Severity::Warning => [1.0, 0.8, 0.3, 1.0],
+            Severity::Danger => [1.0, 0.3, 0.3, 1.0],
+        }
+    }
+}
+
+/// A run of text in one colour.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Span {
+    pub text: String,
+    pub fg: [f32; 4],
+}
+
+impl Span {
+    pub fn new(text: impl Into<String>, fg: [f32; 4]) -> Self {
+        Self {
+            text: text.into(),
+            fg,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Message {
+    /// When the message was last logged.
+    pub tick: Tick,
+    pub severity: Severity,
+    pub spans: Vec<Span>,
+    /// How many times in a row the message was logged.
+    pub count: u32,
+    /// The text with the details that vary between repeats left out, for messages that have any.
+    /// Messages with the same summary merge, and read as the summary once their details differ.
+    pub summary: Option<String>,
+}
+
+impl Message {
+    /// A message in the colour of its severity throughout.
+    pub fn plain(tick: Tick, severity: Severity, text: impl Into<String>) -> Self {
+        Self {
+            tick,
+            severity,
+            spans: vec![Span::new(text, severity.colour())],
+            count: 1,
+            summary: None,
+        }
+    }
+
+    /// The message for a world event.
+    pub fn from_event(tick: Tick, event: &Event) -> Self {
+        let text = event.to_string();
+        let severity = match event {
+            Event::Attacked { target, outcome: AttackOutcome::Hit { critical: true, .. }, .. }
+                if target == "you" => Severity::Danger,
+            Event::Attacked { target, outcome: AttackOutcome::Hit { .. }, .. }
+                if target == "you" => Severity::Warning,
+            Event::Died { name } if name == "you" => Severity::Danger,
+            Event::Died { .. } => Severity::Good,
+            Event::TooHeavy { .. } => Severity::Warning,
+            _ => Severity::Info,
+        };
+
+        let mut message = Self::plain(tick, severity, text);
+
+        // Pick the damage out of the rest of the sentence. Hits merge whatever damage they do.
+        if let Event::Attacked { outcome: AttackOutcome::Hit { damage, .. }, .. } = event {
+            let (text, damage) = (message.text(), damage.to_string());
+            if let Some(at) = text.rfind(&format!(" for {}", damage)) {
+                let (start, end) = (at + " for ".len(), at + " for ".len() + damage.len());
+                message.spans = vec![
+                    Span::new(&text[..start], severity.colour()),
+                    Span::new(&text[start..end], DAMAGE_FG),
+                    Span::new(&text[end..], severity.colour()),
+                ];
+                message.summary = Some(format!("{}{}", &text[..at], &text[end..]));
+            }
+        }
+
+        message
+    }
+
+    /// The text of the message without colours or repeat count.
+    pub fn text(&self) -> String {
+        self.spans.iter().map(|span| &*span.text).collect()
+    }
+
+    /// The spans of the message followed by its repeat count, if it has been repeated.
+    pub fn display_spans(&self) -> Vec<Span> {
+        let mut spans = self.spans.clone();
+        if self.count > 1 {
+            spans.push(Span::new(format!(" x{}", self.count), REPEAT_FG));
+        }
+        spans
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MessageLog {
+    messages: VecDeque<Message>,
+    capacity: usize,
+    /// How many messages back from the newest the log is scrolled. Zero shows the newest.
+    scroll: usize,
+}
+
+impl Default for MessageLog {
+    fn default() -> Self {
+        Self::new(MESSAGE_LOG_CAPACITY)
+    }
+}
+
+impl MessageLog {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            messages: VecDeque::with_capacity(capacity),
+            capacity: capacity.max(1),
+            scroll: 0,
+        }
+    }
+
+    /// Logs `message`, or counts it as a repeat if it reads the same as the newest message or
+    /// has the same summary. New messages scroll the log back down to the newest.
+    pub fn push(&mut self, message: Message) {
+        self.scroll = 0;
+
+        if let Some(last) = self.messages.back_mut() {
+            let same_summary = last.summary.is_some() && last.summary == message.summary;
+            if last.severity == message.severity && (last.spans == message.spans || same_summary) {
+                if last.spans != message.spans {
+                    let summary = message.summary.unwrap_or_default();
+                    last.spans = vec![Span::new(summary, message.severity.colour())];
+                }
+                last.count += message.count;
+                last.tick = message.tick;
+                return;
+            }
+        }
+
+        if self.messages.len() == self.capacity {
+            self.messages.pop_front();
+        }
+        self.messages.push_back(message);
+    }
+
+    /// Logs a message for each of `events`, all stamped with `tick`.
+    pub fn record(&mut self, tick: Tick, events: &[Event]) {
+        for event in events {
+            self.push(Message::from_event(tick, event));
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    /// Every message in the log, oldest first.
+    pub fn messages(&self) -> impl DoubleEndedIterator<Item = &Message> + ExactSizeIterator {
+        self.messages.iter()
+    }
+
+    /// The `rows` messages showing at the current scroll position, oldest first.
+    pub fn visible(&self, rows: usize) -> impl Iterator<Item = &Message> {
+        let end = self.messages.len() - self.scroll;
+        self.messages.range(end.saturating_sub(rows)..end)
+    }
+
+    pub fn scroll(&self) -> usize {
+        self.scroll
+    }
+
+    /// Scrolls `lines` messages back towards the oldest, stopping once the oldest message is the
+    /// first of `rows` on show.
+    pub fn scroll_up(&mut self, lines: usize, rows: usize) {
+        let max = self.messages.len().saturating_sub(rows);
+        self.scroll = (self.scroll + lines).min(max);
+    }
+
+    /// Scrolls `lines` messages forward towards the newest.
+    pub fn scroll_down(&mut self, lines: usize) {
+        self.scroll = self.scroll.saturating_sub(lines);
+    }
+
+    pub fn scroll_to_newest(&mut self) {
+        self.scroll = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::combat::DamageType;
+
+    fn hit(target: &'static str, damage: i32) -> Event {
+        Event::Attacked {
+            attacker: "you".into(),
+            target: target.into(),
+            ranged: false,
+            outcome: AttackOutcome::Hit { damage, damage_type: DamageType::Blunt, critical: false },
+        }
+    }
+
+    fn lines(log: &MessageLog) -> Vec<String> {
+        log.messages()
+            .map(|message| message.display_spans().into_iter().map(|span| span.text).collect())
+            .collect()
+    }
+
+    #[test]
+    fn hits_on_the_same_target_merge_whatever_their_damage() {
+        let tick = Tick::new(1);
+
+        let mut log = MessageLog::default();
+        log.record(tick, &[hit("rat", 3), hit("rat", 3)]);
+        assert_eq!(lines(&log), ["You hit the rat for 3. x2"]);
+
+        log.record(tick + 1, &[hit("rat", 5)]);
+        assert_eq!(lines(&log), ["You hit the rat. x3"]);
+        assert_eq!(log.messages().next().unwrap().tick, tick + 1);
+
+        log.record(tick + 2, &[hit("bat", 5)]);
+        assert_eq!(lines(&log), ["You hit the rat. x3", "You hit the bat for 5."]);
+    }
+}
@@ -1,5 +1,31 @@
+pub mod action;
+pub mod ai;
+pub mod combat;
+pub mod ecs;
+pub mod event;
+pub mod fov;
+pub mod item;
+pub mod light;
+pub mod material;
+pub mod message;
+pub mod monster;
+pub mod path;
+pub mod scheduler;
 pub mod world;
 
 pub mod prelude {
+    pub use super::action::*;
+    pub use super::ai::*;
+    pub use super::combat::*;
+    pub use super::ecs::*;
+    pub use super::event::*;
+    pub use super::fov::*;
+    pub use super::item::*;
+    pub use super::light::*;
+    pub use super::material::*;
+    pub use super::message::*;
+    pub use super::monster::*;
+    pub use super::path::*;
+    pub use super::scheduler::*;
     pub use super::world::*;
 }
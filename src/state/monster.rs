@@ -0,0 +1,151 @@
+//! The kinds of monsters there are, and putting them into the world.
+
+use std::borrow::Cow;
+
+use rand::Rng;
+
+use crate::state::ai::Ai;
+use crate::state::combat::{Attack, DamageType, Defence, Dice, RangedAttack};
+use crate::state::ecs::{Appearance, Blocking, EntityId, Health, Name};
+use crate::state::fov::Vision;
+use crate::state::item::Inventory;
+use crate::state::scheduler::Actor;
+use crate::state::world::gen::seeded_rng;
+use crate::state::world::World;
+
+/// Salt for the rng that places monsters.
+const POPULATE_SALT: u64 = 0x4D4F_4E53;
+
+/// Everything needed to spawn a kind of monster.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MonsterTemplate {
+    pub name: Cow<'static, str>,
+    pub glyph: Cow<'static, str>,
+    pub fg: [f32; 4],
+    pub speed: u32,
+    pub sight: u32,
+    pub health: i32,
+    pub attack: Attack,
+    pub ranged: Option<RangedAttack>,
+    pub defence: Defence,
+    /// How much the monster can carry, in grams. Whatever it carries is dropped when it dies.
+    pub max_weight: u32,
+}
+
+impl MonsterTemplate {
+    pub fn rat() -> Self {
+        Self {
+            name: "rat".into(),
+            glyph: "r".into(),
+            fg: [0.6, 0.45, 0.3, 1.0],
+            speed: 120,
+            sight: 6,
+            health: 4,
+            attack: Attack {
+                to_hit: 0,
+                damage: Dice::new(1, 3, 0),
+                damage_type: DamageType::Piercing,
+            },
+            ranged: None,
+            defence: Defence { evasion: 2, ..Defence::default() },
+            max_weight: 0,
+        }
+    }
+
+    pub fn goblin() -> Self {
+        Self {
+            name: "goblin".into(),
+            glyph: "g".into(),
+            fg: [0.3, 0.8, 0.3, 1.0],
+            speed: 100,
+            sight: 8,
+            health: 10,
+            attack: Attack {
+                to_hit: 1,
+                damage: Dice::new(1, 6, 0),
+                damage_type: DamageType::Slashing,
+            },
+            // Goblins throw rocks at whatever they can't reach.
+            ranged: Some(RangedAttack {
+                to_hit: 0,
+                damage: Dice::new(1, 4, 0),
+                damage_type: DamageType::Blunt,
+                range: 6,
+            }),
+            defence: Defence { evasion: 1, ..Defence::default() },
+            max_weight: 10_000,
+        }
+    }
+
+    pub fn orc() -> Self {
+        Self {
+            name: "orc".into(),
+            glyph: "o".into(),
+            fg: [0.8, 0.3, 0.2, 1.0],
+            speed: 80,
+            sight: 8,
+            health: 18,
+            attack: Attack {
+                to_hit: 2,
+                damage: Dice::new(2, 4, 1),
+                damage_type: DamageType::Blunt,
+            },
+            ranged: None,
+            defence: Defence { evasion: 0, armour: 1, resistances: vec![DamageType::Poison] },
+            max_weight: 30_000,
+        }
+    }
+}
+
+impl World {
+    /// Creates a monster from `template` at `pos`, making its home there, and gives it turns.
+    pub fn spawn_monster(&mut self, template: &MonsterTemplate, pos: (i32, i32, i32)) -> EntityId {
+        let monster = self.entities.spawn();
+        self.entities.insert(monster, Name(template.name.clone()));
+        let appearance = Appearance { glyph: template.glyph.clone(), fg: template.fg };
+        self.entities.insert(monster, appearance);
+        self.entities.insert(monster, Blocking);
+        self.entities.insert(monster, Actor { speed: template.speed });
+        self.entities.insert(monster, Vision { radius: template.sight });
+        self.entities.insert(monster, Health::new(template.health));
+        self.entities.insert(monster, template.attack);
+        if let Some(ranged) = template.ranged {
+            self.entities.insert(monster, ranged);
+        }
+        self.entities.insert(monster, template.defence.clone());
+        self.entities.insert(monster, Inventory::new(template.max_weight));
+        self.entities.insert(monster, Ai::new(pos));
+        self.entities.set_position(monster, pos);
+        self.schedule_actor(monster);
+
+        monster
+    }
+
+    /// Scatters `count` monsters picked from `templates` over free floor within `radius` tiles of
+    /// `center`. Placement is decided by the world seed, so the same world is always populated the
+    /// same way. Returns the monsters placed, which may be fewer than `count` if free floor is
+    /// hard to find.
+    pub fn populate(
+        &mut self,
+        templates: &[MonsterTemplate],
+        count: u32,
+        center: (i32, i32, i32),
+        radius: i32,
+    ) -> Vec<EntityId> {
+        let mut rng = seeded_rng(self.seed ^ POPULATE_SALT, center.2 as i64 as u64);
+        let mut monsters = Vec::new();
+
+        if templates.is_empty() {
+            return monsters;
+        }
+
+        for _ in 0..count {
+            let template = &templates[rng.gen_range(0, templates.len())];
+            if let Some(pos) = self.random_free_tile(&mut rng, center, radius) {
+                monsters.push(self.spawn_monster(template, pos));
+            }
+        }
+
+        monsters
+    }
+}
@@ -0,0 +1,465 @@
+//! Pathfinding over global tile coordinates: A* for single routes and Dijkstra maps for flow
+//! fields with many sources. Both read the moves available from each tile out of a navigation
+//! cache kept per region, so searches cross region boundaries freely and follow stairs, ladders
+//! and holes between levels.
+//!
+//! The cache only knows about terrain. Blocking entities are not taken into account; whoever
+//! follows a path has to deal with being bumped into.
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+use crate::util::prelude::*;
+use crate::state::world::{split_position, BlockFill, Direction, Tick, World};
+
+/// A single move between neighbouring tiles.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Step {
+    Orthogonal,
+    Diagonal,
+    /// Up or down a connector.
+    Climb,
+    /// Down a hole to the level below. There is no way back up the same way.
+    Fall,
+}
+
+/// What each kind of step costs. Searches minimise the total cost of a path.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MoveCosts {
+    pub orthogonal: u32,
+    pub diagonal: u32,
+    pub climb: u32,
+    pub fall: u32,
+}
+
+impl Default for MoveCosts {
+    fn default() -> Self {
+        Self {
+            orthogonal: 100,
+            diagonal: 141,
+            climb: 100,
+            fall: 0,
+        }
+    }
+}
+
+impl MoveCosts {
+    pub fn cost(&self, step: Step) -> u32 {
+        match step {
+            Step::Orthogonal => self.orthogonal,
+            Step::Diagonal => self.diagonal,
+            Step::Climb => self.climb,
+            Step::Fall => self.fall,
+        }
+    }
+
+    /// A lower bound on the cost of getting from `from` to `to`, for A*. Moves between levels
+    /// may be free, so only the distance within a level counts. Cheap steps stand in for dear
+    /// ones: two orthogonal steps make a diagonal one, and two diagonal steps a straight line.
+    fn estimate(&self, from: (i32, i32, i32), to: (i32, i32, i32)) -> u64 {
+        let dx = (from.0 - to.0).unsigned_abs() as u64;
+        let dy = (from.1 - to.1).unsigned_abs() as u64;
+        let (long, short) = (dx.max(dy), dx.min(dy));
+        let diagonal = (self.diagonal as u64).min(2 * self.orthogonal as u64);
+        let straight = (self.orthogonal as u64).min(self.diagonal as u64);
+
+        short * diagonal + (long - short) * straight
+    }
+}
+
+/// The tiles that can be reached from a tile in a single step, and how.
+type Moves = Vec<((i32, i32, i32), Step)>;
+
+/// Regions along with the versions of them something was worked out from.
+type SourceVersions = Vec<((i32, i32, i32), (Tick, u64))>;
+
+/// The moves out of every tile in a region, along with the versions of the regions they were
+/// worked out from.
+#[derive(Clone, Debug)]
+struct RegionNav {
+    sources: SourceVersions,
+    /// This vector is guaranteed to be REGION_DIM^2 in size.
+    moves: Vec<Moves>,
+}
+
+/// The navigation caches of a world. These are rebuilt on demand and never saved.
+#[derive(Clone, Debug, Default)]
+pub struct Navigation {
+    regions: HashMap<(i32, i32, i32), RegionNav>,
+}
+
+impl Navigation {
+    /// Drops the cached moves of a region that is no longer resident.
+    pub fn forget_region(&mut self, offset: (i32, i32, i32)) {
+        self.regions.remove(&offset);
+    }
+}
+
+/// The regions whose blocks decide the moves out of the region at `offset`: its neighbours on the
+/// same level, and the region below for holes.
+fn nav_sources(offset: (i32, i32, i32)) -> Vec<(i32, i32, i32)> {
+    let (x, y, z) = offset;
+
+    let mut sources = Vec::with_capacity(10);
+    for dy in -1..=1 {
+        for dx in -1..=1 {
+            sources.push((x + dx, y + dy, z));
+        }
+    }
+    sources.push((x, y, z - 1));
+
+    sources
+}
+
+/// Cost per tile of distance from the sources, as kept in a `DijkstraMap`. Lower is closer.
+#[derive(Clone, Debug, Default)]
+pub struct DijkstraMap {
+    values: HashMap<(i32, i32, i32), i64>,
+}
+
+impl DijkstraMap {
+    /// The value of the tile at `pos`, or `None` if the map never reached it.
+    pub fn value(&self, pos: (i32, i32, i32)) -> Option<i64> {
+        self.values.get(&pos).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+impl World {
+    /// The moves that can be made out of the tile at `pos`, ignoring entities.
+    pub fn moves_from(&mut self, pos: (i32, i32, i32)) -> Moves {
+        let (offset, idx) = split_position(pos);
+
+        let sources: SourceVersions = nav_sources(offset).into_iter()
+            .map(|source| (source, self.load_region(source).version()))
+            .collect();
+
+        let up_to_date = self.navigation.regions.get(&offset)
+            .map_or(false, |nav| nav.sources == sources);
+        if !up_to_date {
+            let moves = (0..REGION_LEN).map(|idx| self.find_moves(offset, idx)).collect();
+            self.navigation.regions.insert(offset, RegionNav { sources, moves });
+        }
+
+        self.navigation.regions[&offset].moves[idx].clone()
+    }
+
+    fn find_moves(&mut self, offset: (i32, i32, i32), idx: usize) -> Moves {
+        let dim = REGION_DIM as i32;
+        let pos = (
+            offset.0 * dim + idx as i32 % dim,
+            offset.1 * dim + idx as i32 / dim,
+            offset.2,
+        );
+        let block = self.block_at(pos).clone();
+
+        let mut moves = Vec::new();
+        match block.fill {
+            BlockFill::Solid(_) => return moves,
+            // Nothing to stand on: whatever is here falls, unless it lands on the block below.
+            BlockFill::Empty | BlockFill::Ceiling(_) => {
+                let below = (pos.0, pos.1, pos.2 - 1);
+                if !matches!(self.block_at(below).fill, BlockFill::Solid(_)) {
+                    moves.push((below, Step::Fall));
+                    return moves;
+                }
+            },
+            BlockFill::Floor(_) | BlockFill::FloorCeiling(_, _) => {},
+        }
+
+        for &direction in &Direction::ALL {
+            let target = direction.step(pos);
+            let fill = self.block_at(target).fill;
+            if !self.materials.blocks_movement(fill) {
+                let (dx, dy) = direction.delta();
+                let step = if dx != 0 && dy != 0 { Step::Diagonal } else { Step::Orthogonal };
+                moves.push((target, step));
+            }
+        }
+
+        if let Some(connector) = block.connector {
+            if connector.leads_up() {
+                moves.push(((pos.0, pos.1, pos.2 + 1), Step::Climb));
+            }
+            if connector.leads_down() {
+                moves.push(((pos.0, pos.1, pos.2 - 1), Step::Climb));
+            }
+        }
+
+        moves
+    }
+
+    /// Finds the cheapest path from `from` to `to` with A*. The path starts with the first tile
+    /// after `from` and ends with `to`, so it is empty when the two are the same. Gives up and
+    /// returns `None` once `limit` tiles have been explored without reaching `to`, so that
+    /// searches for unreachable tiles stay bounded.
+    pub fn find_path(
+        &mut self,
+        from: (i32, i32, i32),
+        to: (i32, i32, i32),
+        costs: &MoveCosts,
+        limit: usize,
+    ) -> Option<Vec<(i32, i32, i32)>> {
+        let mut open = BinaryHeap::new();
+        let mut best: HashMap<(i32, i32, i32), u64> = HashMap::new();
+        let mut came_from: HashMap<(i32, i32, i32), (i32, i32, i32)> = HashMap::new();
+
+        open.push(Reverse((costs.estimate(from, to), 0, from)));
+        best.insert(from, 0);
+
+        let mut explored = 0;
+        while let Some(Reverse((_, cost, pos))) = open.pop() {
+            if pos == to {
+                if to == from {
+                    return Some(Vec::new());
+                }
+
+                let mut path = vec![to];
+                let mut at = to;
+                while let Some(&previous) = came_from.get(&at) {
+                    if previous == from {
+                        break;
+                    }
+                    path.push(previous);
+                    at = previous;
+                }
+                path.reverse();
+                return Some(path);
+            }
+
+            // Skip entries superseded by a cheaper route found since they were queued.
+            if best.get(&pos).map_or(false, |&b| cost > b) {
+                continue;
+            }
+
+            explored += 1;
+            if explored > limit {
+                return None;
+            }
+
+            for (next, step) in self.moves_from(pos) {
+                let next_cost = cost + costs.cost(step) as u64;
+                if best.get(&next).map_or(true, |&b| next_cost < b) {
+                    best.insert(next, next_cost);
+                    came_from.insert(next, pos);
+                    open.push(Reverse((next_cost + costs.estimate(next, to), next_cost, next)));
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Builds a Dijkstra map spreading out from `sources`, which all start at zero. Tiles costing
+    /// more than `max_cost` to reach are left out. Distances are measured outwards from the
+    /// sources, so one-way moves such as falls count in the direction away from them.
+    pub fn dijkstra_map(
+        &mut self,
+        sources: &[(i32, i32, i32)],
+        costs: &MoveCosts,
+        max_cost: i64,
+    ) -> DijkstraMap {
+        let seeds = sources.iter().map(|&pos| (pos, 0)).collect();
+        self.relax_dijkstra_map(seeds, costs, max_cost)
+    }
+
+    /// Turns an approach map into one for running away: following it downhill leads away from
+    /// the sources, but towards open space rather than into the nearest dead end. `factor` is how
+    /// strongly distance is preferred over open space; around 1.2 works well.
+    pub fn flee_map(
+        &mut self,
+        approach: &DijkstraMap,
+        costs: &MoveCosts,
+        factor: f32,
+    ) -> DijkstraMap {
+        let seeds = approach.values.iter()
+            .map(|(&pos, &value)| (pos, -(value as f32 * factor) as i64))
+            .collect();
+        let max_cost = approach.values.values().copied().max().unwrap_or(0);
+        self.relax_dijkstra_map(seeds, costs, max_cost)
+    }
+
+    fn relax_dijkstra_map(
+        &mut self,
+        seeds: Vec<((i32, i32, i32), i64)>,
+        costs: &MoveCosts,
+        max_cost: i64,
+    ) -> DijkstraMap {
+        let mut values: HashMap<(i32, i32, i32), i64> = HashMap::new();
+        let mut open = BinaryHeap::new();
+        for (pos, value) in seeds {
+            if values.get(&pos).map_or(true, |&v| value < v) {
+                values.insert(pos, value);
+                open.push(Reverse((value, pos)));
+            }
+        }
+
+        while let Some(Reverse((value, pos))) = open.pop() {
+            if values.get(&pos).map_or(false, |&v| value > v) {
+                continue;
+            }
+
+            for (next, step) in self.moves_from(pos) {
+                let next_value = value + costs.cost(step) as i64;
+                if next_value > max_cost {
+                    continue;
+                }
+
+                if values.get(&next).map_or(true, |&v| next_value < v) {
+                    values.insert(next, next_value);
+                    open.push(Reverse((next_value, next)));
+                }
+            }
+        }
+
+        DijkstraMap { values }
+    }
+
+    /// The best move out of `pos` according to `map`: the reachable neighbour with the lowest
+    /// value, provided it is lower than the value at `pos`.
+    pub fn downhill(
+        &mut self,
+        map: &DijkstraMap,
+        pos: (i32, i32, i32),
+    ) -> Option<(i32, i32, i32)> {
+        let here = map.value(pos).unwrap_or(i64::MAX);
+
+        self.moves_from(pos).into_iter()
+            .filter_map(|(next, _)| Some((map.value(next)?, next)))
+            .filter(|&(value, _)| value < here)
+            .min()
+            .map(|(_, next)| next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::world::testing;
+    use crate::state::world::{Block, Connector, MaterialId};
+
+    const DIM: i32 = REGION_DIM as i32;
+
+    fn wall() -> Block {
+        Block::new(BlockFill::Solid(MaterialId::STONE))
+    }
+
+    /// What following `path` from `from` costs.
+    fn path_cost(from: (i32, i32, i32), path: &[(i32, i32, i32)], costs: &MoveCosts) -> u64 {
+        let mut previous = from;
+        path.iter()
+            .map(|&pos| {
+                let step = if pos.2 != previous.2 {
+                    Step::Climb
+                } else if pos.0 != previous.0 && pos.1 != previous.1 {
+                    Step::Diagonal
+                } else {
+                    Step::Orthogonal
+                };
+                previous = pos;
+                costs.cost(step) as u64
+            })
+            .sum()
+    }
+
+    #[test]
+    fn paths_cross_region_borders() {
+        let mut world = testing::flat_world();
+        let costs = MoveCosts::default();
+
+        // A wall along the border with a single gap in it.
+        for y in -4..=4 {
+            if y != 3 {
+                world.set_block((DIM, y, 0), wall());
+            }
+        }
+
+        let (from, to) = ((DIM - 3, 0, 0), (DIM + 3, 0, 0));
+        let path = world.find_path(from, to, &costs, 1000).expect("the gap lets paths through");
+        assert!(path.contains(&(DIM, 3, 0)));
+        assert_eq!(path.last(), Some(&to));
+        assert_eq!(path_cost(from, &path, &costs), 6 * 141);
+    }
+
+    #[test]
+    fn paths_climb_between_levels() {
+        let mut world = testing::flat_world();
+        let costs = MoveCosts::default();
+        let (from, to) = ((2, 2, 0), (2, 2, -1));
+
+        assert_eq!(world.find_path(from, to, &costs, 500), None);
+
+        world.set_block((6, 2, 0), Block {
+            fill: BlockFill::Floor(MaterialId::STONE),
+            connector: Some(Connector::StairsDown),
+        });
+        world.set_block((6, 2, -1), Block {
+            fill: BlockFill::Floor(MaterialId::STONE),
+            connector: Some(Connector::StairsUp),
+        });
+
+        let path = world.find_path(from, to, &costs, 500).expect("the stairs lead down");
+        assert_eq!(path, [(3, 2, 0), (4, 2, 0), (5, 2, 0), (6, 2, 0), (6, 2, -1), (5, 2, -1),
+            (4, 2, -1), (3, 2, -1), (2, 2, -1)]);
+    }
+
+    #[test]
+    fn estimate_never_overestimates() {
+        let cheap_diagonals = MoveCosts { diagonal: 50, ..MoveCosts::default() };
+
+        for costs in &[MoveCosts::default(), cheap_diagonals] {
+            let mut world = testing::flat_world();
+            let origin = (0, 0, 0);
+            let map = world.dijkstra_map(&[origin], costs, 1000);
+
+            for y in -5..=5 {
+                for x in -5..=5 {
+                    let actual = map.value((x, y, 0)).expect("everything nearby is reached");
+                    assert!(costs.estimate(origin, (x, y, 0)) <= actual as u64);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn dijkstra_maps_lead_back_to_their_sources() {
+        let mut world = testing::flat_world();
+        let costs = MoveCosts::default();
+        world.set_block((1, 0, 0), wall());
+
+        let map = world.dijkstra_map(&[(0, 0, 0)], &costs, 300);
+        assert_eq!(map.value((0, 0, 0)), Some(0));
+        assert_eq!(map.value((1, 0, 0)), None);
+        assert_eq!(map.value((2, 0, 0)), Some(282));
+        assert_eq!(map.value((0, 3, 0)), Some(300));
+        assert_eq!(map.value((0, 4, 0)), None);
+
+        let mut pos = (2, 0, 0);
+        while pos != (0, 0, 0) {
+            let next = world.downhill(&map, pos).expect("every tile in the map leads downhill");
+            assert!(map.value(next) < map.value(pos));
+            pos = next;
+        }
+    }
+
+    #[test]
+    fn set_block_invalidates_cached_moves() {
+        let mut world = testing::flat_world();
+        let (edge, across) = ((DIM - 1, 0, 0), (DIM, 0, 0));
+        assert!(world.moves_from(edge).contains(&(across, Step::Orthogonal)));
+
+        // Walling off a tile in the neighbouring region changes the moves out of this one.
+        world.set_block(across, wall());
+        assert!(!world.moves_from(edge).iter().any(|&(pos, _)| pos == across));
+
+        world.set_block(across, Block::new(BlockFill::Floor(MaterialId::STONE)));
+        assert!(world.moves_from(edge).contains(&(across, Step::Orthogonal)));
+    }
+}
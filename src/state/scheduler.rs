@@ -0,0 +1,189 @@
+//! Decides who acts next. Every actor is queued under the tick on which it may act again; taking
+//! an action pushes the actor back by however many ticks the action cost, so fast actors and cheap
+//! actions come around more often.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use crate::state::action::Action;
+use crate::state::ecs::EntityId;
+use crate::state::world::{Tick, World};
+
+/// The number of ticks in a turn: what a normal-speed actor spends on a typical action.
+pub const TICKS_PER_TURN: u64 = 1000;
+
+/// The speed of an ordinary actor. An actor with twice this speed spends half as many ticks on
+/// each action.
+pub const NORMAL_SPEED: u32 = 100;
+
+/// How quickly an entity acts. Entities without this component never get a turn.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Actor {
+    pub speed: u32,
+}
+
+impl Default for Actor {
+    fn default() -> Self {
+        Self {
+            speed: NORMAL_SPEED,
+        }
+    }
+}
+
+impl Actor {
+    /// The number of ticks this actor spends on an action with the given base cost. Any action
+    /// that takes time at all takes at least one tick.
+    pub fn cost(&self, base_cost: u64) -> u64 {
+        if base_cost == 0 {
+            return 0;
+        }
+
+        (base_cost * NORMAL_SPEED as u64 / self.speed.max(1) as u64).max(1)
+    }
+}
+
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct Scheduler {
+    /// Ordered by tick, then by the order actors were scheduled in, so that actors ready on the
+    /// same tick take turns in a stable order.
+    queue: BinaryHeap<Reverse<(Tick, u64, EntityId)>>,
+    sequence: u64,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues `entity` to act on `tick`.
+    pub fn schedule(&mut self, entity: EntityId, tick: Tick) {
+        self.queue.push(Reverse((tick, self.sequence, entity)));
+        self.sequence += 1;
+    }
+
+    /// The next actor to act and the tick it acts on, without removing it from the queue.
+    pub fn peek(&self) -> Option<(Tick, EntityId)> {
+        self.queue.peek().map(|&Reverse((tick, _, entity))| (tick, entity))
+    }
+
+    /// Removes and returns the next actor to act and the tick it acts on.
+    pub fn pop(&mut self) -> Option<(Tick, EntityId)> {
+        self.queue.pop().map(|Reverse((tick, _, entity))| (tick, entity))
+    }
+
+    /// Drops `entity` from the queue, for instance when it dies.
+    pub fn unschedule(&mut self, entity: EntityId) {
+        let queue = std::mem::take(&mut self.queue);
+        self.queue = queue.into_iter().filter(|Reverse((_, _, e))| *e != entity).collect();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+impl World {
+    /// Gives `entity` turns, starting on the current tick.
+    pub fn schedule_actor(&mut self, entity: EntityId) {
+        self.scheduler.schedule(entity, self.current_tick);
+    }
+
+    /// Whether the player is the next to act, so the game is waiting on their input.
+    pub fn is_player_turn(&self) -> bool {
+        self.player.is_some() && self.scheduler.peek().map(|(_, e)| e) == self.player
+    }
+
+    /// Carries out the player's `action`, then lets every other actor take their turns until it is
+    /// the player's turn again. Returns `false` if it is not the player's turn or the action could
+    /// not be carried out, in which case no time passes.
+    pub fn act_player(&mut self, action: Action) -> bool {
+        let player = match self.player {
+            Some(player) if self.is_player_turn() => player,
+            _ => return false,
+        };
+
+        let cost = match self.perform(player, action) {
+            Some(cost) => cost,
+            None => return false,
+        };
+
+        self.scheduler.pop();
+        self.scheduler.schedule(player, self.current_tick + cost);
+        self.update_player_view();
+        self.run_until_player_turn();
+
+        true
+    }
+
+    /// Lets actors take their turns in order, advancing `current_tick` to each, until the player
+    /// is next to act. Returns immediately if there is no player to wait for, and stops as soon as
+    /// the player dies.
+    pub fn run_until_player_turn(&mut self) {
+        let player = match self.player {
+            Some(player) => player,
+            None => return,
+        };
+
+        while let Some((tick, entity)) = self.scheduler.peek() {
+            if self.is_player_dead() {
+                return;
+            }
+
+            self.current_tick = self.current_tick.max(tick);
+            if entity == player {
+                return;
+            }
+
+            self.scheduler.pop();
+
+            // Actors that died or stopped acting since they were scheduled drop out here.
+            let actor = match self.entities.get::<Actor>(entity) {
+                Some(&actor) if self.entities.is_alive(entity) => actor,
+                _ => continue,
+            };
+
+            let action = self.decide_ai(entity);
+            let cost = self.perform(entity, action)
+                .unwrap_or_else(|| actor.cost(Action::Wait.base_cost()));
+            self.scheduler.schedule(entity, self.current_tick + cost);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::world::{testing, Direction};
+
+    #[test]
+    fn double_speed_acts_twice_per_turn() {
+        let mut world = testing::world();
+
+        let normal = world.entities.spawn();
+        world.entities.insert(normal, Actor::default());
+        let fast = world.entities.spawn();
+        world.entities.insert(fast, Actor { speed: NORMAL_SPEED * 2 });
+        world.schedule_actor(normal);
+        world.schedule_actor(fast);
+
+        // Everyone takes the same step over and over.
+        let step = Action::Move(Direction::North).base_cost();
+        let mut turns = Vec::new();
+        for _ in 0..30 {
+            let (tick, entity) = world.scheduler.pop().expect("both actors stay queued");
+            let actor = *world.entities.get::<Actor>(entity).unwrap();
+            world.scheduler.schedule(entity, tick + actor.cost(step));
+            turns.push(entity);
+        }
+
+        let normal_turns: Vec<_> = turns.iter().enumerate()
+            .filter(|&(_, &entity)| entity == normal)
+            .map(|(i, _)| i)
+            .collect();
+        assert!(normal_turns.len() >= 5);
+        for pair in normal_turns.windows(2) {
+            let fast_turns = turns[pair[0] + 1..pair[1]].iter().filter(|&&e| e == fast).count();
+            assert_eq!(fast_turns, 2);
+        }
+    }
+}
@@ -0,0 +1,197 @@
+//! Binary space partition dungeons. The map is split recursively into leaves, each leaf gets one
+//! room, and the rooms of every pair of sibling subtrees are joined by an L-shaped corridor. Since
+//! the corridors follow the partition tree, they form a spanning tree over the rooms and every
+//! room is reachable from every other.
+
+use rand::distributions::{Distribution, Uniform};
+use rand::Rng;
+
+use super::*;
+
+#[derive(Clone, Debug)]
+pub struct BspGenerator {
+    pub width: u32,
+    pub height: u32,
+    /// Leaves are never split into pieces narrower or shorter than this. Sizes below 1 are treated
+    /// as 1.
+    pub min_leaf_size: u32,
+    /// The smallest room that is placed in a leaf, as `(width, height)`.
+    pub min_room_size: (u32, u32),
+}
+
+/// The rooms of a generated dungeon and the corridors between them. Each edge holds the indices
+/// of the two rooms it joins.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RoomGraph {
+    pub rooms: Vec<Rect>,
+    pub edges: Vec<(usize, usize)>,
+}
+
+impl BspGenerator {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            min_leaf_size: 8,
+            min_room_size: (4, 3),
+        }
+    }
+
+    /// Generates the dungeon along with the graph of its rooms.
+    pub fn generate(&self, rng: &mut GenRng) -> (TileMap, RoomGraph) {
+        let mut map = TileMap::filled(self.width, self.height, BlockFill::Solid(MaterialId::GRANITE));
+        let mut graph = RoomGraph::default();
+
+        let root = Rect::new(0, 0, self.width, self.height);
+        self.split(root, rng, &mut map, &mut graph);
+
+        debug_assert!(
+            map.is_connected(|fill| matches!(fill, BlockFill::Floor(_))),
+            "BSP generator produced unreachable floor tiles",
+        );
+
+        (map, graph)
+    }
+
+    /// Partitions `leaf`, places rooms in the resulting leaves and joins them. Returns the indices
+    /// of every room placed inside of `leaf`.
+    fn split(
+        &self,
+        leaf: Rect,
+        rng: &mut GenRng,
+        map: &mut TileMap,
+        graph: &mut RoomGraph,
+    ) -> Vec<usize> {
+        // A leaf could be split into an empty piece and itself forever otherwise.
+        let min_leaf_size = self.min_leaf_size.max(1);
+        let can_split_x = leaf.width() >= min_leaf_size * 2;
+        let can_split_y = leaf.height() >= min_leaf_size * 2;
+
+        // Prefer cutting across the longer axis so leaves stay roughly square.
+        let split_x = match (can_split_x, can_split_y) {
+            (false, false) => return self.place_room(leaf, rng, map, graph).into_iter().collect(),
+            (true, false) => true,
+            (false, true) => false,
+            (true, true) => {
+                let (w, h) = (leaf.width() as f32, leaf.height() as f32);
+                if w / h >= 1.25 {
+                    true
+                } else if h / w >= 1.25 {
+                    false
+                } else {
+                    rng.gen_bool(0.5)
+                }
+            },
+        };
+
+        let (first, second) = if split_x {
+            let at = Uniform::from(min_leaf_size..=leaf.width() - min_leaf_size)
+                .sample(rng) as i32;
+            (
+                Rect { x2: leaf.x1 + at, ..leaf },
+                Rect { x1: leaf.x1 + at, ..leaf },
+            )
+        } else {
+            let at = Uniform::from(min_leaf_size..=leaf.height() - min_leaf_size)
+                .sample(rng) as i32;
+            (
+                Rect { y2: leaf.y1 + at, ..leaf },
+                Rect { y1: leaf.y1 + at, ..leaf },
+            )
+        };
+
+        let first_rooms = self.split(first, rng, map, graph);
+        let second_rooms = self.split(second, rng, map, graph);
+
+        // Join the two siblings through the closest pair of rooms across the cut.
+        let closest = first_rooms.iter()
+            .flat_map(|&a| second_rooms.iter().map(move |&b| (a, b)))
+            .min_by_key(|&(a, b)| {
+                let (ax, ay) = graph.rooms[a].center();
+                let (bx, by) = graph.rooms[b].center();
+                (ax - bx).abs() + (ay - by).abs()
+            });
+
+        if let Some((a, b)) = closest {
+            let horizontal_first = rng.gen_bool(0.5);
+            map.carve_l_corridor(
+                graph.rooms[a].center(),
+                graph.rooms[b].center(),
+                horizontal_first,
+                BlockFill::Floor(MaterialId::STONE),
+            );
+            graph.edges.push((a, b));
+        }
+
+        let mut rooms = first_rooms;
+        rooms.extend(second_rooms);
+        rooms
+    }
+
+    /// Places a randomly sized room inside `leaf`, leaving a wall of at least one tile around it.
+    /// Returns `None` if the leaf is too small to hold a room.
+    fn place_room(
+        &self,
+        leaf: Rect,
+        rng: &mut GenRng,
+        map: &mut TileMap,
+        graph: &mut RoomGraph,
+    ) -> Option<usize> {
+        let (min_width, min_height) = self.min_room_size;
+        let (max_width, max_height) = (leaf.width().checked_sub(2)?, leaf.height().checked_sub(2)?);
+        if max_width < min_width || max_height < min_height {
+            return None;
+        }
+
+        let width = Uniform::from(min_width..=max_width).sample(rng);
+        let height = Uniform::from(min_height..=max_height).sample(rng);
+        let x = leaf.x1 + 1 + Uniform::from(0..=max_width - width).sample(rng) as i32;
+        let y = leaf.y1 + 1 + Uniform::from(0..=max_height - height).sample(rng) as i32;
+
+        let room = Rect::new(x, y, width, height);
+        map.fill_rect(room, BlockFill::Floor(MaterialId::WOOD));
+        graph.rooms.push(room);
+
+        Some(graph.rooms.len() - 1)
+    }
+}
+
+impl MapGenerator for BspGenerator {
+    fn generate_map(&self, rng: &mut GenRng) -> TileMap {
+        self.generate(rng).0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_floor(fill: BlockFill) -> bool {
+        matches!(fill, BlockFill::Floor(_))
+    }
+
+    #[test]
+    fn rooms_form_a_connected_tree() {
+        for &(width, height) in &[(16, 16), (40, 20), (64, 48), (100, 37)] {
+            for seed in 0..50 {
+                let generator = BspGenerator::new(width, height);
+                let (map, graph) = generator.generate(&mut seeded_rng(seed, 0));
+
+                assert!(!graph.rooms.is_empty(), "no rooms in {}x{} seed {}", width, height, seed);
+                assert!(map.is_connected(is_floor), "{}x{} seed {} is split", width, height, seed);
+                assert_eq!(graph.edges.len(), graph.rooms.len() - 1);
+            }
+        }
+    }
+
+    #[test]
+    fn zero_min_leaf_size_terminates() {
+        let mut generator = BspGenerator::new(24, 24);
+        generator.min_leaf_size = 0;
+        generator.min_room_size = (1, 1);
+
+        let (map, graph) = generator.generate(&mut seeded_rng(1, 0));
+        assert!(map.is_connected(is_floor));
+        assert_eq!(graph.edges.len(), graph.rooms.len().saturating_sub(1));
+    }
+}
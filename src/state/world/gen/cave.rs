@@ -0,0 +1,236 @@
+//! Cellular automata caves. The map starts out as random noise and is smoothed over several passes:
+//! a tile becomes wall when enough of its neighbours are walls, and stays wall when enough of them
+//! still are. Pockets of floor that end up cut off from the main cave are filled back in.
+
+use std::path::Path;
+
+use rand::Rng;
+use serde::Deserialize;
+
+use super::*;
+
+/// The knobs designers tune cave density with. These are usually loaded from
+/// `resources/caves.ron`.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct CaveParams {
+    /// The chance for each tile to start out as wall.
+    pub fill_ratio: f32,
+    /// The number of smoothing passes.
+    pub iterations: u32,
+    /// A floor tile turns into wall when at least this many of its eight neighbours are walls.
+    pub birth_threshold: u32,
+    /// A wall tile stays wall when at least this many of its eight neighbours are walls.
+    pub survival_threshold: u32,
+}
+
+impl Default for CaveParams {
+    fn default() -> Self {
+        Self {
+            fill_ratio: 0.45,
+            iterations: 5,
+            birth_threshold: 5,
+            survival_threshold: 4,
+        }
+    }
+}
+
+impl CaveParams {
+    /// Reads cave parameters from a RON file. Fields missing from the file keep their defaults.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+
+        let params: Self = ron::de::from_str(&text)
+            .map_err(|e| format!("failed to parse {}: {}", path.display(), e))?;
+        params.validate().map_err(|e| format!("invalid {}: {}", path.display(), e))?;
+        Ok(params)
+    }
+
+    /// Checks that the parameters make sense. Fill ratios outside of 0 to 1 are fine, and are
+    /// clamped when generating; a fill ratio that is not a number at all is not.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.fill_ratio.is_nan() {
+            return Err("fill_ratio is not a number".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CaveGenerator {
+    pub width: u32,
+    pub height: u32,
+    pub params: CaveParams,
+}
+
+impl CaveGenerator {
+    pub fn new(width: u32, height: u32, params: CaveParams) -> Self {
+        Self {
+            width,
+            height,
+            params,
+        }
+    }
+
+    fn is_border(&self, x: u32, y: u32) -> bool {
+        x == 0 || y == 0 || x + 1 >= self.width || y + 1 >= self.height
+    }
+
+    fn smooth(&self, walls: &[bool]) -> Vec<bool> {
+        let mut next = Vec::with_capacity(walls.len());
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                if self.is_border(x, y) {
+                    next.push(true);
+                    continue;
+                }
+
+                let mut wall_neighbours = 0;
+                for ny in y - 1..=y + 1 {
+                    for nx in x - 1..=x + 1 {
+                        if (nx, ny) != (x, y) && walls[(ny * self.width + nx) as usize] {
+                            wall_neighbours += 1;
+                        }
+                    }
+                }
+
+                let threshold = if walls[(y * self.width + x) as usize] {
+                    self.params.survival_threshold
+                } else {
+                    self.params.birth_threshold
+                };
+
+                next.push(wall_neighbours >= threshold);
+            }
+        }
+
+        next
+    }
+}
+
+impl MapGenerator for CaveGenerator {
+    fn generate_map(&self, rng: &mut GenRng) -> TileMap {
+        let fill_ratio = match self.params.validate() {
+            Ok(()) => (self.params.fill_ratio as f64).clamp(0.0, 1.0),
+            Err(_) => CaveParams::default().fill_ratio as f64,
+        };
+
+        let mut walls = Vec::with_capacity((self.width * self.height) as usize);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                walls.push(self.is_border(x, y) || rng.gen_bool(fill_ratio));
+            }
+        }
+
+        for _ in 0..self.params.iterations {
+            walls = self.smooth(&walls);
+        }
+
+        let mut map = TileMap::filled(self.width, self.height, BlockFill::Solid(MaterialId::STONE));
+        for y in 0..self.height {
+            for x in 0..self.width {
+                if !walls[(y * self.width + x) as usize] {
+                    map.set(x as i32, y as i32, BlockFill::Floor(MaterialId::STONE));
+                }
+            }
+        }
+
+        keep_largest_cave(&mut map);
+        map
+    }
+}
+
+/// Fills in every floor tile that is not part of the largest connected cave.
+fn keep_largest_cave(map: &mut TileMap) {
+    let is_floor = |fill| matches!(fill, BlockFill::Floor(_));
+
+    let mut claimed = vec![false; (map.width * map.height) as usize];
+    let mut largest: Option<Vec<bool>> = None;
+    let mut largest_size = 0;
+
+    for y in 0..map.height as i32 {
+        for x in 0..map.width as i32 {
+            let idx = (y as u32 * map.width + x as u32) as usize;
+            if claimed[idx] || !map.get(x, y).map_or(false, is_floor) {
+                continue;
+            }
+
+            let cave = map.flood_fill((x, y), is_floor);
+            let size = cave.iter().filter(|&&reached| reached).count();
+            for (claimed, &reached) in claimed.iter_mut().zip(&cave) {
+                *claimed |= reached;
+            }
+
+            if size > largest_size {
+                largest_size = size;
+                largest = Some(cave);
+            }
+        }
+    }
+
+    let largest = match largest {
+        Some(largest) => largest,
+        None => return,
+    };
+
+    for y in 0..map.height as i32 {
+        for x in 0..map.width as i32 {
+            if !largest[(y as u32 * map.width + x as u32) as usize] {
+                map.set(x, y, BlockFill::Solid(MaterialId::STONE));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn caves() -> impl Iterator<Item = TileMap> {
+        let generator = CaveGenerator::new(64, 48, CaveParams::default());
+        (0..8).map(move |seed| generator.generate_map(&mut seeded_rng(seed, 0)))
+    }
+
+    #[test]
+    fn caves_are_one_connected_cave() {
+        for map in caves() {
+            assert!(map.fills.iter().any(|fill| matches!(fill, BlockFill::Floor(_))));
+            assert!(map.is_connected(|fill| matches!(fill, BlockFill::Floor(_))));
+        }
+    }
+
+    #[test]
+    fn the_border_is_solid() {
+        for map in caves() {
+            for x in 0..map.width as i32 {
+                for &y in &[0, map.height as i32 - 1] {
+                    assert_eq!(map.get(x, y), Some(BlockFill::Solid(MaterialId::STONE)));
+                }
+            }
+            for y in 0..map.height as i32 {
+                for &x in &[0, map.width as i32 - 1] {
+                    assert_eq!(map.get(x, y), Some(BlockFill::Solid(MaterialId::STONE)));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn bad_parameters_do_not_panic() {
+        let nan = CaveParams { fill_ratio: f32::NAN, ..CaveParams::default() };
+        assert!(nan.validate().is_err());
+        assert!(CaveParams::default().validate().is_ok());
+
+        let mut rng = seeded_rng(0, 0);
+        CaveGenerator::new(16, 16, nan).generate_map(&mut rng);
+        for &(width, height) in &[(0, 0), (0, 5), (5, 0), (1, 1), (2, 3)] {
+            let generator = CaveGenerator::new(width, height, CaveParams::default());
+            let map = generator.generate_map(&mut rng);
+            assert!(!map.fills.contains(&BlockFill::Floor(MaterialId::STONE)));
+        }
+    }
+}
@@ -0,0 +1,521 @@
+//! World generation. A `WorldGenerator` fills in one region at a time from the world seed, so
+//! regions can be generated lazily the first time they are accessed. Generators must only draw
+//! randomness from a `GenRng` seeded through `seeded_rng`; anything else (the thread rng, hash map
+//! iteration order, `usize` arithmetic) breaks reproducibility across machines.
+
+use std::collections::HashMap;
+
+use rand::{Rng, SeedableRng};
+
+use super::*;
+
+mod bsp;
+mod cave;
+mod noise;
+mod overworld;
+mod rooms;
+
+pub use bsp::{BspGenerator, RoomGraph};
+pub use cave::{CaveGenerator, CaveParams};
+pub use noise::GradientNoise;
+pub use overworld::OverworldGenerator;
+pub use rooms::RoomsGenerator;
+
+/// The random number generator handed to generators. ChaCha is used explicitly (instead of
+/// `StdRng`) because its output is guaranteed to be stable across platforms.
+pub type GenRng = rand_chacha::ChaCha8Rng;
+
+/// Creates the generator rng for `seed`. Independent uses of the same seed (e.g. different
+/// z-levels) should pick different `stream`s so that they do not produce correlated output.
+pub fn seeded_rng(seed: u64, stream: u64) -> GenRng {
+    let mut rng = GenRng::seed_from_u64(seed);
+    rng.set_stream(stream);
+    rng
+}
+
+pub trait WorldGenerator: std::fmt::Debug {
+    /// Generates the region at `offset`. This must be a pure function of `seed` and `offset`: the
+    /// same inputs produce the same region, byte for byte, on every machine.
+    fn generate_region(&mut self, seed: u64, offset: (i32, i32, i32)) -> Region;
+
+    /// The light that reaches every tile on level `z` without any light source nearby. Dark by
+    /// default, so that only lights show anything.
+    fn ambient_light(&self, _z: i32) -> [f32; 3] {
+        DARKNESS
+    }
+}
+
+/// The ambient light of places the sun does not reach. Not quite black, so that the shapes of
+/// unlit rooms in view can still be made out.
+pub const DARKNESS: [f32; 3] = [0.15, 0.15, 0.2];
+/// The ambient light of the surface.
+pub const DAYLIGHT: [f32; 3] = [1.0, 1.0, 1.0];
+
+/// A generator that lays out a whole, fixed-size map at once. Wrap it in a `BoundedGenerator` to
+/// use it as a `WorldGenerator`.
+pub trait MapGenerator: std::fmt::Debug {
+    fn generate_map(&self, rng: &mut GenRng) -> TileMap;
+}
+
+/// Serves regions out of the maps produced by a `MapGenerator`, one map per z-level. Level 0 is
+/// the top of the dungeon and deeper levels go down from there. Each pair of adjacent levels is
+/// linked by stairs (or a ladder, if the stairs up and down of a level happen to coincide), with a
+/// corridor carved on the lower level if the stairs would otherwise land in rock. A few floor tiles
+/// above floor on the level below are left open as holes to fall through.
+///
+/// Maps are generated once per seed and kept around, since every region needs to see the whole
+/// layout of its level. Everything outside of the maps is solid.
+#[derive(Debug)]
+pub struct BoundedGenerator<G: MapGenerator> {
+    generator: G,
+    depth: u32,
+    cache: Option<LevelCache>,
+}
+
+#[derive(Debug)]
+struct LevelCache {
+    seed: u64,
+    /// Maps straight out of the `MapGenerator`.
+    base_maps: HashMap<i32, TileMap>,
+    /// Base maps with their connectors added.
+    levels: HashMap<i32, TileMap>,
+}
+
+/// Salt for the rng that places the connectors between levels.
+const LINK_SALT: u64 = 0x4C49_4E4B;
+
+/// Salt for the rng that places the holes between levels.
+const HOLE_SALT: u64 = 0x484F_4C45;
+
+/// How many holes each level has down to the next.
+const HOLES_PER_LEVEL: usize = 3;
+
+impl<G: MapGenerator> BoundedGenerator<G> {
+    pub fn new(generator: G) -> Self {
+        Self {
+            generator,
+            depth: 1,
+            cache: None,
+        }
+    }
+
+    /// Generates `depth` levels, from z = 0 down to z = 1 - `depth`.
+    pub fn with_depth(mut self, depth: u32) -> Self {
+        self.depth = depth.max(1);
+        self
+    }
+
+    fn has_level(&self, z: i32) -> bool {
+        z <= 0 && z > -(self.depth as i32)
+    }
+
+    fn level(&mut self, seed: u64, z: i32) -> &TileMap {
+        if self.cache.as_ref().map(|cache| cache.seed) != Some(seed) {
+            self.cache = Some(LevelCache {
+                seed,
+                base_maps: HashMap::new(),
+                levels: HashMap::new(),
+            });
+        }
+
+        let has_level_below = self.has_level(z - 1);
+        let has_level_above = self.has_level(z + 1);
+
+        // Holes have to land on the finished level below, holes and all, so that is made first.
+        let built = self.cache.as_ref().map_or(false, |cache| cache.levels.contains_key(&z));
+        if has_level_below && !built {
+            self.level(seed, z - 1);
+        }
+
+        let generator = &self.generator;
+        let cache = self.cache.as_mut().expect("cache was just initialized");
+
+        if !cache.levels.contains_key(&z) {
+            let mut map = base_map(generator, &mut cache.base_maps, seed, z).clone();
+
+            if has_level_below {
+                let (x, y) = link_position(generator, &mut cache.base_maps, seed, z);
+                map.set_connector(x, y, Some(Connector::StairsDown));
+            }
+
+            if has_level_above {
+                let (x, y) = link_position(generator, &mut cache.base_maps, seed, z + 1);
+                if let Some(nearest) = map.nearest(x, y, |fill| matches!(fill, BlockFill::Floor(_))) {
+                    map.carve_l_corridor((x, y), nearest, true, BlockFill::Floor(MaterialId::STONE));
+                }
+
+                let connector = match map.connector(x, y) {
+                    Some(Connector::StairsDown) => Connector::Ladder,
+                    _ => Connector::StairsUp,
+                };
+                map.set_connector(x, y, Some(connector));
+            }
+
+            if has_level_below {
+                carve_holes(&mut map, &cache.levels[&(z - 1)], seed, z);
+            }
+
+            cache.levels.insert(z, map);
+        }
+
+        &cache.levels[&z]
+    }
+}
+
+fn base_map<'a, G: MapGenerator>(
+    generator: &G,
+    base_maps: &'a mut HashMap<i32, TileMap>,
+    seed: u64,
+    z: i32,
+) -> &'a TileMap {
+    base_maps.entry(z).or_insert_with(|| {
+        generator.generate_map(&mut seeded_rng(seed, z as i64 as u64))
+    })
+}
+
+/// Picks where the stairs between level `upper_z` and the level below it go: a random floor tile
+/// of the upper level. Only base maps are consulted, so the result does not depend on the order in
+/// which levels are generated.
+fn link_position<G: MapGenerator>(
+    generator: &G,
+    base_maps: &mut HashMap<i32, TileMap>,
+    seed: u64,
+    upper_z: i32,
+) -> (i32, i32) {
+    let upper = base_map(generator, base_maps, seed, upper_z);
+
+    let mut floors = Vec::new();
+    for y in 0..upper.height as i32 {
+        for x in 0..upper.width as i32 {
+            if let Some(BlockFill::Floor(_)) = upper.get(x, y) {
+                floors.push((x, y));
+            }
+        }
+    }
+
+    if floors.is_empty() {
+        return (upper.width as i32 / 2, upper.height as i32 / 2);
+    }
+
+    let mut rng = seeded_rng(seed ^ LINK_SALT, upper_z as i64 as u64);
+    floors[rng.gen_range(0, floors.len() as u32) as usize]
+}
+
+/// Opens up to `HOLES_PER_LEVEL` holes in the floor of level `z`, each over floor on the finished
+/// level below, so that nothing falls further than one level. Tiles with connectors are left
+/// alone, as are tiles whose hole would cut the level in two.
+fn carve_holes(map: &mut TileMap, below: &TileMap, seed: u64, z: i32) {
+    let is_floor = |fill| matches!(fill, BlockFill::Floor(_) | BlockFill::FloorCeiling(_, _));
+
+    let mut candidates = Vec::new();
+    for y in 0..map.height as i32 {
+        for x in 0..map.width as i32 {
+            let open = map.get(x, y).map_or(false, is_floor) && map.connector(x, y).is_none();
+            if open && below.get(x, y).map_or(false, is_floor) {
+                candidates.push((x, y));
+            }
+        }
+    }
+
+    // Levels that come out of their generator in pieces are only kept from falling further apart.
+    let was_connected = map.is_connected(is_floor);
+    let mut rng = seeded_rng(seed ^ HOLE_SALT, z as i64 as u64);
+    let mut holes = 0;
+    while holes < HOLES_PER_LEVEL && !candidates.is_empty() {
+        let (x, y) = candidates.swap_remove(rng.gen_range(0, candidates.len() as u32) as usize);
+        let fill = map.get(x, y).expect("candidates are on the map");
+
+        map.set(x, y, BlockFill::Empty);
+        if !was_connected || map.is_connected(is_floor) {
+            holes += 1;
+        } else {
+            map.set(x, y, fill);
+        }
+    }
+}
+
+impl<G: MapGenerator> WorldGenerator for BoundedGenerator<G> {
+    fn generate_region(&mut self, seed: u64, (x, y, z): (i32, i32, i32)) -> Region {
+        if !self.has_level(z) {
+            return Region::filled(BlockFill::Solid(MaterialId::STONE));
+        }
+
+        self.level(seed, z).region((x, y))
+    }
+}
+
+/// An axis-aligned rectangle of tiles. `x2` and `y2` are exclusive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rect {
+    pub x1: i32,
+    pub y1: i32,
+    pub x2: i32,
+    pub y2: i32,
+}
+
+impl Rect {
+    pub fn new(x: i32, y: i32, width: u32, height: u32) -> Self {
+        Self {
+            x1: x,
+            y1: y,
+            x2: x + width as i32,
+            y2: y + height as i32,
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        (self.x2 - self.x1) as u32
+    }
+
+    pub fn height(&self) -> u32 {
+        (self.y2 - self.y1) as u32
+    }
+
+    pub fn center(&self) -> (i32, i32) {
+        ((self.x1 + self.x2) / 2, (self.y1 + self.y2) / 2)
+    }
+
+    pub fn overlaps(&self, other: Rect) -> bool {
+        self.x1 < other.x2 && self.x2 > other.x1 && self.y1 < other.y2 && self.y2 > other.y1
+    }
+}
+
+/// A bounded, flat grid of block fills that map generators draw into before the result is cut
+/// into regions.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TileMap {
+    pub width: u32,
+    pub height: u32,
+    fills: Vec<BlockFill>,
+    connectors: HashMap<(i32, i32), Connector>,
+}
+
+impl TileMap {
+    pub fn filled(width: u32, height: u32, fill: BlockFill) -> Self {
+        Self {
+            width,
+            height,
+            fills: vec![fill; (width * height) as usize],
+            connectors: HashMap::new(),
+        }
+    }
+
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        x >= 0 && y >= 0 && (x as u32) < self.width && (y as u32) < self.height
+    }
+
+    pub fn get(&self, x: i32, y: i32) -> Option<BlockFill> {
+        if self.contains(x, y) {
+            Some(self.fills[(y as u32 * self.width + x as u32) as usize])
+        } else {
+            None
+        }
+    }
+
+    /// Out of bounds writes are ignored.
+    pub fn set(&mut self, x: i32, y: i32, fill: BlockFill) {
+        if self.contains(x, y) {
+            self.fills[(y as u32 * self.width + x as u32) as usize] = fill;
+        }
+    }
+
+    pub fn connector(&self, x: i32, y: i32) -> Option<Connector> {
+        self.connectors.get(&(x, y)).copied()
+    }
+
+    /// Out of bounds writes are ignored.
+    pub fn set_connector(&mut self, x: i32, y: i32, connector: Option<Connector>) {
+        if !self.contains(x, y) {
+            return;
+        }
+
+        match connector {
+            Some(connector) => self.connectors.insert((x, y), connector),
+            None => self.connectors.remove(&(x, y)),
+        };
+    }
+
+    /// Finds the tile closest to `(x, y)` (by Manhattan distance) for which `matches` holds.
+    pub fn nearest(&self, x: i32, y: i32, matches: impl Fn(BlockFill) -> bool) -> Option<(i32, i32)> {
+        let mut nearest = None;
+        let mut nearest_distance = i32::MAX;
+
+        for ty in 0..self.height as i32 {
+            for tx in 0..self.width as i32 {
+                let distance = (tx - x).abs() + (ty - y).abs();
+                if distance < nearest_distance && self.get(tx, ty).map_or(false, &matches) {
+                    nearest = Some((tx, ty));
+                    nearest_distance = distance;
+                }
+            }
+        }
+
+        nearest
+    }
+
+    pub fn fill_rect(&mut self, rect: Rect, fill: BlockFill) {
+        for y in rect.y1..rect.y2 {
+            for x in rect.x1..rect.x2 {
+                self.set(x, y, fill);
+            }
+        }
+    }
+
+    /// Carves an L-shaped corridor between `from` and `to`, either running horizontally first
+    /// and then vertically, or the other way around. Only solid tiles are carved, so a corridor
+    /// running through a room leaves the room's floor alone.
+    pub fn carve_l_corridor(
+        &mut self,
+        from: (i32, i32),
+        to: (i32, i32),
+        horizontal_first: bool,
+        fill: BlockFill,
+    ) {
+        let corner = if horizontal_first { (to.0, from.1) } else { (from.0, to.1) };
+
+        for &((x1, y1), (x2, y2)) in &[(from, corner), (corner, to)] {
+            for y in y1.min(y2)..=y1.max(y2) {
+                for x in x1.min(x2)..=x1.max(x2) {
+                    if let Some(BlockFill::Solid(_)) = self.get(x, y) {
+                        self.set(x, y, fill);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns a mask of every tile reachable from `start` through orthogonal steps onto tiles
+    /// for which `passable` holds.
+    pub fn flood_fill(&self, start: (i32, i32), passable: impl Fn(BlockFill) -> bool) -> Vec<bool> {
+        let mut reached = vec![false; self.fills.len()];
+
+        match self.get(start.0, start.1) {
+            Some(fill) if passable(fill) => {},
+            _ => return reached,
+        }
+
+        let mut frontier = vec![start];
+        reached[(start.1 as u32 * self.width + start.0 as u32) as usize] = true;
+
+        while let Some((x, y)) = frontier.pop() {
+            for &(nx, ny) in &[(x + 1, y), (x - 1, y), (x, y + 1), (x, y - 1)] {
+                match self.get(nx, ny) {
+                    Some(fill) if passable(fill) => {},
+                    _ => continue,
+                }
+
+                let idx = (ny as u32 * self.width + nx as u32) as usize;
+                if !reached[idx] {
+                    reached[idx] = true;
+                    frontier.push((nx, ny));
+                }
+            }
+        }
+
+        reached
+    }
+
+    /// Whether every passable tile can be reached from every other passable tile.
+    pub fn is_connected(&self, passable: impl Fn(BlockFill) -> bool) -> bool {
+        let start = match self.fills.iter().position(|&fill| passable(fill)) {
+            Some(idx) => ((idx as u32 % self.width) as i32, (idx as u32 / self.width) as i32),
+            None => return true,
+        };
+
+        let reached = self.flood_fill(start, &passable);
+        self.fills.iter().zip(reached).all(|(&fill, reached)| reached || !passable(fill))
+    }
+
+    /// Cuts out the region at region offset `(rx, ry)`. Tiles outside of the map are solid.
+    pub fn region(&self, (rx, ry): (i32, i32)) -> Region {
+        let mut blocks = Vec::with_capacity(REGION_LEN);
+        for y in 0..REGION_DIM as i32 {
+            for x in 0..REGION_DIM as i32 {
+                let global_x = rx * REGION_DIM as i32 + x;
+                let global_y = ry * REGION_DIM as i32 + y;
+
+                let fill = self.get(global_x, global_y).unwrap_or(BlockFill::Solid(MaterialId::STONE));
+                blocks.push(Block {
+                    fill,
+                    connector: self.connector(global_x, global_y),
+                });
+            }
+        }
+
+        Region { blocks }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::world::testing;
+
+    #[test]
+    fn holes_drop_onto_the_level_below() {
+        let mut generator = testing::dungeon().with_depth(3);
+
+        // Every level but the bottom one has holes, none of them over another hole.
+        let mut holes = Vec::new();
+        for z in -1..=0 {
+            let level = generator.level(testing::SEED, z).clone();
+            let below = generator.level(testing::SEED, z - 1).clone();
+            assert!(level.is_connected(|fill| matches!(fill, BlockFill::Floor(_))));
+
+            let mut level_holes = Vec::new();
+            for y in 0..level.height as i32 {
+                for x in 0..level.width as i32 {
+                    if level.get(x, y) == Some(BlockFill::Empty) {
+                        assert!(matches!(below.get(x, y), Some(BlockFill::Floor(_))));
+                        level_holes.push((x, y, z));
+                    }
+                }
+            }
+            assert_eq!(level_holes.len(), HOLES_PER_LEVEL);
+            holes.extend(level_holes);
+        }
+
+        let mut world = World::new(Box::new(generator), testing::SEED);
+        for &(x, y, z) in &holes {
+            assert_eq!(world.fall((x, y, z)), (x, y, z - 1));
+            assert!(world.can_stand_at((x, y, z - 1)));
+        }
+    }
+
+    /// The saved bytes of every region in `offsets`, loaded in that order.
+    fn region_bytes(world: &mut World, offsets: &[(i32, i32, i32)]) -> Vec<Vec<u8>> {
+        for &offset in offsets {
+            world.load_region(offset);
+        }
+
+        let mut offsets = offsets.to_vec();
+        offsets.sort();
+        offsets.iter()
+            .map(|offset| {
+                let mut bytes = Vec::new();
+                save::write_region_file(&mut bytes, &world.regions[offset]).unwrap();
+                bytes
+            })
+            .collect()
+    }
+
+    #[test]
+    fn the_same_seed_makes_the_same_world() {
+        let mut offsets = Vec::new();
+        for z in -2..=0 {
+            for y in -1..=3 {
+                for x in -1..=4 {
+                    offsets.push((x, y, z));
+                }
+            }
+        }
+        let expected = region_bytes(&mut testing::world(), &offsets);
+
+        // Regions come out the same whichever order they are generated in.
+        offsets.reverse();
+        assert_eq!(region_bytes(&mut testing::world(), &offsets), expected);
+
+        let mut reseeded = World::new(testing::generator(), testing::SEED + 1);
+        assert_ne!(region_bytes(&mut reseeded, &offsets), expected);
+    }
+}
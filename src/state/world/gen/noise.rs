@@ -0,0 +1,86 @@
+//! Seeded gradient noise over an unbounded domain. The lattice gradients come from hashing the
+//! lattice coordinates with the seed rather than from a permutation table, so the noise never
+//! repeats and any point can be sampled without generating its surroundings first.
+//!
+//! Only additions and multiplications are used (no `sin`, `exp` and friends, whose results differ
+//! between platform math libraries), so samples are identical on every machine.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GradientNoise {
+    seed: u64,
+}
+
+impl GradientNoise {
+    pub fn new(seed: u64) -> Self {
+        Self { seed }
+    }
+
+    /// Samples 2D Perlin noise at `(x, y)`. The result lies roughly within `[-1, 1]` and is
+    /// continuous everywhere.
+    pub fn sample(&self, x: f64, y: f64) -> f64 {
+        let (x0, y0) = (x.floor(), y.floor());
+        let (xi, yi) = (x0 as i64, y0 as i64);
+        let (fx, fy) = (x - x0, y - y0);
+
+        let n00 = self.gradient(xi, yi, fx, fy);
+        let n10 = self.gradient(xi + 1, yi, fx - 1.0, fy);
+        let n01 = self.gradient(xi, yi + 1, fx, fy - 1.0);
+        let n11 = self.gradient(xi + 1, yi + 1, fx - 1.0, fy - 1.0);
+
+        let (u, v) = (fade(fx), fade(fy));
+        lerp(lerp(n00, n10, u), lerp(n01, n11, u), v)
+    }
+
+    /// Sums `octaves` layers of noise, each at twice the frequency and half the amplitude of the
+    /// last, for detail at several scales. The result is normalised back into roughly `[-1, 1]`.
+    pub fn fbm(&self, x: f64, y: f64, octaves: u32) -> f64 {
+        let mut total = 0.0;
+        let mut amplitude = 1.0;
+        let mut frequency = 1.0;
+        let mut max_amplitude = 0.0;
+
+        for octave in 0..octaves {
+            // Offset each octave so that the lattice points of different octaves do not line up.
+            let offset = octave as f64 * 17.31;
+            total += self.sample(x * frequency + offset, y * frequency - offset) * amplitude;
+            max_amplitude += amplitude;
+            amplitude *= 0.5;
+            frequency *= 2.0;
+        }
+
+        if max_amplitude > 0.0 { total / max_amplitude } else { 0.0 }
+    }
+
+    /// The dot product of the lattice point's pseudo-random gradient with the offset `(dx, dy)`.
+    fn gradient(&self, xi: i64, yi: i64, dx: f64, dy: f64) -> f64 {
+        match hash(self.seed, xi, yi) & 7 {
+            0 => dx + dy,
+            1 => dx - dy,
+            2 => -dx + dy,
+            3 => -dx - dy,
+            4 => dx,
+            5 => -dx,
+            6 => dy,
+            _ => -dy,
+        }
+    }
+}
+
+/// Perlin's quintic fade curve, `6t^5 - 15t^4 + 10t^3`.
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(a: f64, b: f64, t: f64) -> f64 {
+    a + (b - a) * t
+}
+
+/// Mixes the seed and lattice coordinates with the SplitMix64 finaliser.
+fn hash(seed: u64, x: i64, y: i64) -> u64 {
+    let mut h = seed
+        ^ (x as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+        ^ (y as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F);
+    h = (h ^ (h >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    h = (h ^ (h >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    h ^ (h >> 31)
+}
@@ -0,0 +1,177 @@
+//! An endless overworld. Every block is a function of its global tile coordinates and the seed
+//! alone, so regions can be generated in any order and neighbouring regions always line up.
+
+use super::noise::GradientNoise;
+use super::*;
+
+// Salts that give the elevation, moisture and vegetation fields independent noise from one seed.
+const ELEVATION_SALT: u64 = 0x454C_4556;
+const MOISTURE_SALT: u64 = 0x4D4F_4953;
+const VEGETATION_SALT: u64 = 0x5645_4745;
+
+#[derive(Clone, Debug)]
+pub struct OverworldGenerator {
+    /// The size of continents and mountain ranges, in tiles per noise lattice cell.
+    pub elevation_scale: f64,
+    /// The size of wet and dry climate zones, in tiles per noise lattice cell.
+    pub moisture_scale: f64,
+    /// Elevation below which the surface is flooded.
+    pub sea_level: f64,
+    /// Elevation above which the surface is bare rock, and above that impassable mountain.
+    pub hill_level: f64,
+    pub mountain_level: f64,
+}
+
+impl Default for OverworldGenerator {
+    fn default() -> Self {
+        Self {
+            elevation_scale: 96.0,
+            moisture_scale: 160.0,
+            sea_level: -0.15,
+            hill_level: 0.25,
+            mountain_level: 0.35,
+        }
+    }
+}
+
+impl OverworldGenerator {
+    /// The height of the land at global tile `(x, y)`, roughly between -1 and 1.
+    fn elevation(&self, seed: u64, x: i64, y: i64) -> f64 {
+        GradientNoise::new(seed ^ ELEVATION_SALT).fbm(
+            x as f64 / self.elevation_scale,
+            y as f64 / self.elevation_scale,
+            5,
+        )
+    }
+
+    /// The surface block at global tile `(x, y)`.
+    fn surface_fill(&self, seed: u64, x: i64, y: i64) -> BlockFill {
+        let elevation = self.elevation(seed, x, y);
+        let moisture = GradientNoise::new(seed ^ MOISTURE_SALT).fbm(
+            x as f64 / self.moisture_scale,
+            y as f64 / self.moisture_scale,
+            3,
+        );
+
+        if elevation < self.sea_level {
+            return BlockFill::Floor(MaterialId::WATER);
+        } else if elevation < self.sea_level + 0.04 {
+            return BlockFill::Floor(MaterialId::SAND);
+        } else if elevation >= self.mountain_level {
+            return BlockFill::Solid(MaterialId::GRANITE);
+        } else if elevation >= self.hill_level {
+            return if moisture > 0.25 {
+                BlockFill::Floor(MaterialId::SNOW)
+            } else {
+                BlockFill::Floor(MaterialId::STONE)
+            };
+        }
+
+        if moisture < -0.25 {
+            BlockFill::Floor(MaterialId::SAND)
+        } else if moisture < 0.0 {
+            BlockFill::Floor(MaterialId::DIRT)
+        } else {
+            // Wetter land grows denser forest. The vegetation noise is high frequency so trees
+            // form clumps instead of solid walls.
+            let vegetation = GradientNoise::new(seed ^ VEGETATION_SALT)
+                .sample(x as f64 / 3.0, y as f64 / 3.0);
+            if vegetation > 0.6 - moisture {
+                BlockFill::Solid(MaterialId::TREE)
+            } else {
+                BlockFill::Floor(MaterialId::GRASS)
+            }
+        }
+    }
+}
+
+impl WorldGenerator for OverworldGenerator {
+    fn generate_region(&mut self, seed: u64, (rx, ry, z): (i32, i32, i32)) -> Region {
+        // Only the surface level has terrain; below it is rock and above it is open sky.
+        if z < 0 {
+            return Region::filled(BlockFill::Solid(MaterialId::STONE));
+        } else if z > 0 {
+            return Region::filled(BlockFill::Empty);
+        }
+
+        let mut blocks = Vec::with_capacity(REGION_LEN);
+        for y in 0..REGION_DIM as i64 {
+            for x in 0..REGION_DIM as i64 {
+                let global_x = rx as i64 * REGION_DIM as i64 + x;
+                let global_y = ry as i64 * REGION_DIM as i64 + y;
+                blocks.push(Block::new(self.surface_fill(seed, global_x, global_y)));
+            }
+        }
+
+        Region { blocks }
+    }
+
+    fn ambient_light(&self, z: i32) -> [f32; 3] {
+        if z >= 0 { DAYLIGHT } else { DARKNESS }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIM: i64 = REGION_DIM as i64;
+    const SEED: u64 = 42;
+
+    /// The tiles along the edges of `a` and `b` that face each other, in global coordinates, for
+    /// horizontally or vertically adjacent regions.
+    fn facing_edges(a: (i32, i32), b: (i32, i32)) -> Vec<((i64, i64), (i64, i64))> {
+        let (ax, ay) = (a.0 as i64 * DIM, a.1 as i64 * DIM);
+        (0..DIM)
+            .map(|i| match (b.0 - a.0, b.1 - a.1) {
+                (1, 0) => ((ax + DIM - 1, ay + i), (ax + DIM, ay + i)),
+                (0, 1) => ((ax + i, ay + DIM - 1), (ax + i, ay + DIM)),
+                _ => panic!("{:?} is not right of or below {:?}", b, a),
+            })
+            .collect()
+    }
+
+    fn tile(region: &Region, (rx, ry): (i32, i32), (x, y): (i64, i64)) -> BlockFill {
+        let (lx, ly) = (x - rx as i64 * DIM, y - ry as i64 * DIM);
+        region.blocks[(ly * DIM + lx) as usize].fill
+    }
+
+    #[test]
+    fn neighbouring_regions_line_up() {
+        let pairs = [
+            ((-1, -1), (0, -1)),
+            ((-1, -1), (-1, 0)),
+            ((-1, 0), (0, 0)),
+            ((0, -1), (0, 0)),
+        ];
+        for &(a, b) in &pairs {
+            // Each region is generated on its own, and in the opposite order to its neighbour.
+            let mut generator = OverworldGenerator::default();
+            let region_b = generator.generate_region(SEED, (b.0, b.1, 0));
+            let region_a = OverworldGenerator::default().generate_region(SEED, (a.0, a.1, 0));
+
+            for ((ax, ay), (bx, by)) in facing_edges(a, b) {
+                assert_eq!(tile(&region_a, a, (ax, ay)), generator.surface_fill(SEED, ax, ay));
+                assert_eq!(tile(&region_b, b, (bx, by)), generator.surface_fill(SEED, bx, by));
+
+                // No seam: the land barely changes height from one side of the border to the other.
+                let step = generator.elevation(SEED, ax, ay) - generator.elevation(SEED, bx, by);
+                assert!(step.abs() < 0.1, "seam between {:?} and {:?}", (ax, ay), (bx, by));
+            }
+        }
+    }
+
+    #[test]
+    fn the_same_seed_makes_the_same_tiles() {
+        for &offset in &[(0, 0, 0), (-3, 2, 0), (5, -7, 0)] {
+            let region = OverworldGenerator::default().generate_region(SEED, offset);
+            assert_eq!(region, OverworldGenerator::default().generate_region(SEED, offset));
+        }
+
+        let mut generator = OverworldGenerator::default();
+        let regions = |generator: &mut OverworldGenerator, seed| {
+            (-2..2).map(|x| generator.generate_region(seed, (x, 0, 0))).collect::<Vec<_>>()
+        };
+        assert_ne!(regions(&mut generator, SEED), regions(&mut generator, SEED + 1));
+    }
+}
@@ -0,0 +1,95 @@
+use rand::distributions::{Distribution, Uniform};
+
+use super::*;
+
+/// Scatters non-overlapping rectangular rooms over a solid map. Nothing connects the rooms; see
+/// `BspGenerator` for a layout where every room is reachable.
+#[derive(Clone, Debug)]
+pub struct RoomsGenerator {
+    pub width: u32,
+    pub height: u32,
+    pub room_count: u32,
+}
+
+impl RoomsGenerator {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            room_count: 20,
+        }
+    }
+}
+
+impl MapGenerator for RoomsGenerator {
+    fn generate_map(&self, rng: &mut GenRng) -> TileMap {
+        let mut map_builder = MapBuilder {
+            width: self.width,
+            height: self.height,
+            rooms: Vec::new(),
+        };
+
+        for _ in 0..self.room_count {
+            map_builder.add_random_room(rng);
+        }
+
+        map_builder.build()
+    }
+}
+
+struct MapBuilder {
+    width: u32,
+    height: u32,
+    rooms: Vec<Rect>,
+}
+
+impl MapBuilder {
+    fn add_room(&mut self, x: i32, y: i32, width: u32, height: u32) -> bool {
+        let r = Rect::new(x, y, width, height);
+
+        if r.x2 > self.width as i32 || r.y2 > self.height as i32 { return false };
+
+        if let Some(_) = self.rooms.iter().find(|&&room| room.overlaps(r)) {
+            false
+        } else {
+            self.rooms.push(r);
+            true
+        }
+    }
+
+    fn add_random_room(&mut self, rng: &mut GenRng) -> bool {
+        let x = Uniform::from(0..self.width);
+        let y = Uniform::from(0..self.height);
+        let width = Uniform::from(4..12);
+        let height = Uniform::from(3..6);
+
+        let mut tries: u32 = 0;
+
+        loop {
+            let rand_x = x.sample(rng);
+            let rand_y = y.sample(rng);
+
+            let rand_width = width.sample(rng);
+            let rand_height = height.sample(rng);
+
+            if self.add_room(rand_x as i32, rand_y as i32, rand_width, rand_height) {
+                return true
+            } else if tries >= 200 {
+                return false
+            } else {
+                tries += 1;
+                continue
+            }
+        }
+    }
+
+    fn build(self) -> TileMap {
+        let mut map = TileMap::filled(self.width, self.height, BlockFill::Solid(MaterialId::STONE));
+
+        for room in self.rooms {
+            map.fill_rect(room, BlockFill::Floor(MaterialId::STONE));
+        }
+
+        map
+    }
+}
@@ -1,15 +1,72 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
 use crate::util::prelude::*;
+use crate::state::combat::{Attack, DamageType, Defence, Dice, RangedAttack};
+use crate::state::ecs::{Appearance, Blocking, Ecs, EntityId, Health, PlayerControlled};
+use crate::state::event::Event;
+use crate::state::fov::Vision;
+use crate::state::item::Inventory;
+use crate::state::light::{Falloff, Light, Lighting};
+use crate::state::material::MaterialRegistry;
+use crate::state::path::Navigation;
+use crate::state::scheduler::{Actor, Scheduler, TICKS_PER_TURN};
+
+pub mod gen;
+mod save;
+mod store;
+#[cfg(test)]
+pub mod testing;
+mod view;
+
+use rand::Rng;
+
+use gen::{seeded_rng, GenRng};
+
+pub use gen::WorldGenerator;
+pub use save::SaveError;
+pub use store::{RegionStore, DEFAULT_REGION_BUDGET};
+pub use view::Visibility;
+
+use store::Residency;
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct WorldId(u32);
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, serde::Deserialize)]
+#[serde(transparent)]
 pub struct MaterialId(u32);
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+/// The materials the built-in generators place. What they look like and how they behave is defined
+/// by the material registry.
+impl MaterialId {
+    pub const STONE: Self = Self(0);
+    pub const GRANITE: Self = Self(1);
+    pub const DIRT: Self = Self(2);
+    pub const GRASS: Self = Self(3);
+    pub const SAND: Self = Self(4);
+    pub const WATER: Self = Self(5);
+    pub const WOOD: Self = Self(6);
+    pub const SNOW: Self = Self(7);
+    pub const TREE: Self = Self(8);
+
+    /// The id of the placeholder the registry hands out for materials it does not know.
+    pub const UNKNOWN: Self = Self(u32::MAX);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize)]
 pub struct Tick(u64);
 
+impl Tick {
+    pub fn new(ticks: u64) -> Self {
+        Self(ticks)
+    }
+
+    /// The number of ticks since the start of the world.
+    pub fn ticks(self) -> u64 {
+        self.0
+    }
+}
+
 impl std::ops::Add<u64> for Tick {
     type Output = Self;
 
@@ -40,30 +97,249 @@ pub enum BlockFill {
     Empty,
 }
 
+/// Something in a block that leads to a neighbouring z-level. Positive z is up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Connector {
+    /// Leads up to the level above.
+    StairsUp,
+    /// Leads down to the level below.
+    StairsDown,
+    /// Leads both up and down.
+    Ladder,
+}
+
+impl Connector {
+    pub fn leads_up(self) -> bool {
+        matches!(self, Connector::StairsUp | Connector::Ladder)
+    }
+
+    pub fn leads_down(self) -> bool {
+        matches!(self, Connector::StairsDown | Connector::Ladder)
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Block {
     pub fill: BlockFill,
+    pub connector: Option<Connector>,
 }
 
-#[derive(Clone, Debug)]
+impl Block {
+    pub fn new(fill: BlockFill) -> Self {
+        Self {
+            fill,
+            connector: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Vertical {
+    Up,
+    Down,
+}
+
+/// One of the eight directions of movement within a level. North is towards negative y.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Direction {
+    North,
+    NorthEast,
+    East,
+    SouthEast,
+    South,
+    SouthWest,
+    West,
+    NorthWest,
+}
+
+impl Direction {
+    pub const ALL: [Direction; 8] = [
+        Direction::North,
+        Direction::NorthEast,
+        Direction::East,
+        Direction::SouthEast,
+        Direction::South,
+        Direction::SouthWest,
+        Direction::West,
+        Direction::NorthWest,
+    ];
+
+    /// The change in `(x, y)` from taking one step in this direction.
+    pub fn delta(self) -> (i32, i32) {
+        match self {
+            Direction::North => (0, -1),
+            Direction::NorthEast => (1, -1),
+            Direction::East => (1, 0),
+            Direction::SouthEast => (1, 1),
+            Direction::South => (0, 1),
+            Direction::SouthWest => (-1, 1),
+            Direction::West => (-1, 0),
+            Direction::NorthWest => (-1, -1),
+        }
+    }
+
+    pub fn is_diagonal(self) -> bool {
+        let (dx, dy) = self.delta();
+        dx != 0 && dy != 0
+    }
+
+    /// The tile one step from `pos` in this direction, on the same level.
+    pub fn step(self, (x, y, z): (i32, i32, i32)) -> (i32, i32, i32) {
+        let (dx, dy) = self.delta();
+        (x + dx, y + dy, z)
+    }
+}
+
+
+/// Splits a global tile position into the offset of the region that holds it and the index of the
+/// block within that region. Negative coordinates round towards negative infinity, so tile -1 is
+/// the last tile of region -1.
+pub fn split_position((x, y, z): (i32, i32, i32)) -> ((i32, i32, i32), usize) {
+    let dim = REGION_DIM as i32;
+    let offset = (x.div_euclid(dim), y.div_euclid(dim), z);
+    let idx = (y.rem_euclid(dim) * dim + x.rem_euclid(dim)) as usize;
+
+    (offset, idx)
+}
+
+/// How far something can fall before it is assumed to have dropped out of the world.
+const MAX_FALL: i32 = 64;
+
+/// How far from the requested position `World::spawn_player` looks for somewhere to stand.
+const SPAWN_SEARCH_RADIUS: i32 = 64;
+
+/// The hardness of a material that takes a turn to dig through.
+const HARDNESS_PER_TURN: u64 = 10;
+
+/// How many random tiles `World::random_free_tile` tries before giving up.
+const PLACEMENT_TRIES: u32 = 100;
+
+const PLAYER_FG: [f32; 4] = [1.0, 1.0, 0.3, 1.0];
+const PLAYER_SIGHT_RADIUS: u32 = 12;
+/// The torch the player carries.
+const PLAYER_LIGHT: Light = Light {
+    colour: [1.0, 0.8, 0.55],
+    radius: 8,
+    falloff: Falloff::Quadratic,
+};
+const PLAYER_HEALTH: i32 = 30;
+const PLAYER_ATTACK: Attack = Attack {
+    to_hit: 3,
+    damage: Dice::new(1, 6, 1),
+    damage_type: DamageType::Slashing,
+};
+/// The sling the player starts with.
+const PLAYER_RANGED_ATTACK: RangedAttack = RangedAttack {
+    to_hit: 1,
+    damage: Dice::new(1, 4, 0),
+    damage_type: DamageType::Blunt,
+    range: 8,
+};
+const PLAYER_EVASION: i32 = 2;
+/// How much the player can carry, in grams.
+const PLAYER_MAX_WEIGHT: u32 = 25_000;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Region {
     /// This vector is guaranteed to be REGION_DIM^2 in size.
     pub blocks: Vec<Block>,
 }
 
+impl Region {
+    pub fn filled(fill: BlockFill) -> Self {
+        Self {
+            blocks: vec![Block::new(fill); REGION_LEN],
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct CachedRegion {
     pub region: Region,
     /// The last tick on which this region was updated in world memory. This is useful for caching
     /// world state on render.
     pub last_update_tick: Tick,
+    /// Which blocks the player has ever seen. This vector is guaranteed to be REGION_DIM^2 in size.
+    pub explored: Vec<bool>,
+    /// Counts the writes made to this region since it was loaded, so that several changes made
+    /// within the same tick can still be told apart.
+    revision: u64,
+    /// Counts the changes to what the player can see of this region.
+    view_revision: u64,
+    /// Whether the region differs from its copy in the region store, or from what the generator
+    /// would make of it if it was never stored. Only dirty regions are written back on eviction.
+    dirty: bool,
 }
 
-#[derive(Clone, Debug)]
+impl CachedRegion {
+    pub fn new(region: Region, last_update_tick: Tick) -> Self {
+        Self {
+            region,
+            last_update_tick,
+            explored: vec![false; REGION_LEN],
+            revision: 0,
+            view_revision: 0,
+            dirty: false,
+        }
+    }
+
+    /// Identifies the blocks of this region. It changes on every write, even when the tick does
+    /// not.
+    pub fn version(&self) -> (Tick, u64) {
+        (self.last_update_tick, self.revision)
+    }
+
+    /// Identifies what the player can see of this region. It changes whenever the blocks do and
+    /// whenever the player's view of the region changes.
+    pub fn view_version(&self) -> (Tick, u64, u64) {
+        (self.last_update_tick, self.revision, self.view_revision)
+    }
+}
+
+/// Cached regions compare by their contents, what has been explored and when they were last
+/// updated. How many changes it took to get there, and whether they have been stored yet, do not
+/// take part.
+impl PartialEq for CachedRegion {
+    fn eq(&self, other: &Self) -> bool {
+        self.region == other.region
+            && self.explored == other.explored
+            && self.last_update_tick == other.last_update_tick
+    }
+}
+
+impl Eq for CachedRegion {}
+
+#[derive(Debug)]
 pub struct World {
     pub id: WorldId,
+    /// The seed handed to the generator. Regions that have never been generated before are
+    /// generated from this seed the first time they are accessed.
+    pub seed: u64,
+    generator: Box<dyn WorldGenerator>,
+    /// Gives meaning to the `MaterialId`s stored in blocks.
+    pub materials: MaterialRegistry,
     /// Regions currently loaded into memory.
     regions: HashMap<(i32, i32, i32), CachedRegion>,
+    /// Where regions are streamed from and evicted to. Without a store, every region stays in
+    /// memory for the lifetime of the world once it has been generated.
+    store: Option<RegionStore>,
+    residency: Residency,
+    /// Everything in the world that is not terrain.
+    pub entities: Ecs,
+    /// The entity the player controls, once they have been spawned into the world.
+    pub player: Option<EntityId>,
+    /// Who acts next. Saved along with the entities, so turns carry on in the same order after a
+    /// load.
+    pub scheduler: Scheduler,
+    /// The tiles the player can currently see, or `None` when there is no player and everything
+    /// is shown.
+    view: Option<HashSet<(i32, i32, i32)>>,
+    /// Cached light levels. Not saved; they are recomputed on demand.
+    pub lighting: Lighting,
+    /// Cached moves between tiles for pathfinding. Not saved; they are recomputed on demand.
+    pub navigation: Navigation,
+    /// What has happened since the UI last asked. Not saved.
+    pub events: Vec<Event>,
 
     /// The current tick of the simulated world. There are 1000 ticks in a given turn. If a player
     /// makes 10,000 turns per second (a massive overestimate), then a world may safely be simulated
@@ -72,165 +348,507 @@ pub struct World {
 }
 
 impl World {
-    pub fn new() -> Self {
-        // TODO: Remove this camera; this is only here so we can generate a debug map that fills
-        // the screen.
-        let camera = crate::gfx::camera::Camera {
-            world_offset: (0, 0, 0),
-            region_offset: (0, 0),
-            // TODO: Assuming glyph size of (10, 20) and window size of (1280, 720).
-            tiles_dims: (128, 36),
-        };
-
+    pub fn new(generator: Box<dyn WorldGenerator>, seed: u64) -> Self {
         Self {
             id: WorldId(0),
-            regions: DEBUG_gen_regions(&camera),
+            seed,
+            generator,
+            materials: MaterialRegistry::default(),
+            regions: HashMap::new(),
             current_tick: Tick(1),
+            store: None,
+            residency: Residency::default(),
+            entities: Ecs::new(),
+            player: None,
+            scheduler: Scheduler::new(),
+            view: None,
+            lighting: Lighting::default(),
+            navigation: Navigation::default(),
+            events: Vec::new(),
         }
     }
 
+    pub fn with_materials(mut self, materials: MaterialRegistry) -> Self {
+        self.materials = materials;
+        self
+    }
+
+    /// Backs this world with `store`. Regions missing from memory are loaded from the store on
+    /// demand, and once more than `region_budget` regions are resident the least recently used
+    /// ones are written back and dropped.
+    pub fn with_region_store(mut self, store: RegionStore, region_budget: usize) -> Self {
+        self.store = Some(store);
+        self.residency.budget = region_budget;
+        self
+    }
+
+    /// Returns the region only if it is already resident. Use `World::load_region` to stream it
+    /// in from the region store or generate it.
     pub fn get_cached_region(&self, offset: (i32, i32, i32)) -> Option<&CachedRegion> {
         self.regions.get(&offset)
     }
-}
 
-//
-// TEMP/DEBUG MAP GENERATION CODE
-//
+    /// Returns the region at `offset`. Regions that are not resident are read from the region
+    /// store, or generated if they have never existed before. Either way the region is marked as
+    /// recently used.
+    pub fn load_region(&mut self, offset: (i32, i32, i32)) -> &CachedRegion {
+        if !self.regions.contains_key(&offset) {
+            let stored = match &self.store {
+                Some(store) => store.read_region(offset).unwrap_or_else(|e| {
+                    log::error!("Failed to read region {:?} from the region store: {}", offset, e);
+                    None
+                }),
+                None => None,
+            };
+
+            let cached_region = match stored {
+                Some(cached_region) => cached_region,
+                None => CachedRegion::new(
+                    self.generator.generate_region(self.seed, offset),
+                    self.current_tick,
+                ),
+            };
+
+            self.regions.insert(offset, cached_region);
+        }
 
-use rand::distributions::{Distribution, Uniform};
+        self.residency.touch(offset);
+        &self.regions[&offset]
+    }
 
-#[allow(non_snake_case)]
-fn DEBUG_gen_regions(camera: &crate::gfx::camera::Camera) -> HashMap<(i32, i32, i32), CachedRegion> {
-    let (num_tiles_x, num_tiles_y) = camera.tiles_dims;
+    /// Returns the block at `pos` in global tile coordinates, loading its region if needed.
+    pub fn block_at(&mut self, pos: (i32, i32, i32)) -> &Block {
+        let (offset, idx) = split_position(pos);
+        &self.load_region(offset).region.blocks[idx]
+    }
 
-    let mut map_builder = MapBuilder {
-        width: num_tiles_x,
-        height: num_tiles_y,
-        rooms: Vec::new(),
-    };
+    /// Replaces the block at `pos` in global tile coordinates, loading its region if needed. The
+    /// region is marked as updated on the current tick.
+    pub fn set_block(&mut self, pos: (i32, i32, i32), block: Block) {
+        let (offset, idx) = split_position(pos);
+        self.load_region(offset);
+
+        let current_tick = self.current_tick;
+        let cached_region = self.regions.get_mut(&offset).expect("region was just loaded");
+        cached_region.region.blocks[idx] = block;
+        cached_region.last_update_tick = current_tick;
+        cached_region.revision += 1;
+        cached_region.dirty = true;
+    }
 
-    for _ in 0..20 {
-        map_builder.add_random_room();
+    /// Whether something walking could stand at `pos`: the block has a floor that can be walked on
+    /// and is not filled solid.
+    pub fn can_stand_at(&mut self, pos: (i32, i32, i32)) -> bool {
+        let fill = self.block_at(pos).fill;
+        matches!(fill, BlockFill::Floor(_) | BlockFill::FloorCeiling(_, _))
+            && !self.materials.blocks_movement(fill)
     }
 
-    map_builder.build()
-}
+    /// Whether something can move into `pos`: the terrain does not block movement and no
+    /// blocking entity is already there. Blocks without a floor can be entered; whatever enters
+    /// them falls.
+    pub fn can_enter(&mut self, pos: (i32, i32, i32)) -> bool {
+        let fill = self.block_at(pos).fill;
+        if self.materials.blocks_movement(fill) {
+            return false;
+        }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-struct Rect {
-    x1: i32,
-    y1: i32,
-    x2: i32,
-    y2: i32,
-}
+        let entities = &self.entities;
+        !entities.at(pos).any(|id| entities.has::<Blocking>(id))
+    }
+
+    /// A random tile within `radius` tiles of `center`, on the same level, that something could
+    /// both stand at and move into. Returns `None` if none of the tiles tried is free, which
+    /// becomes likely when there is little free floor around `center`.
+    pub fn random_free_tile(
+        &mut self,
+        rng: &mut GenRng,
+        center: (i32, i32, i32),
+        radius: i32,
+    ) -> Option<(i32, i32, i32)> {
+        for _ in 0..PLACEMENT_TRIES {
+            let pos = (
+                center.0 + rng.gen_range(-radius, radius + 1),
+                center.1 + rng.gen_range(-radius, radius + 1),
+                center.2,
+            );
+
+            if self.can_stand_at(pos) && self.can_enter(pos) {
+                return Some(pos);
+            }
+        }
 
-impl Rect {
-    fn overlaps(&self, other: Rect) -> bool {
-        self.x1 < other.x2 && self.x2 > other.x1 && self.y1 > other.y2 && self.y2 < other.y1
+        None
     }
-}
 
-struct MapBuilder {
-    width: u32,
-    height: u32,
-    rooms: Vec<Rect>,
-}
+    /// The rng for what `entity` does on the current tick. Each use passes its own `salt`, so that
+    /// say the dice an entity rolls do not follow the choices it makes on the same tick.
+    pub fn entity_rng(&self, salt: u64, entity: EntityId) -> GenRng {
+        let entity_bits = (entity.index() as u64) << 32 | entity.generation() as u64;
+        seeded_rng(self.seed ^ salt ^ entity_bits, self.current_tick.ticks())
+    }
 
-impl MapBuilder {
-    fn add_room(&mut self, x: i32, y: i32, width: u32, height: u32) -> bool {
-        let r = Rect {
-            x1: x,
-            y1: y,
-            x2: x + width as i32,
-            y2: y + height as i32,
-        };
+    /// Where the player is, if they have been spawned.
+    pub fn player_position(&self) -> Option<(i32, i32, i32)> {
+        self.entities.position(self.player?)
+    }
 
-        if r.x2 > self.width as i32 || r.y2 > self.height as i32 { return false };
+    /// Creates the player entity at `pos`, replacing any previous player.
+    pub fn place_player(&mut self, pos: (i32, i32, i32)) -> EntityId {
+        if let Some(player) = self.player.take() {
+            self.entities.despawn(player);
+            self.scheduler.unschedule(player);
+        }
 
-        if let Some(_) = self.rooms.iter().find(|&&room| room.overlaps(r)) {
-            false
-        } else {
-            self.rooms.push(r);
-            true
+        let player = self.entities.spawn();
+        self.entities.insert(player, PlayerControlled);
+        self.entities.insert(player, Blocking);
+        self.entities.insert(player, Appearance { glyph: "@".into(), fg: PLAYER_FG });
+        self.entities.insert(player, Actor::default());
+        self.entities.insert(player, Vision { radius: PLAYER_SIGHT_RADIUS });
+        self.entities.insert(player, PLAYER_LIGHT);
+        self.entities.insert(player, Health::new(PLAYER_HEALTH));
+        self.entities.insert(player, PLAYER_ATTACK);
+        self.entities.insert(player, PLAYER_RANGED_ATTACK);
+        self.entities.insert(player, Defence { evasion: PLAYER_EVASION, ..Defence::default() });
+        self.entities.insert(player, Inventory::new(PLAYER_MAX_WEIGHT));
+        self.entities.set_position(player, pos);
+        self.schedule_actor(player);
+
+        self.player = Some(player);
+        self.update_player_view();
+        player
+    }
+
+    /// Places the player on the closest tile to `near` that can be stood on, searching outwards
+    /// ring by ring on the same level. Returns where the player was placed, or `None` if there is
+    /// nowhere to stand nearby.
+    pub fn spawn_player(&mut self, near: (i32, i32, i32)) -> Option<(i32, i32, i32)> {
+        let (cx, cy, z) = near;
+
+        for radius in 0..=SPAWN_SEARCH_RADIUS {
+            for y in cy - radius..=cy + radius {
+                for x in cx - radius..=cx + radius {
+                    let on_ring = (x - cx).abs() == radius || (y - cy).abs() == radius;
+                    if on_ring && self.can_stand_at((x, y, z)) {
+                        self.place_player((x, y, z));
+                        return Some((x, y, z));
+                    }
+                }
+            }
         }
+
+        None
     }
 
-    fn add_random_room(&mut self) -> bool {
-        let mut rng = rand::thread_rng();
+    /// Moves `entity` one step in `direction`, dropping it down any hole it walks into. Returns
+    /// `false` without moving if the entity has no position or the way is blocked.
+    pub fn move_entity(&mut self, entity: EntityId, direction: Direction) -> bool {
+        let target = match self.entities.position(entity) {
+            Some(position) => direction.step(position),
+            None => return false,
+        };
+
+        if !self.can_enter(target) {
+            return false;
+        }
 
-        let x = Uniform::from(0..self.width);
-        let y = Uniform::from(0..self.height);
-        let width = Uniform::from(4..12);
-        let height = Uniform::from(3..6);
+        let target = self.fall(target);
+        self.entities.set_position(entity, target);
+        true
+    }
 
-        let mut tries: u32 = 0;
+    /// Digs through the solid block next to `entity` in `direction`, leaving a floor of the same
+    /// material. Returns `false` if the entity has no position or there is nothing solid there.
+    pub fn dig(&mut self, entity: EntityId, direction: Direction) -> bool {
+        let target = match self.entities.position(entity) {
+            Some(position) => direction.step(position),
+            None => return false,
+        };
 
-        loop {
-            let rand_x = x.sample(&mut rng);
-            let rand_y = y.sample(&mut rng);
+        let material = match self.block_at(target).fill {
+            BlockFill::Solid(material) => material,
+            _ => return false,
+        };
 
-            let rand_width = width.sample(&mut rng);
-            let rand_height = height.sample(&mut rng);
+        let connector = self.block_at(target).connector;
+        self.set_block(target, Block { fill: BlockFill::Floor(material), connector });
+        self.events.push(Event::Dug {
+            name: self.name_of(entity),
+            material: self.materials.get(material).name.clone(),
+        });
+        true
+    }
 
-            if self.add_room(rand_x as i32, rand_y as i32, rand_width, rand_height) {
-                return true
-            } else if tries >= 200 {
-                return false
-            } else {
-                tries += 1;
-                continue
+    /// The ticks a normal-speed actor spends digging next to `entity` in `direction`: longer the
+    /// harder the material. Nothing to dig through costs nothing, since digging fails anyway.
+    pub fn dig_cost(&mut self, entity: EntityId, direction: Direction) -> u64 {
+        let target = match self.entities.position(entity) {
+            Some(position) => direction.step(position),
+            None => return 0,
+        };
+
+        match self.block_at(target).fill {
+            BlockFill::Solid(material) => {
+                let hardness = self.materials.get(material).hardness as u64;
+                hardness * TICKS_PER_TURN / HARDNESS_PER_TURN
+            },
+            _ => 0,
+        }
+    }
+
+    /// Takes the connector `entity` is standing on in `direction`. Returns `false` without moving
+    /// if the entity has no position or there is no connector going that way.
+    pub fn climb_entity(&mut self, entity: EntityId, direction: Vertical) -> bool {
+        let target = match self.entities.position(entity) {
+            Some(position) => self.climb(position, direction),
+            None => return false,
+        };
+
+        match target {
+            Some(target) => {
+                let target = self.fall(target);
+                self.entities.set_position(entity, target);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Where something let go of at `pos` comes to rest. Blocks without a floor drop whatever is
+    /// in them into the block below, unless the block below is solid and can be stood on.
+    pub fn fall(&mut self, pos: (i32, i32, i32)) -> (i32, i32, i32) {
+        let mut pos = pos;
+
+        for _ in 0..MAX_FALL {
+            match self.block_at(pos).fill {
+                BlockFill::Empty | BlockFill::Ceiling(_) => {},
+                _ => break,
+            }
+
+            let below = (pos.0, pos.1, pos.2 - 1);
+            if let BlockFill::Solid(_) = self.block_at(below).fill {
+                break;
             }
+
+            pos = below;
         }
+
+        pos
     }
 
-    fn build(self) -> HashMap<(i32, i32, i32), CachedRegion> {
-        let mut map = HashMap::new();
+    /// Where taking the connector at `pos` in `direction` leads, or `None` if there is no
+    /// connector going that way.
+    pub fn climb(
+        &mut self,
+        pos: (i32, i32, i32),
+        direction: Vertical,
+    ) -> Option<(i32, i32, i32)> {
+        let connector = self.block_at(pos).connector?;
+        match direction {
+            Vertical::Up if connector.leads_up() => Some((pos.0, pos.1, pos.2 + 1)),
+            Vertical::Down if connector.leads_down() => Some((pos.0, pos.1, pos.2 - 1)),
+            _ => None,
+        }
+    }
 
-        let mut flat_map = vec!['#'; (self.width * self.height) as usize];
+    /// The light that reaches every tile on level `z` without any light source nearby.
+    pub fn ambient_light(&self, z: i32) -> [f32; 3] {
+        self.generator.ambient_light(z)
+    }
 
-        for room in self.rooms {
-            for y in room.y1..room.y2 {
-                for x in room.x1..room.x2 {
-                    flat_map[(y * self.width as i32 + x) as usize] = '.';
-                }
+    /// Drops the least recently used regions from memory until the world is within its region
+    /// budget, writing back the ones that changed since they were loaded. Does nothing for worlds
+    /// without a store.
+    pub fn evict_regions(&mut self) -> Result<(), SaveError> {
+        let store = match &self.store {
+            Some(store) => store,
+            None => return Ok(()),
+        };
+
+        if self.regions.len() <= self.residency.budget {
+            return Ok(());
+        }
+
+        let residency = &self.residency;
+        let mut by_age: Vec<_> = self.regions.keys()
+            .map(|&offset| (residency.last_access(offset), offset))
+            .collect();
+        by_age.sort();
+
+        let excess = self.regions.len() - self.residency.budget;
+        for (_, offset) in by_age.into_iter().take(excess) {
+            let cached_region = &self.regions[&offset];
+            if cached_region.dirty {
+                store.write_region(offset, cached_region)?;
             }
+            self.regions.remove(&offset);
+            self.residency.forget(offset);
+            self.lighting.forget_region(offset);
+            self.navigation.forget_region(offset);
         }
 
-        let regions_wide = (self.width / REGION_DIM as u32) + 1;
-        let regions_tall = (self.height / REGION_DIM as u32) + 1;
-
-        for ry in 0..regions_tall {
-            for rx in 0..regions_wide {
-                let mut blocks = vec![ Block { fill: BlockFill::Solid(MaterialId(0)) }; REGION_LEN];
-                for y in 0..REGION_DIM {
-                    for x in 0..REGION_DIM {
-                        let global_y = ry * (REGION_DIM as u32) + y as u32;
-                        let global_x = rx * (REGION_DIM as u32) + x as u32;
-
-                        let idx: usize = (global_y * self.width + global_x) as usize;
-
-                        if idx >= flat_map.len() {
-                            continue
-                        } else {
-                            if flat_map[idx] == '.' {
-                                blocks[(y * REGION_DIM + x) as usize] = Block {
-                                    fill: BlockFill::Floor(MaterialId(0)),
-                                };
-                            }
-                        }
-                    }
+        Ok(())
+    }
+
+    /// Writes every resident region that changed since it was loaded to the region store, without
+    /// evicting anything.
+    pub fn flush_regions(&mut self) -> Result<(), SaveError> {
+        if let Some(store) = &self.store {
+            for (&offset, cached_region) in &mut self.regions {
+                if cached_region.dirty {
+                    store.write_region(offset, cached_region)?;
+                    cached_region.dirty = false;
                 }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes the world and every region loaded in memory to `path`, replacing any existing file.
+    /// A world backed by a region store records where the store is, so that regions evicted to it
+    /// are streamed back in after loading; the store has to stay where it is.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SaveError> {
+        let mut writer = std::io::BufWriter::new(std::fs::File::create(path)?);
+        save::write_world(&mut writer, self)?;
+        std::io::Write::flush(&mut writer)?;
+        Ok(())
+    }
+
+    /// Reads a world previously written by `World::save`. Regions that were never generated
+    /// before the save are generated by `generator`, which should be the generator the world was
+    /// created with. Materials are data rather than state, so they are not saved; the world uses
+    /// `materials` instead. Files from older format versions are migrated on load; files that are
+    /// not world saves, or that come from a newer build, are rejected with an error.
+    pub fn load(
+        path: impl AsRef<Path>,
+        generator: Box<dyn WorldGenerator>,
+        materials: MaterialRegistry,
+    ) -> Result<Self, SaveError> {
+        let mut reader = std::io::BufReader::new(std::fs::File::open(path)?);
+        save::read_world(&mut reader, generator, materials)
+    }
+}
+
+/// Worlds compare by their terrain and where the player is. The generator, where regions are
+/// streamed from and how recently they were used do not take part.
+impl PartialEq for World {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+            && self.seed == other.seed
+            && self.current_tick == other.current_tick
+            && self.regions == other.regions
+            && self.player_position() == other.player_position()
+    }
+}
+
+impl Eq for World {}
 
-                map.insert((rx as i32, ry as i32, 0), CachedRegion {
-                    region: Region {
-                        blocks,
-                    },
-                    last_update_tick: Tick(1),
-                });
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::testing::flat_world;
+
+    #[test]
+    fn positions_split_into_regions() {
+        let dim = REGION_DIM as i32;
+        let row = REGION_DIM as usize;
+
+        assert_eq!(split_position((0, 0, 0)), ((0, 0, 0), 0));
+        assert_eq!(split_position((dim - 1, dim - 1, 3)), ((0, 0, 3), REGION_LEN - 1));
+        assert_eq!(split_position((dim, 1, 0)), ((1, 0, 0), row));
+
+        // Negative coordinates round down into the region before, counting up from its corner.
+        assert_eq!(split_position((-1, -1, -2)), ((-1, -1, -2), REGION_LEN - 1));
+        assert_eq!(split_position((-dim, -dim - 1, 0)), ((-1, -2, 0), REGION_LEN - row));
+    }
+
+    #[test]
+    fn blocks_are_written_where_they_are_read() {
+        let mut world = flat_world();
+        let dim = REGION_DIM as i32;
+        let granite = Block::new(BlockFill::Solid(MaterialId::GRANITE));
+
+        // Both sides of the borders around the origin, on either side of zero.
+        let positions = [
+            (-1, -1, 0),
+            (0, 0, 0),
+            (dim - 1, 0, 0),
+            (dim, 0, 0),
+            (-dim, -dim - 1, -1),
+        ];
+        for &pos in &positions {
+            world.set_block(pos, granite.clone());
+        }
+
+        for &pos in &positions {
+            assert_eq!(*world.block_at(pos), granite, "{:?}", pos);
+
+            // The neighbours either side, across the border or not, are untouched.
+            for &dx in &[-1, 1] {
+                let beside = (pos.0 + dx, pos.1, pos.2);
+                if !positions.contains(&beside) {
+                    assert_eq!(world.block_at(beside).fill, BlockFill::Floor(MaterialId::STONE));
+                }
             }
         }
+    }
 
-        map
+    #[test]
+    fn set_block_marks_the_region_changed() {
+        let mut world = flat_world();
+        let before = world.load_region((-1, 0, 0)).version();
+
+        world.current_tick = Tick::new(5);
+        world.set_block((-1, 0, 0), Block::new(BlockFill::Empty));
+
+        let cached_region = &world.regions[&(-1, 0, 0)];
+        assert!(cached_region.dirty);
+        assert_eq!(cached_region.version(), (Tick::new(5), before.1 + 1));
+        assert_eq!(world.load_region((0, 0, 0)).version().1, 0);
+    }
+
+    #[test]
+    fn players_walk_in_eight_directions_but_not_through_walls() {
+        let mut world = flat_world();
+        let player = world.place_player((15, 8, 0));
+
+        world.set_block((16, 8, 0), Block::new(BlockFill::Solid(MaterialId::STONE)));
+        assert!(!world.move_entity(player, Direction::East));
+        assert_eq!(world.player_position(), Some((15, 8, 0)));
+
+        // Diagonal steps go around the wall into the next region.
+        assert!(world.move_entity(player, Direction::NorthEast));
+        assert_eq!(world.player_position(), Some((16, 7, 0)));
+        assert!(world.move_entity(player, Direction::SouthWest));
+        assert!(world.move_entity(player, Direction::SouthEast));
+        assert_eq!(world.player_position(), Some((16, 9, 0)));
+    }
+
+    #[test]
+    fn players_climb_connectors_and_fall_down_holes() {
+        let mut world = flat_world();
+        let player = world.place_player((4, 4, 0));
+
+        // Nothing to climb here.
+        assert!(!world.climb_entity(player, Vertical::Down));
+
+        let stairs = |connector| Block {
+            fill: BlockFill::Floor(MaterialId::STONE),
+            connector: Some(connector),
+        };
+        world.set_block((4, 4, 0), stairs(Connector::StairsDown));
+        world.set_block((4, 4, -1), stairs(Connector::StairsUp));
+
+        assert!(!world.climb_entity(player, Vertical::Up));
+        assert!(world.climb_entity(player, Vertical::Down));
+        assert_eq!(world.player_position(), Some((4, 4, -1)));
+        assert!(world.climb_entity(player, Vertical::Up));
+        assert_eq!(world.player_position(), Some((4, 4, 0)));
+
+        world.set_block((5, 4, 0), Block::new(BlockFill::Empty));
+        assert!(world.move_entity(player, Direction::East));
+        assert_eq!(world.player_position(), Some((5, 4, -1)));
     }
 }
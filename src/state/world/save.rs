@@ -0,0 +1,588 @@
+//! The on-disk world format. A save file is a small header (magic bytes and a format version)
+//! followed by the world metadata and every region currently held in memory. Region files written
+//! by the `RegionStore` use the same region encoding behind their own header. All integers are
+//! little-endian.
+//!
+//! Regions evicted to a `RegionStore` are not copied into the save; it records the store's root
+//! instead, and they are streamed back from there after loading.
+//!
+//! Entities and the turn order are saved whole, so a loaded world carries on exactly where the
+//! saved one left off. They are written as RON rather than by hand, since there are many component
+//! types and they change often.
+//!
+//! When the format changes, bump `FORMAT_VERSION` and teach `read_world` how to migrate the older
+//! layouts instead of rejecting them.
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+
+use super::*;
+
+const MAGIC: &[u8; 4] = b"RLWD";
+const REGION_MAGIC: &[u8; 4] = b"RLRG";
+
+/// The version written by `World::save`.
+pub const FORMAT_VERSION: u16 = 7;
+
+// Format history:
+//  1: initial format.
+//  2: the world seed follows the current tick. Version 1 worlds load with a seed of 0.
+//  3: every block fill is followed by a connector byte. Older blocks load without connectors.
+//  4: the player follows the seed, as a presence byte and a position. Older worlds load without a
+//     player.
+//  5: every region ends with a bitmap of its explored blocks, one bit per block, least
+//     significant bit first. Older regions load unexplored.
+//  6: the root of the world's region store follows the player, as a presence byte and a UTF-8
+//     path prefixed by its length in bytes. Older worlds load without a store.
+//  7: every entity, the player's id and the scheduler follow the regions, as a RON document
+//     prefixed by its length in bytes. Older worlds load with only a fresh player, rebuilt at the
+//     saved position.
+
+#[derive(Debug)]
+pub enum SaveError {
+    Io(io::Error),
+    /// The file does not start with the expected magic bytes, so it is not a save file at all.
+    BadMagic,
+    /// The file was written by a format version this build does not know how to read.
+    UnsupportedVersion(u16),
+    /// The file is a world save, but its contents are malformed.
+    Corrupt(&'static str),
+}
+
+impl std::fmt::Display for SaveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SaveError::Io(e) => write!(f, "i/o error: {}", e),
+            SaveError::BadMagic => write!(f, "not a world save file"),
+            SaveError::UnsupportedVersion(v) => write!(
+                f,
+                "unsupported world format version {} (this build reads up to version {})",
+                v, FORMAT_VERSION,
+            ),
+            SaveError::Corrupt(what) => write!(f, "corrupt world save: {}", what),
+        }
+    }
+}
+
+impl std::error::Error for SaveError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SaveError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SaveError {
+    fn from(e: io::Error) -> Self {
+        // A truncated file shows up as an early EOF; report that as corruption rather than as a
+        // generic i/o failure.
+        if e.kind() == io::ErrorKind::UnexpectedEof {
+            SaveError::Corrupt("unexpected end of file")
+        } else {
+            SaveError::Io(e)
+        }
+    }
+}
+
+pub fn write_world<W: Write>(w: &mut W, world: &World) -> Result<(), SaveError> {
+    w.write_all(MAGIC)?;
+    write_u16(w, FORMAT_VERSION)?;
+
+    write_u32(w, world.id.0)?;
+    write_u64(w, world.current_tick.0)?;
+    write_u64(w, world.seed)?;
+
+    match world.player_position() {
+        Some(position) => {
+            write_u8(w, 1)?;
+            write_position(w, position)?;
+        },
+        None => write_u8(w, 0)?,
+    }
+
+    match &world.store {
+        Some(store) => {
+            write_u8(w, 1)?;
+            write_string(w, &store.root().to_string_lossy())?;
+        },
+        None => write_u8(w, 0)?,
+    }
+
+    // Sort the regions so that saving the same world twice produces the same bytes.
+    let mut keys: Vec<_> = world.regions.keys().copied().collect();
+    keys.sort();
+
+    write_u32(w, keys.len() as u32)?;
+    for key in keys {
+        write_region_key(w, key)?;
+        write_cached_region(w, &world.regions[&key])?;
+    }
+
+    let entities = (&world.entities, world.player, &world.scheduler);
+    let entities = ron::ser::to_string(&entities)
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    write_string(w, &entities)?;
+
+    Ok(())
+}
+
+pub fn read_world<R: Read>(
+    r: &mut R,
+    generator: Box<dyn WorldGenerator>,
+    materials: MaterialRegistry,
+) -> Result<World, SaveError> {
+    let version = read_header(r, MAGIC)?;
+
+    let id = WorldId(read_u32(r)?);
+    let current_tick = Tick(read_u64(r)?);
+    let seed = if version >= 2 { read_u64(r)? } else { 0 };
+
+    let player_position = if version >= 4 {
+        match read_u8(r)? {
+            0 => None,
+            1 => Some(read_position(r)?),
+            _ => return Err(SaveError::Corrupt("invalid player presence byte")),
+        }
+    } else {
+        None
+    };
+
+    let store_root = if version >= 6 {
+        match read_u8(r)? {
+            0 => None,
+            1 => Some(read_string(r, "region store path is not valid UTF-8")?),
+            _ => return Err(SaveError::Corrupt("invalid region store presence byte")),
+        }
+    } else {
+        None
+    };
+    let store = match store_root {
+        Some(root) => Some(RegionStore::open(root)?),
+        None => None,
+    };
+
+    let region_count = read_u32(r)?;
+    let mut regions = HashMap::new();
+    for _ in 0..region_count {
+        let key = read_region_key(r)?;
+        // The save may hold the only copy of a region, so it has to be written to the region
+        // store before it is evicted again.
+        let mut region = read_cached_region(r, version)?;
+        region.dirty = true;
+        if regions.insert(key, region).is_some() {
+            return Err(SaveError::Corrupt("duplicate region"));
+        }
+    }
+
+    let entities = if version >= 7 {
+        let text = read_string(r, "entities are not valid UTF-8")?;
+        let entities: (Ecs, Option<EntityId>, Scheduler) = ron::de::from_str(&text)
+            .map_err(|_| SaveError::Corrupt("invalid entities"))?;
+        Some(entities)
+    } else {
+        None
+    };
+
+    let mut world = World {
+        id,
+        seed,
+        generator,
+        materials,
+        regions,
+        current_tick,
+        store,
+        residency: Residency::default(),
+        entities: Ecs::new(),
+        player: None,
+        scheduler: Scheduler::new(),
+        view: None,
+        lighting: Lighting::default(),
+        navigation: Navigation::default(),
+        events: Vec::new(),
+    };
+
+    match entities {
+        Some((entities, player, scheduler)) => {
+            world.entities = entities;
+            world.player = player;
+            world.scheduler = scheduler;
+            world.update_player_view();
+        },
+        None => if let Some(position) = player_position {
+            world.place_player(position);
+        },
+    }
+
+    Ok(world)
+}
+
+pub fn write_region_file<W: Write>(w: &mut W, cached_region: &CachedRegion) -> Result<(), SaveError> {
+    w.write_all(REGION_MAGIC)?;
+    write_u16(w, FORMAT_VERSION)?;
+    write_cached_region(w, cached_region)?;
+    Ok(())
+}
+
+pub fn read_region_file<R: Read>(r: &mut R) -> Result<CachedRegion, SaveError> {
+    let version = read_header(r, REGION_MAGIC)?;
+    read_cached_region(r, version)
+}
+
+/// Checks the magic bytes and returns the format version of the file.
+fn read_header<R: Read>(r: &mut R, expected_magic: &[u8; 4]) -> Result<u16, SaveError> {
+    let mut magic = [0u8; 4];
+    r.read_exact(&mut magic)?;
+    if &magic != expected_magic {
+        return Err(SaveError::BadMagic);
+    }
+
+    let version = read_u16(r)?;
+    if version == 0 || version > FORMAT_VERSION {
+        return Err(SaveError::UnsupportedVersion(version));
+    }
+
+    Ok(version)
+}
+
+fn write_position<W: Write>(w: &mut W, (x, y, z): (i32, i32, i32)) -> io::Result<()> {
+    write_i32(w, x)?;
+    write_i32(w, y)?;
+    write_i32(w, z)
+}
+
+fn read_position<R: Read>(r: &mut R) -> io::Result<(i32, i32, i32)> {
+    Ok((read_i32(r)?, read_i32(r)?, read_i32(r)?))
+}
+
+fn write_string<W: Write>(w: &mut W, s: &str) -> io::Result<()> {
+    write_u32(w, s.len() as u32)?;
+    w.write_all(s.as_bytes())
+}
+
+/// Reads a length-prefixed string, failing with `invalid` if it is not UTF-8.
+fn read_string<R: Read>(r: &mut R, invalid: &'static str) -> Result<String, SaveError> {
+    let len = read_u32(r)? as usize;
+    let mut bytes = Vec::new();
+    r.take(len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() != len {
+        return Err(SaveError::Corrupt("unexpected end of file"));
+    }
+
+    String::from_utf8(bytes).map_err(|_| SaveError::Corrupt(invalid))
+}
+
+fn write_region_key<W: Write>(w: &mut W, (x, y, z): (i32, i32, i32)) -> io::Result<()> {
+    write_i32(w, x)?;
+    write_i32(w, y)?;
+    write_i32(w, z)
+}
+
+fn read_region_key<R: Read>(r: &mut R) -> io::Result<(i32, i32, i32)> {
+    Ok((read_i32(r)?, read_i32(r)?, read_i32(r)?))
+}
+
+fn write_cached_region<W: Write>(w: &mut W, cached_region: &CachedRegion) -> io::Result<()> {
+    write_u64(w, cached_region.last_update_tick.0)?;
+
+    let blocks = &cached_region.region.blocks;
+    write_u32(w, blocks.len() as u32)?;
+    for block in blocks {
+        write_block_fill(w, block.fill)?;
+        write_connector(w, block.connector)?;
+    }
+
+    for bits in cached_region.explored.chunks(8) {
+        let byte = bits.iter().enumerate().fold(0u8, |byte, (i, &bit)| byte | (bit as u8) << i);
+        write_u8(w, byte)?;
+    }
+
+    Ok(())
+}
+
+fn read_cached_region<R: Read>(r: &mut R, version: u16) -> Result<CachedRegion, SaveError> {
+    let last_update_tick = Tick(read_u64(r)?);
+
+    if read_u32(r)? as usize != REGION_LEN {
+        return Err(SaveError::Corrupt("region has the wrong number of blocks"));
+    }
+
+    let mut blocks = Vec::with_capacity(REGION_LEN);
+    for _ in 0..REGION_LEN {
+        let fill = read_block_fill(r)?;
+        let connector = if version >= 3 { read_connector(r)? } else { None };
+        blocks.push(Block { fill, connector });
+    }
+
+    let mut cached_region = CachedRegion::new(Region { blocks }, last_update_tick);
+    if version >= 5 {
+        for bits in cached_region.explored.chunks_mut(8) {
+            let byte = read_u8(r)?;
+            for (i, bit) in bits.iter_mut().enumerate() {
+                *bit = byte & (1 << i) != 0;
+            }
+        }
+    }
+
+    Ok(cached_region)
+}
+
+const FILL_SOLID: u8 = 0;
+const FILL_FLOOR: u8 = 1;
+const FILL_CEILING: u8 = 2;
+const FILL_FLOOR_CEILING: u8 = 3;
+const FILL_EMPTY: u8 = 4;
+
+fn write_block_fill<W: Write>(w: &mut W, fill: BlockFill) -> io::Result<()> {
+    match fill {
+        BlockFill::Solid(m) => {
+            write_u8(w, FILL_SOLID)?;
+            write_u32(w, m.0)
+        },
+        BlockFill::Floor(m) => {
+            write_u8(w, FILL_FLOOR)?;
+            write_u32(w, m.0)
+        },
+        BlockFill::Ceiling(m) => {
+            write_u8(w, FILL_CEILING)?;
+            write_u32(w, m.0)
+        },
+        BlockFill::FloorCeiling(floor, ceiling) => {
+            write_u8(w, FILL_FLOOR_CEILING)?;
+            write_u32(w, floor.0)?;
+            write_u32(w, ceiling.0)
+        },
+        BlockFill::Empty => write_u8(w, FILL_EMPTY),
+    }
+}
+
+fn read_block_fill<R: Read>(r: &mut R) -> Result<BlockFill, SaveError> {
+    Ok(match read_u8(r)? {
+        FILL_SOLID => BlockFill::Solid(MaterialId(read_u32(r)?)),
+        FILL_FLOOR => BlockFill::Floor(MaterialId(read_u32(r)?)),
+        FILL_CEILING => BlockFill::Ceiling(MaterialId(read_u32(r)?)),
+        FILL_FLOOR_CEILING => {
+            let floor = MaterialId(read_u32(r)?);
+            let ceiling = MaterialId(read_u32(r)?);
+            BlockFill::FloorCeiling(floor, ceiling)
+        },
+        FILL_EMPTY => BlockFill::Empty,
+        _ => return Err(SaveError::Corrupt("unknown block fill")),
+    })
+}
+
+const CONNECTOR_NONE: u8 = 0;
+const CONNECTOR_STAIRS_UP: u8 = 1;
+const CONNECTOR_STAIRS_DOWN: u8 = 2;
+const CONNECTOR_LADDER: u8 = 3;
+
+fn write_connector<W: Write>(w: &mut W, connector: Option<Connector>) -> io::Result<()> {
+    write_u8(w, match connector {
+        None => CONNECTOR_NONE,
+        Some(Connector::StairsUp) => CONNECTOR_STAIRS_UP,
+        Some(Connector::StairsDown) => CONNECTOR_STAIRS_DOWN,
+        Some(Connector::Ladder) => CONNECTOR_LADDER,
+    })
+}
+
+fn read_connector<R: Read>(r: &mut R) -> Result<Option<Connector>, SaveError> {
+    Ok(match read_u8(r)? {
+        CONNECTOR_NONE => None,
+        CONNECTOR_STAIRS_UP => Some(Connector::StairsUp),
+        CONNECTOR_STAIRS_DOWN => Some(Connector::StairsDown),
+        CONNECTOR_LADDER => Some(Connector::Ladder),
+        _ => return Err(SaveError::Corrupt("unknown connector")),
+    })
+}
+
+//
+// Primitive encoding helpers.
+//
+
+fn write_u8<W: Write>(w: &mut W, v: u8) -> io::Result<()> {
+    w.write_all(&[v])
+}
+
+fn write_u16<W: Write>(w: &mut W, v: u16) -> io::Result<()> {
+    w.write_all(&v.to_le_bytes())
+}
+
+fn write_u32<W: Write>(w: &mut W, v: u32) -> io::Result<()> {
+    w.write_all(&v.to_le_bytes())
+}
+
+fn write_i32<W: Write>(w: &mut W, v: i32) -> io::Result<()> {
+    w.write_all(&v.to_le_bytes())
+}
+
+fn write_u64<W: Write>(w: &mut W, v: u64) -> io::Result<()> {
+    w.write_all(&v.to_le_bytes())
+}
+
+fn read_u8<R: Read>(r: &mut R) -> io::Result<u8> {
+    let mut buf = [0u8; 1];
+    r.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u16<R: Read>(r: &mut R) -> io::Result<u16> {
+    let mut buf = [0u8; 2];
+    r.read_exact(&mut buf)?;
+    Ok(u16::from_le_bytes(buf))
+}
+
+fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_i32<R: Read>(r: &mut R) -> io::Result<i32> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(i32::from_le_bytes(buf))
+}
+
+fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::action::Action;
+    use crate::state::item::ItemTemplate;
+    use crate::state::monster::MonsterTemplate;
+    use crate::state::world::testing::{self, generator};
+
+    fn read_from(mut bytes: &[u8]) -> Result<World, SaveError> {
+        read_world(&mut bytes, generator(), MaterialRegistry::default())
+    }
+
+    fn write_to_vec(world: &World) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_world(&mut bytes, world).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn invalid_strings_name_what_was_read() {
+        let mut bytes = Vec::new();
+        write_u32(&mut bytes, 2).unwrap();
+        bytes.extend_from_slice(&[0xc3, 0x28]);
+
+        match read_string(&mut &bytes[..], "entities are not valid UTF-8") {
+            Err(SaveError::Corrupt(what)) => assert_eq!(what, "entities are not valid UTF-8"),
+            other => panic!("expected a corrupt save, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let (mut world, _) = testing::world_with_player();
+
+        // Load the whole of the top level so that its stairs down are saved too.
+        for y in 0..3 {
+            for x in 0..4 {
+                world.load_region((x, y, 0));
+            }
+        }
+
+        let regions = || world.regions.values();
+        assert!(regions().any(|r| r.region.blocks.iter().any(|b| b.connector.is_some())));
+        assert!(regions().any(|r| r.explored.contains(&true)));
+
+        let bytes = write_to_vec(&world);
+        let loaded = read_from(&bytes).unwrap();
+        assert!(loaded == world);
+        assert_eq!(write_to_vec(&loaded), bytes);
+    }
+
+    #[test]
+    fn migrates_version_1() {
+        let fill = |idx: usize| if idx % 2 == 0 {
+            BlockFill::Floor(MaterialId::GRASS)
+        } else {
+            BlockFill::Solid(MaterialId::STONE)
+        };
+
+        // Version 1 has no seed, no player, no connectors and no explored bits.
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(MAGIC);
+        write_u16(&mut bytes, 1).unwrap();
+        write_u32(&mut bytes, 3).unwrap();
+        write_u64(&mut bytes, 42).unwrap();
+        write_u32(&mut bytes, 1).unwrap();
+        write_region_key(&mut bytes, (1, -2, 0)).unwrap();
+        write_u64(&mut bytes, 40).unwrap();
+        write_u32(&mut bytes, REGION_LEN as u32).unwrap();
+        for idx in 0..REGION_LEN {
+            write_block_fill(&mut bytes, fill(idx)).unwrap();
+        }
+
+        let world = read_from(&bytes).unwrap();
+        assert_eq!(world.id, WorldId(3));
+        assert_eq!(world.current_tick, Tick(42));
+        assert_eq!(world.seed, 0);
+        assert_eq!(world.player_position(), None);
+
+        let cached_region = world.get_cached_region((1, -2, 0)).expect("region was saved");
+        assert_eq!(cached_region.last_update_tick, Tick(40));
+        assert!(cached_region.explored.iter().all(|&explored| !explored));
+        for (idx, block) in cached_region.region.blocks.iter().enumerate() {
+            assert_eq!(*block, Block::new(fill(idx)));
+        }
+    }
+
+    #[test]
+    fn truncated_file_is_corrupt() {
+        let (world, _) = testing::world_with_player();
+
+        let bytes = write_to_vec(&world);
+        for &len in &[4, 10, bytes.len() / 2, bytes.len() - 1] {
+            match read_from(&bytes[..len]) {
+                Err(SaveError::Corrupt(_)) => {},
+                other => panic!("expected a corrupt save at {} bytes, got {:?}", len, other),
+            }
+        }
+    }
+
+    #[test]
+    fn future_version_is_unsupported() {
+        let mut bytes = write_to_vec(&testing::world());
+        bytes[4..6].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+
+        match read_from(&bytes) {
+            Err(SaveError::UnsupportedVersion(version)) => assert_eq!(version, FORMAT_VERSION + 1),
+            other => panic!("expected an unsupported version, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn entities_carry_on_after_load() {
+        let (mut world, position) = testing::world_with_player();
+        world.populate(&[MonsterTemplate::rat(), MonsterTemplate::goblin()], 6, position, 12);
+        world.scatter_items(&[ItemTemplate::dagger(), ItemTemplate::gold()], 6, 20, position, 12);
+        for _ in 0..5 {
+            world.act_player(Action::Wait);
+        }
+
+        let bytes = write_to_vec(&world);
+        let mut loaded = read_from(&bytes).unwrap();
+        assert_eq!(write_to_vec(&loaded), bytes);
+
+        // Both worlds play out the same from here on.
+        for _ in 0..20 {
+            world.act_player(Action::Wait);
+            loaded.act_player(Action::Wait);
+        }
+        assert!(loaded == world);
+        let entities = |world: &World| world.entities.entities()
+            .map(|id| (id, world.entities.position(id), world.entities.get::<Health>(id).copied()))
+            .collect::<Vec<_>>();
+        assert_eq!(entities(&loaded), entities(&world));
+        assert_eq!(loaded.scheduler.peek(), world.scheduler.peek());
+    }
+}
@@ -0,0 +1,217 @@
+//! Backing storage for regions that are not resident in memory. Each region lives in its own file
+//! under the store's root directory, so a world can be much larger than what is loaded at once.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use super::save::{self, SaveError};
+use super::CachedRegion;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RegionStore {
+    root: PathBuf,
+}
+
+impl RegionStore {
+    /// Opens the store rooted at `root`, creating the directory if it does not exist yet.
+    pub fn open(root: impl AsRef<Path>) -> Result<Self, SaveError> {
+        let root = root.as_ref().to_path_buf();
+        std::fs::create_dir_all(&root)?;
+
+        Ok(Self { root })
+    }
+
+    /// Deletes every region in the store, so that a new world can start from it without picking up
+    /// the regions of the last one.
+    pub fn clear(&self) -> Result<(), SaveError> {
+        for entry in std::fs::read_dir(&self.root)? {
+            let path = entry?.path();
+            let is_region = path.extension().map_or(false, |ext| ext == "region" || ext == "tmp");
+            if is_region {
+                std::fs::remove_file(path)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Reads the region at `offset`, or returns `None` if it has never been written to the store.
+    pub fn read_region(&self, offset: (i32, i32, i32)) -> Result<Option<CachedRegion>, SaveError> {
+        let file = match std::fs::File::open(self.region_path(offset)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut reader = std::io::BufReader::new(file);
+        save::read_region_file(&mut reader).map(Some)
+    }
+
+    pub fn write_region(
+        &self,
+        offset: (i32, i32, i32),
+        cached_region: &CachedRegion,
+    ) -> Result<(), SaveError> {
+        // Write to a temporary file first so that a crash mid-write never leaves a truncated
+        // region behind.
+        let path = self.region_path(offset);
+        let tmp_path = path.with_extension("region.tmp");
+
+        let mut writer = std::io::BufWriter::new(std::fs::File::create(&tmp_path)?);
+        save::write_region_file(&mut writer, cached_region)?;
+        std::io::Write::flush(&mut writer)?;
+        drop(writer);
+
+        std::fs::rename(tmp_path, path)?;
+        Ok(())
+    }
+
+    fn region_path(&self, (x, y, z): (i32, i32, i32)) -> PathBuf {
+        self.root.join(format!("{}.{}.{}.region", x, y, z))
+    }
+}
+
+/// The number of regions a streaming world keeps in memory before it starts evicting. The
+/// renderer alone touches around a hundred regions per frame at the default window size.
+pub const DEFAULT_REGION_BUDGET: usize = 256;
+
+/// Tracks when each resident region was last touched, so the least recently used regions can be
+/// evicted once the world holds more than its budget.
+#[derive(Clone, Debug)]
+pub struct Residency {
+    pub budget: usize,
+    clock: u64,
+    last_access: HashMap<(i32, i32, i32), u64>,
+}
+
+impl Default for Residency {
+    fn default() -> Self {
+        Self {
+            budget: DEFAULT_REGION_BUDGET,
+            clock: 0,
+            last_access: HashMap::new(),
+        }
+    }
+}
+
+impl Residency {
+    pub fn touch(&mut self, offset: (i32, i32, i32)) {
+        self.clock += 1;
+        self.last_access.insert(offset, self.clock);
+    }
+
+    pub fn forget(&mut self, offset: (i32, i32, i32)) {
+        self.last_access.remove(&offset);
+    }
+
+    /// Regions that have never been touched count as the oldest.
+    pub fn last_access(&self, offset: (i32, i32, i32)) -> u64 {
+        self.last_access.get(&offset).copied().unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::material::MaterialRegistry;
+    use crate::state::world::testing::{self, generator};
+    use crate::state::world::{split_position, Block, BlockFill, MaterialId, World};
+
+    /// A fresh store under the system's temporary directory, unique to this test run.
+    fn temp_store(name: &str) -> RegionStore {
+        let root = std::env::temp_dir()
+            .join(format!("roguelike-store-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        RegionStore::open(root).unwrap()
+    }
+
+    fn stored_regions(store: &RegionStore) -> usize {
+        std::fs::read_dir(store.root()).unwrap().count()
+    }
+
+    #[test]
+    fn evicted_edits_read_back() {
+        let store = temp_store("evict");
+        let mut world = testing::world().with_region_store(store.clone(), 2);
+
+        let edited = (5, 5, 0);
+        let block = Block::new(BlockFill::Solid(MaterialId::GRANITE));
+        world.set_block(edited, block.clone());
+
+        // Touching more regions than the budget allows pushes the edited one out.
+        for x in 1..4 {
+            world.load_region((x, 0, 0));
+        }
+        world.evict_regions().unwrap();
+        assert!(world.get_cached_region((0, 0, 0)).is_none());
+
+        // Only the edited region was worth writing; the others regenerate as they were.
+        assert_eq!(stored_regions(&store), 1);
+        assert_eq!(*world.block_at(edited), block);
+
+        // Reading it back leaves it clean, so evicting it again writes nothing new.
+        std::fs::remove_dir_all(store.root()).unwrap();
+        std::fs::create_dir_all(store.root()).unwrap();
+        for x in 1..4 {
+            world.load_region((x, 0, 0));
+        }
+        world.evict_regions().unwrap();
+        assert_eq!(stored_regions(&store), 0);
+
+        std::fs::remove_dir_all(store.root()).unwrap();
+    }
+
+    #[test]
+    fn save_records_store() {
+        let store = temp_store("save");
+        let mut world = testing::world().with_region_store(store.clone(), 1);
+
+        let edited = (5, 5, 0);
+        let block = Block::new(BlockFill::Solid(MaterialId::GRANITE));
+        world.set_block(edited, block.clone());
+        world.load_region((1, 0, 0));
+        world.evict_regions().unwrap();
+
+        let path = store.root().join("world.sav");
+        world.save(&path).unwrap();
+        let mut loaded = World::load(&path, generator(), MaterialRegistry::default()).unwrap();
+
+        assert!(loaded.get_cached_region((0, 0, 0)).is_none());
+        assert_eq!(*loaded.block_at(edited), block);
+
+        std::fs::remove_dir_all(store.root()).unwrap();
+    }
+
+    #[test]
+    fn resumed_regions_survive_eviction() {
+        let store = temp_store("resume");
+        let (world, player) = testing::world_with_player();
+        let mut world = world.with_region_store(store.clone(), 1);
+        let (offset, idx) = split_position(player);
+
+        // The region is dug into and explored, but still resident when the game is saved.
+        let block = Block::new(BlockFill::Floor(MaterialId::GRANITE));
+        world.set_block(player, block.clone());
+        assert!(world.get_cached_region(offset).unwrap().explored[idx]);
+
+        let path = store.root().join("world.sav");
+        world.save(&path).unwrap();
+        let mut loaded = World::load(&path, generator(), MaterialRegistry::default()).unwrap();
+        loaded.residency.budget = 1;
+
+        for x in 1..4 {
+            loaded.load_region((offset.0 + x, offset.1, offset.2));
+        }
+        loaded.evict_regions().unwrap();
+        assert!(loaded.get_cached_region(offset).is_none());
+
+        assert_eq!(*loaded.block_at(player), block);
+        assert!(loaded.load_region(offset).explored[idx]);
+
+        std::fs::remove_dir_all(store.root()).unwrap();
+    }
+}
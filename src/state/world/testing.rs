@@ -0,0 +1,48 @@
+//! The world tests play in: two levels of BSP rooms generated from a fixed seed, so that every
+//! test module starts from the same layout.
+
+use super::gen::{BoundedGenerator, BspGenerator};
+use super::{BlockFill, MaterialId, Region, World, WorldGenerator};
+
+/// The seed test worlds are generated from.
+pub const SEED: u64 = 7;
+
+/// The generator behind test worlds, for tests that look at the maps themselves.
+pub fn dungeon() -> BoundedGenerator<BspGenerator> {
+    BoundedGenerator::new(BspGenerator::new(64, 48)).with_depth(2)
+}
+
+pub fn generator() -> Box<dyn WorldGenerator> {
+    Box::new(dungeon())
+}
+
+/// A test world without a player.
+pub fn world() -> World {
+    World::new(generator(), SEED)
+}
+
+/// A test world with the player spawned on the top level, and where they stand.
+pub fn world_with_player() -> (World, (i32, i32, i32)) {
+    let mut world = world();
+    let position = world.spawn_player((0, 0, 0)).expect("the dungeon has floor to stand on");
+    (world, position)
+}
+
+/// Open floor on levels 0 and -1 and solid rock everywhere else, for tests that lay out their own
+/// terrain with `World::set_block`.
+#[derive(Debug)]
+pub struct Flat;
+
+impl WorldGenerator for Flat {
+    fn generate_region(&mut self, _seed: u64, (_, _, z): (i32, i32, i32)) -> Region {
+        match z {
+            0 | -1 => Region::filled(BlockFill::Floor(MaterialId::STONE)),
+            _ => Region::filled(BlockFill::Solid(MaterialId::STONE)),
+        }
+    }
+}
+
+/// A world of open floor without a player.
+pub fn flat_world() -> World {
+    World::new(Box::new(Flat), SEED)
+}
@@ -0,0 +1,147 @@
+//! What the player can see of the world right now, and what they remember of it. Each region
+//! records which of its blocks the player has ever seen; the blocks in view are recomputed with
+//! the field of view after every player action.
+
+use super::*;
+
+/// How a tile appears to the player.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Visibility {
+    /// In view right now.
+    Visible,
+    /// Seen before but out of view; shown as it was remembered, without anything standing on it.
+    Remembered,
+    /// Never seen.
+    Unknown,
+}
+
+impl World {
+    /// How the tile at `pos` appears to the player. Everything is visible while there is no player.
+    pub fn visibility(&self, pos: (i32, i32, i32)) -> Visibility {
+        let view = match &self.view {
+            Some(view) => view,
+            None => return Visibility::Visible,
+        };
+
+        if view.contains(&pos) {
+            return Visibility::Visible;
+        }
+
+        let (offset, idx) = split_position(pos);
+        match self.regions.get(&offset) {
+            Some(cached_region) if cached_region.explored[idx] => Visibility::Remembered,
+            _ => Visibility::Unknown,
+        }
+    }
+
+    /// Recomputes what the player can see and marks it as explored. Regions whose appearance
+    /// changed have their view version bumped, so cached renders of them are redrawn.
+    pub fn update_player_view(&mut self) {
+        let (position, radius) = match self.player {
+            Some(player) => {
+                let radius = self.entities.get::<Vision>(player).map_or(0, |vision| vision.radius);
+                match self.entities.position(player) {
+                    Some(position) => (position, radius),
+                    None => return,
+                }
+            },
+            None => return,
+        };
+
+        let view = self.field_of_view(position, radius);
+        let previous = self.view.take();
+
+        // Every region that gains or loses a visible tile needs redrawing, as does every region
+        // with newly explored tiles. Without a previous view everything was shown, so every
+        // loaded region needs it.
+        let mut changed: HashSet<(i32, i32, i32)> = match &previous {
+            Some(previous) => previous.symmetric_difference(&view)
+                .map(|&pos| split_position(pos).0)
+                .collect(),
+            None => self.regions.keys().copied().collect(),
+        };
+
+        for &pos in &view {
+            let (offset, idx) = split_position(pos);
+            if let Some(cached_region) = self.regions.get_mut(&offset) {
+                if !cached_region.explored[idx] {
+                    cached_region.explored[idx] = true;
+                    cached_region.dirty = true;
+                    changed.insert(offset);
+                }
+            }
+        }
+
+        for offset in changed {
+            if let Some(cached_region) = self.regions.get_mut(&offset) {
+                cached_region.view_revision += 1;
+            }
+        }
+
+        self.view = Some(view);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::world::testing;
+
+    /// How a region looks to the player, and its view version.
+    type View = (Vec<Visibility>, (Tick, u64, u64));
+
+    fn views(world: &World) -> HashMap<(i32, i32, i32), View> {
+        let dim = REGION_DIM as i32;
+        world.regions.iter()
+            .map(|(&offset, region)| {
+                let visibility = (0..REGION_LEN as i32)
+                    .map(|idx| (offset.0 * dim + idx % dim, offset.1 * dim + idx / dim, offset.2))
+                    .map(|pos| world.visibility(pos))
+                    .collect();
+                (offset, (visibility, region.view_version()))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn tiles_go_from_unknown_to_visible_to_remembered() {
+        let mut world = testing::flat_world();
+        let far = (100, 0, 0);
+        assert_eq!(world.visibility(far), Visibility::Visible);
+
+        world.spawn_player((0, 0, 0));
+        let player = world.player.unwrap();
+        assert_eq!(world.visibility((0, 0, 0)), Visibility::Visible);
+        assert_eq!(world.visibility((1, 1, 0)), Visibility::Visible);
+        assert_eq!(world.visibility(far), Visibility::Unknown);
+
+        world.entities.set_position(player, (50, 0, 0));
+        world.update_player_view();
+        assert_eq!(world.visibility((0, 0, 0)), Visibility::Remembered);
+        assert_eq!(world.visibility((50, 0, 0)), Visibility::Visible);
+        assert_eq!(world.visibility(far), Visibility::Unknown);
+    }
+
+    #[test]
+    fn only_regions_whose_view_changed_are_redrawn() {
+        let mut world = testing::flat_world();
+        world.spawn_player((8, 8, 0));
+        let player = world.player.unwrap();
+
+        let before = views(&world);
+        world.update_player_view();
+        assert_eq!(views(&world), before);
+
+        world.entities.set_position(player, (9, 8, 0));
+        world.update_player_view();
+        let after = views(&world);
+
+        let mut redrawn = 0;
+        for (offset, (visibility, version)) in &before {
+            let (new_visibility, new_version) = &after[offset];
+            assert_eq!(new_visibility != visibility, new_version != version, "{:?}", offset);
+            redrawn += (new_version != version) as usize;
+        }
+        assert!(redrawn > 0 && redrawn < before.len());
+    }
+}